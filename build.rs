@@ -0,0 +1,44 @@
+//! Embeds build information shown by --version: the git commit the binary was built from, the
+//! target and the enabled cargo features.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=.git/index");
+
+    println!("cargo:rustc-env=VDC_GIT_COMMIT={}", git_commit());
+    println!(
+        "cargo:rustc-env=VDC_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=VDC_FEATURES={}", features.join(","));
+}
+
+/// The short hash of the commit being built, with `-dirty` appended if there are uncommitted
+/// changes, or `unknown` if not building from a git checkout.
+fn git_commit() -> String {
+    let git = |args: &[&str]| -> Option<String> {
+        let output = Command::new("git").args(args).output().ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+    };
+    match git(&["rev-parse", "--short=12", "HEAD"]) {
+        Some(commit) => match git(&["status", "--porcelain", "--untracked-files=no"]) {
+            Some(status) if !status.is_empty() => format!("{}-dirty", commit),
+            _ => commit,
+        },
+        None => "unknown".to_string(),
+    }
+}
@@ -0,0 +1,29 @@
+//! Pinning threads to CPU cores with --pin-cpus.
+
+use anyhow::{bail, Error, Result};
+
+/// Pin the calling thread to the given CPU core.
+///
+/// Buffers should be allocated after pinning, so that with the kernel's default first-touch
+/// policy their memory ends up on the NUMA node local to the core.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpu: usize) -> Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        bail!("CPU number {} is too large", cpu);
+    }
+
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(Error::from(std::io::Error::last_os_error())
+                .context(format!("Unable to pin thread to CPU {}", cpu)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpu: usize) -> Result<()> {
+    bail!("Pinning threads to CPUs is only supported on Linux");
+}
@@ -0,0 +1,143 @@
+//! I/O buffers, optionally backed by huge pages with --hugepages.
+
+use crate::output::emit;
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Size of the huge pages requested from the kernel. Mappings are rounded up to a multiple of it.
+#[cfg(target_os = "linux")]
+const HUGE_PAGE_SIZE: usize = 2 << 20;
+
+/// Alignment of buffers on the heap, so they can be used with `--open-flags direct`, which
+/// requires buffers aligned to the logical sector size.
+const HEAP_ALIGNMENT: usize = 4096;
+
+/// Whether the fallback warning has been printed, so it is only printed once per run.
+static WARNED: AtomicBool = AtomicBool::new(false);
+
+/// A zero-initialized buffer. Huge page mappings are page aligned, so they satisfy the alignment
+/// requirements of direct I/O just like the page aligned heap buffers.
+pub enum Buffer {
+    Heap {
+        ptr: *mut u8,
+        len: usize,
+        layout: std::alloc::Layout,
+    },
+    #[cfg(target_os = "linux")]
+    HugePages {
+        ptr: *mut u8,
+        len: usize,
+        mapped_len: usize,
+    },
+}
+
+impl Buffer {
+    /// Allocate a buffer of `len` bytes, backed by huge pages if `hugepages` is set and they are
+    /// available, and by normal pages otherwise.
+    pub fn new(len: usize, hugepages: bool) -> Buffer {
+        if hugepages {
+            match Self::huge_pages(len) {
+                Ok(buffer) => return buffer,
+                Err(e) => {
+                    if !WARNED.swap(true, Ordering::Relaxed) {
+                        emit!(
+                            "{} Unable to allocate huge pages, falling back to normal pages: {}",
+                            crate::color::paint("WARNING:", crossterm::style::Color::Yellow),
+                            e
+                        );
+                    }
+                }
+            }
+        }
+        // Allocate at least a byte, since zero-sized allocations are not allowed.
+        let layout = std::alloc::Layout::from_size_align(len.max(1), HEAP_ALIGNMENT)
+            .expect("Buffer size overflows");
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        Buffer::Heap { ptr, len, layout }
+    }
+
+    /// The amount of memory a buffer of `len` bytes takes, accounting for huge page mappings being
+    /// rounded up to whole pages.
+    pub fn allocation_size(len: usize, hugepages: bool) -> usize {
+        #[cfg(target_os = "linux")]
+        if hugepages {
+            return len.div_ceil(HUGE_PAGE_SIZE).max(1) * HUGE_PAGE_SIZE;
+        }
+        len
+    }
+
+    #[cfg(target_os = "linux")]
+    fn huge_pages(len: usize) -> std::io::Result<Buffer> {
+        let mapped_len = len.div_ceil(HUGE_PAGE_SIZE).max(1) * HUGE_PAGE_SIZE;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                mapped_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_HUGETLB,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Buffer::HugePages {
+            ptr: ptr as *mut u8,
+            len,
+            mapped_len,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn huge_pages(_len: usize) -> std::io::Result<Buffer> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "huge pages are only supported on Linux",
+        ))
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // The allocation or mapping is valid for the lifetime of the buffer, and both are
+        // zero-initialized.
+        match self {
+            Buffer::Heap { ptr, len, .. } => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+            #[cfg(target_os = "linux")]
+            Buffer::HugePages { ptr, len, .. } => unsafe { std::slice::from_raw_parts(*ptr, *len) },
+        }
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            Buffer::Heap { ptr, len, .. } => unsafe { std::slice::from_raw_parts_mut(*ptr, *len) },
+            #[cfg(target_os = "linux")]
+            Buffer::HugePages { ptr, len, .. } => unsafe {
+                std::slice::from_raw_parts_mut(*ptr, *len)
+            },
+        }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        match self {
+            Buffer::Heap { ptr, layout, .. } => unsafe { std::alloc::dealloc(*ptr, *layout) },
+            #[cfg(target_os = "linux")]
+            Buffer::HugePages {
+                ptr, mapped_len, ..
+            } => unsafe {
+                libc::munmap(*ptr as *mut libc::c_void, *mapped_len);
+            },
+        }
+    }
+}
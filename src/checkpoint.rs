@@ -0,0 +1,218 @@
+//! Checkpoints of a run in progress, saved periodically and on interrupt with --checkpoint, and
+//! resumed from with --resume-from-json. Interrupts themselves are caught by `interrupt`.
+
+use anyhow::{bail, Context, Error, Result};
+
+use crate::json;
+use crate::output::emit;
+use crate::state::RunState;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often a checkpoint is saved.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The phase a checkpoint was saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Write,
+    Read,
+}
+
+impl std::str::FromStr for Phase {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Phase> {
+        match s {
+            "write" => Ok(Phase::Write),
+            "read" => Ok(Phase::Read),
+            _ => bail!("Unknown phase '{}', expected write or read", s),
+        }
+    }
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Phase::Write => "write",
+            Phase::Read => "read",
+        })
+    }
+}
+
+/// Everything needed to continue a run where it left off.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub state: RunState,
+    pub phase: Phase,
+    /// How far into the device the phase got. Everything before it is done.
+    pub offset: u64,
+}
+
+impl Checkpoint {
+    /// Save the checkpoint as a JSON object, replacing the file atomically so an interruption
+    /// while saving never leaves a truncated checkpoint behind.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut fields = self.state.json_fields();
+        fields.push(("phase", json::string(&self.phase.to_string())));
+        fields.push(("offset", self.offset.to_string()));
+
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut f = std::fs::File::create(&tmp)
+            .with_context(|| format!("Unable to create checkpoint '{}'", tmp.display()))?;
+        writeln!(f, "{}", json::object(&fields))?;
+        f.sync_all()
+            .with_context(|| format!("Unable to sync checkpoint '{}'", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Unable to replace checkpoint '{}'", path.display()))
+    }
+
+    pub fn read(path: &Path) -> Result<Checkpoint> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read checkpoint '{}'", path.display()))?;
+        let fields = json::parse_flat_object(&contents)
+            .with_context(|| format!("Invalid JSON in checkpoint '{}'", path.display()))?;
+
+        let mut phase = None;
+        let mut offset = None;
+        let mut pairs = Vec::new();
+        for (name, value) in fields.iter() {
+            let value = match value {
+                Some(value) => value.as_str(),
+                None => continue,
+            };
+            match name.as_str() {
+                "phase" => phase = Some(value.parse()?),
+                "offset" => offset = Some(value.parse().context("offset must be an integer")?),
+                _ => pairs.push((name.as_str(), value)),
+            }
+        }
+        let state = RunState::from_pairs(&pairs)
+            .with_context(|| format!("Invalid checkpoint '{}'", path.display()))?;
+        Ok(Checkpoint {
+            state,
+            phase: phase.context("Checkpoint is missing phase")?,
+            offset: offset.context("Checkpoint is missing offset")?,
+        })
+    }
+}
+
+/// Saves checkpoints for the rest of the run once set up with `init`.
+struct Checkpointer {
+    path: PathBuf,
+    state: RunState,
+    last_save: Instant,
+}
+
+static CHECKPOINTER: Mutex<Option<Checkpointer>> = Mutex::new(None);
+
+/// Start saving checkpoints of the run described by `state` to `path`. The phases save one with
+/// `interrupted` when they are stopped by a signal.
+pub fn init(path: &Path, state: RunState) {
+    *CHECKPOINTER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Checkpointer {
+        path: path.to_path_buf(),
+        state,
+        last_save: Instant::now(),
+    });
+}
+
+/// Report that the phase has got `offset` bytes into the device. Saves a checkpoint if it is
+/// time to. Does nothing unless `init` was called.
+pub fn update(phase: Phase, offset: u64) -> Result<()> {
+    let mut checkpointer = CHECKPOINTER.lock().unwrap_or_else(|e| e.into_inner());
+    match checkpointer.as_mut() {
+        Some(checkpointer) if checkpointer.last_save.elapsed() >= CHECKPOINT_INTERVAL => {
+            checkpointer.save(phase, offset)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Save a checkpoint when the phase was stopped by a signal after getting `offset` bytes into the
+/// device, and tell how to continue. Does nothing unless `init` was called.
+pub fn interrupted(phase: Phase, offset: u64) -> Result<()> {
+    if let Some(checkpointer) = CHECKPOINTER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()
+    {
+        checkpointer.save(phase, offset)?;
+        emit!(
+            "Saved checkpoint to '{}', continue the run with --resume-from-json '{}'",
+            checkpointer.path.display(),
+            checkpointer.path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Save a checkpoint right away, e.g. at the end of a phase.
+pub fn save(phase: Phase, offset: u64) -> Result<()> {
+    match CHECKPOINTER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_mut()
+    {
+        Some(checkpointer) => checkpointer.save(phase, offset),
+        None => Ok(()),
+    }
+}
+
+/// Remove the checkpoint once the run is complete, so it is not resumed by mistake.
+pub fn remove() -> Result<()> {
+    if let Some(checkpointer) = CHECKPOINTER
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take()
+    {
+        // A run shorter than the checkpoint interval may never have saved one.
+        match std::fs::remove_file(&checkpointer.path) {
+            Ok(()) => emit!("Removed checkpoint '{}'", checkpointer.path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Unable to remove checkpoint '{}'",
+                        checkpointer.path.display()
+                    )
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Checkpointer {
+    fn save(&mut self, phase: Phase, offset: u64) -> Result<()> {
+        // Resume from the start of the buffer the offset is in, so I/O stays aligned and the
+        // keystream can be positioned at a whole 32-bit word. Redoing part of a buffer is
+        // harmless.
+        let granularity = lcm(self.state.buffer_size, 4);
+        let offset = offset - offset % granularity;
+        if phase == Phase::Write {
+            self.state.written_bytes = offset;
+        }
+        Checkpoint {
+            state: self.state.clone(),
+            phase,
+            offset,
+        }
+        .write(&self.path)
+        .with_context(|| format!("Error saving checkpoint to '{}'", self.path.display()))?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let (mut x, mut y) = (a, b);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    a / x * b
+}
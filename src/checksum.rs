@@ -0,0 +1,182 @@
+//! Per-block checksum manifests, written with --checksum-out and verified with --checksum-in.
+//!
+//! A manifest starts with a `block_size = N` line, followed by the hex-encoded sha256 hash of each
+//! consecutive block of N bytes written to the device, one per line. The final block may be
+//! shorter than N bytes.
+
+use anyhow::{bail, Context, Result};
+
+use sha2::{Digest, Sha256};
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Splits a stream of bytes into blocks and hashes each of them.
+struct BlockHasher {
+    block_size: u64,
+    hasher: Sha256,
+    /// Number of bytes hashed for the current block.
+    block_bytes: u64,
+}
+
+impl BlockHasher {
+    fn new(block_size: u64) -> BlockHasher {
+        BlockHasher {
+            block_size,
+            hasher: Sha256::new(),
+            block_bytes: 0,
+        }
+    }
+
+    /// Hash the given data, calling `on_block` with the hash of every block that is completed.
+    fn update(
+        &mut self,
+        mut data: &[u8],
+        mut on_block: impl FnMut([u8; 32]) -> Result<()>,
+    ) -> Result<()> {
+        while !data.is_empty() {
+            let n = (self.block_size - self.block_bytes).min(data.len() as u64) as usize;
+            self.hasher.update(&data[..n]);
+            self.block_bytes += n as u64;
+            data = &data[n..];
+            if self.block_bytes == self.block_size {
+                on_block(self.finish_block())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finish the current partial block, if any.
+    fn finish(&mut self) -> Option<[u8; 32]> {
+        if self.block_bytes == 0 {
+            None
+        } else {
+            Some(self.finish_block())
+        }
+    }
+
+    fn finish_block(&mut self) -> [u8; 32] {
+        self.block_bytes = 0;
+        std::mem::take(&mut self.hasher).finalize().into()
+    }
+}
+
+/// Writes the checksum manifest for the data written to the device.
+pub struct ChecksumWriter {
+    hasher: BlockHasher,
+    out: BufWriter<File>,
+}
+
+impl ChecksumWriter {
+    pub fn create(path: &Path, block_size: u64) -> Result<ChecksumWriter> {
+        if block_size == 0 {
+            bail!("Checksum block size must be greater than 0");
+        }
+        let f = File::create(path)
+            .with_context(|| format!("Unable to create checksum file '{}'", path.display()))?;
+        let mut out = BufWriter::new(f);
+        writeln!(out, "block_size = {}", block_size)?;
+        Ok(ChecksumWriter {
+            hasher: BlockHasher::new(block_size),
+            out,
+        })
+    }
+
+    /// Add the next bytes written to the device.
+    pub fn update(&mut self, data: &[u8]) -> Result<()> {
+        let out = &mut self.out;
+        self.hasher.update(data, |hash| {
+            writeln!(out, "{}", hex::encode(hash)).context("Error writing checksum file")
+        })
+    }
+
+    /// Write the checksum of the final partial block and flush the file.
+    pub fn finish(mut self) -> Result<()> {
+        if let Some(hash) = self.hasher.finish() {
+            writeln!(self.out, "{}", hex::encode(hash))?;
+        }
+        let f = self
+            .out
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Error writing checksum file")?;
+        f.sync_all().context("Error syncing checksum file")?;
+        Ok(())
+    }
+}
+
+/// Verifies the data read from the device against a checksum manifest.
+pub struct ChecksumVerifier {
+    hasher: BlockHasher,
+    expected: std::vec::IntoIter<[u8; 32]>,
+    /// Offset of the start of the current block on the device.
+    block_start: u64,
+}
+
+impl ChecksumVerifier {
+    pub fn open(path: &Path) -> Result<ChecksumVerifier> {
+        let f = File::open(path)
+            .with_context(|| format!("Unable to open checksum file '{}'", path.display()))?;
+        let mut lines = BufReader::new(f).lines();
+
+        let header = lines.next().context("Checksum file is empty")??;
+        let block_size: u64 = match header.split_once('=') {
+            Some(("block_size ", value)) => value
+                .trim()
+                .parse()
+                .context("Invalid block size in checksum file")?,
+            _ => bail!("Checksum file does not start with a block_size line"),
+        };
+        if block_size == 0 {
+            bail!("Checksum block size must be greater than 0");
+        }
+
+        let mut expected = Vec::new();
+        for line in lines {
+            let mut hash = [0u8; 32];
+            hex::decode_to_slice(line?.trim(), &mut hash)
+                .context("Invalid checksum in checksum file")?;
+            expected.push(hash);
+        }
+
+        Ok(ChecksumVerifier {
+            hasher: BlockHasher::new(block_size),
+            expected: expected.into_iter(),
+            block_start: 0,
+        })
+    }
+
+    /// Add the next bytes read from the device. Returns the offset of the start of the first block
+    /// whose checksum does not match, if any.
+    pub fn update(&mut self, data: &[u8]) -> Result<Option<u64>> {
+        let expected = &mut self.expected;
+        let block_start = &mut self.block_start;
+        let block_size = self.hasher.block_size;
+        let mut mismatch = None;
+        self.hasher.update(data, |hash| {
+            if mismatch.is_none() && expected.next() != Some(hash) {
+                mismatch = Some(*block_start);
+            }
+            *block_start += block_size;
+            Ok(())
+        })?;
+        Ok(mismatch)
+    }
+
+    /// Check the final partial block once the end of the device is reached. Returns the offset of
+    /// the start of the block if it does not match, including if the device ended before all
+    /// blocks in the manifest were read.
+    pub fn finish(&mut self) -> Option<u64> {
+        let block_start = self.block_start;
+        if let Some(hash) = self.hasher.finish() {
+            if self.expected.next() != Some(hash) {
+                return Some(block_start);
+            }
+        }
+        if self.expected.next().is_some() {
+            return Some(self.block_start);
+        }
+        None
+    }
+}
@@ -0,0 +1,54 @@
+//! Colored output, controlled by --color and the NO_COLOR environment variable.
+
+use anyhow::{bail, Error, Result};
+
+use crossterm::style::{Color, Stylize};
+use crossterm::tty::IsTty;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// When to color output, chosen with --color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ColorMode> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => bail!("Unknown color mode '{}', expected auto, always or never", s),
+        }
+    }
+}
+
+/// Decide whether to color output for the rest of the run. With `auto`, output is colored only if
+/// stderr is a TTY and NO_COLOR is not set to a non-empty value.
+pub fn init(mode: ColorMode) {
+    let enabled = match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+                && std::io::stderr().is_tty()
+        }
+    };
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Style the text with the given color if color is enabled.
+pub fn paint(text: &str, color: Color) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        text.with(color).to_string()
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,74 @@
+//! Fast search for the bytes that differ between what was read and what was expected.
+//!
+//! Buffers are first compared a block at a time with slice equality, which compiles to `memcmp`
+//! and is vectorized by the C library. Only a block that differs is scanned further, a 64-bit
+//! word at a time, and the differing byte is found from the position of the lowest (or highest)
+//! set bit of the XOR of the words. The few bytes that do not fill a word are compared one by one.
+
+/// Size of the blocks compared with `memcmp` before scanning for the exact byte.
+const BLOCK_SIZE: usize = 4096;
+
+const WORD_SIZE: usize = std::mem::size_of::<u64>();
+
+/// Find the index of the first byte that differs between `a` and `b`, which must have the same
+/// length.
+pub fn first_mismatch(a: &[u8], b: &[u8]) -> Option<usize> {
+    debug_assert_eq!(a.len(), b.len());
+    for (i, (a, b)) in a.chunks(BLOCK_SIZE).zip(b.chunks(BLOCK_SIZE)).enumerate() {
+        if a != b {
+            return first_mismatch_in_block(a, b).map(|j| i * BLOCK_SIZE + j);
+        }
+    }
+    None
+}
+
+/// Find the index of the last byte that differs between `a` and `b`, which must have the same
+/// length.
+pub fn last_mismatch(a: &[u8], b: &[u8]) -> Option<usize> {
+    debug_assert_eq!(a.len(), b.len());
+    for (i, (a, b)) in a
+        .chunks(BLOCK_SIZE)
+        .zip(b.chunks(BLOCK_SIZE))
+        .enumerate()
+        .rev()
+    {
+        if a != b {
+            return last_mismatch_in_block(a, b).map(|j| i * BLOCK_SIZE + j);
+        }
+    }
+    None
+}
+
+fn first_mismatch_in_block(a: &[u8], b: &[u8]) -> Option<usize> {
+    let words = a.chunks_exact(WORD_SIZE).zip(b.chunks_exact(WORD_SIZE));
+    for (i, (a, b)) in words.enumerate() {
+        let diff = word(a) ^ word(b);
+        if diff != 0 {
+            // Words are read as little endian, so the first byte is the lowest.
+            return Some(i * WORD_SIZE + diff.trailing_zeros() as usize / 8);
+        }
+    }
+    let tail = a.len() - a.len() % WORD_SIZE;
+    (tail..a.len()).find(|&i| a[i] != b[i])
+}
+
+fn last_mismatch_in_block(a: &[u8], b: &[u8]) -> Option<usize> {
+    let tail = a.len() - a.len() % WORD_SIZE;
+    if let Some(i) = (tail..a.len()).rev().find(|&i| a[i] != b[i]) {
+        return Some(i);
+    }
+    let words = a[..tail]
+        .chunks_exact(WORD_SIZE)
+        .zip(b[..tail].chunks_exact(WORD_SIZE));
+    for (i, (a, b)) in words.enumerate().rev() {
+        let diff = word(a) ^ word(b);
+        if diff != 0 {
+            return Some(i * WORD_SIZE + WORD_SIZE - 1 - diff.leading_zeros() as usize / 8);
+        }
+    }
+    None
+}
+
+fn word(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().expect("Word has the wrong size"))
+}
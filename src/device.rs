@@ -0,0 +1,201 @@
+//! Opening block devices, and non-destructive queries of their geometry. None of the queries
+//! write to the device, so they are safe to use for inspecting it.
+
+use anyhow::{bail, Context, Error, Result};
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Whether a device is opened for reading or writing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Read,
+    Write,
+}
+
+/// The `O_*` flags to open the device with, chosen with --open-flags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenFlags(libc::c_int);
+
+/// The names accepted by --open-flags and the flags they stand for.
+const OPEN_FLAG_NAMES: &[(&str, libc::c_int)] = &[
+    #[cfg(target_os = "linux")]
+    ("direct", libc::O_DIRECT),
+    ("sync", libc::O_SYNC),
+    ("dsync", libc::O_DSYNC),
+    #[cfg(target_os = "linux")]
+    ("noatime", libc::O_NOATIME),
+    ("excl", libc::O_EXCL),
+];
+
+impl std::str::FromStr for OpenFlags {
+    type Err = Error;
+
+    /// Parse a comma-separated list of flag names, or `none`.
+    fn from_str(s: &str) -> Result<OpenFlags> {
+        let mut flags = 0;
+        for name in s.split(',').map(str::trim) {
+            if name == "none" {
+                continue;
+            }
+            match OPEN_FLAG_NAMES.iter().find(|(n, _)| *n == name) {
+                Some((_, flag)) => flags |= flag,
+                None => {
+                    let names: Vec<&str> = OPEN_FLAG_NAMES.iter().map(|(n, _)| *n).collect();
+                    bail!(
+                        "Unknown open flag '{}', expected a comma-separated list of {}",
+                        name,
+                        names.join(", ")
+                    );
+                }
+            }
+        }
+        Ok(OpenFlags(flags))
+    }
+}
+
+impl OpenFlags {
+    /// Whether I/O bypasses the page cache.
+    pub fn is_direct(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        return self.0 & libc::O_DIRECT != 0;
+        #[cfg(not(target_os = "linux"))]
+        return false;
+    }
+
+    /// These flags with I/O bypassing the page cache, where that is supported.
+    pub fn with_direct(self) -> OpenFlags {
+        #[cfg(target_os = "linux")]
+        return OpenFlags(self.0 | libc::O_DIRECT);
+        #[cfg(not(target_os = "linux"))]
+        return self;
+    }
+}
+
+/// Open the device for reading or writing with the given flags. Every open of the device for I/O
+/// goes through here, so --open-flags applies to all of them.
+pub fn open_device(path: &Path, mode: Mode, flags: OpenFlags) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = std::fs::OpenOptions::new();
+    match mode {
+        Mode::Read => options.read(true),
+        Mode::Write => options.write(true),
+    };
+    options.custom_flags(flags.0).open(path)
+}
+
+/// An open device, either the device itself or a simulation on top of it like --simulate-fake.
+pub trait DeviceHandle: Read + Write + Seek {
+    /// The file underneath, for calls on the file itself like syncing it.
+    fn file(&self) -> &File;
+}
+
+impl DeviceHandle for File {
+    fn file(&self) -> &File {
+        self
+    }
+}
+
+/// Get the preferred I/O block size of a block device or regular file.
+pub fn block_size(path: &Path) -> Result<u64> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path)?;
+
+    if !metadata.file_type().is_block_device() && !metadata.is_file() {
+        bail!("Not a block device");
+    }
+
+    Ok(metadata.blksize())
+}
+
+/// The logical and physical sector sizes of a block device.
+#[derive(Debug, Clone, Copy)]
+pub struct SectorSizes {
+    pub logical: u64,
+    pub physical: u64,
+}
+
+/// Get the sector sizes of a block device. Returns None for regular files.
+#[cfg(target_os = "linux")]
+pub fn sector_sizes(path: &Path) -> Result<Option<SectorSizes>> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::io::AsRawFd;
+
+    /// `_IO(0x12, 104)` from linux/fs.h
+    const BLKSSZGET: libc::c_ulong = 0x1268;
+    /// `_IO(0x12, 123)` from linux/fs.h
+    const BLKPBSZGET: libc::c_ulong = 0x127b;
+
+    let d = File::open(path)?;
+    if !d.metadata()?.file_type().is_block_device() {
+        return Ok(None);
+    }
+
+    let mut logical: libc::c_int = 0;
+    if unsafe { libc::ioctl(d.as_raw_fd(), BLKSSZGET as _, &mut logical) } != 0 {
+        return Err(Error::from(std::io::Error::last_os_error()).context("BLKSSZGET ioctl failed"));
+    }
+    let mut physical: libc::c_uint = 0;
+    if unsafe { libc::ioctl(d.as_raw_fd(), BLKPBSZGET as _, &mut physical) } != 0 {
+        return Err(Error::from(std::io::Error::last_os_error()).context("BLKPBSZGET ioctl failed"));
+    }
+
+    Ok(Some(SectorSizes {
+        logical: logical as u64,
+        physical: physical as u64,
+    }))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sector_sizes(_path: &Path) -> Result<Option<SectorSizes>> {
+    Ok(None)
+}
+
+/// Get the size in bytes of a block device or regular file. Anything else, like a character
+/// device or a pipe, has no end to seek to.
+pub fn disk_size(path: &Path) -> Result<u64> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Checked before opening, since opening a FIFO blocks until something writes to it.
+    let file_type = std::fs::metadata(path)?.file_type();
+    if !file_type.is_file() && !file_type.is_block_device() {
+        bail!(
+            "'{}' is {}, not a seekable block device",
+            path.display(),
+            describe_file_type(file_type)
+        );
+    }
+    let mut d = File::open(path)?;
+    d.seek(SeekFrom::End(0)).with_context(|| {
+        format!(
+            "'{}' cannot be seeked, so it is not a seekable block device",
+            path.display()
+        )
+    })?;
+    d.stream_position().context("Unable to get position")
+}
+
+/// Describe the kind of a file for messages, e.g. "a character device".
+pub fn describe_file_type(file_type: std::fs::FileType) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_block_device() {
+        "a block device"
+    } else if file_type.is_char_device() {
+        "a character device"
+    } else if file_type.is_fifo() {
+        "a pipe"
+    } else if file_type.is_socket() {
+        "a socket"
+    } else if file_type.is_dir() {
+        "a directory"
+    } else if file_type.is_file() {
+        "a regular file"
+    } else {
+        "a special file"
+    }
+}
@@ -0,0 +1,68 @@
+//! Settings known to work well for particular device models, so they need not be rediscovered
+//! every time the same model is tested.
+//!
+//! Devices are recognized by the vendor and model they report, which for USB drives and
+//! enclosures is usually that of the USB bridge rather than of the flash or disk behind it. The
+//! settings of a known device are used for whatever the command line leaves unset, unless
+//! --ignore-device-db is given.
+
+use crate::sysfs;
+
+use std::path::Path;
+
+/// A device model and the settings recommended for it.
+#[derive(Debug)]
+pub struct KnownDevice {
+    /// The vendor and model as reported by the device, compared ignoring case.
+    pub vendor: &'static str,
+    pub model: &'static str,
+    /// What the device is and why the settings help, shown when it is recognized.
+    pub note: &'static str,
+    pub buffer_size: Option<u64>,
+    /// Whether to bypass the page cache, which otherwise hides how the device behaves.
+    pub direct: bool,
+    pub read_retries: Option<u32>,
+    /// The size of the SLC cache in bytes, where known, so --cache-bust-distance can be chosen to
+    /// exceed it.
+    pub slc_cache_size: Option<u64>,
+}
+
+const KNOWN_DEVICES: &[KnownDevice] = &[
+    KnownDevice {
+        vendor: "JMicron",
+        model: "Generic",
+        note: "JMicron USB to SATA bridge, which may reset under large transfers",
+        buffer_size: Some(64 << 10),
+        direct: false,
+        read_retries: Some(3),
+        slc_cache_size: None,
+    },
+    KnownDevice {
+        vendor: "ASMT",
+        model: "2105",
+        note: "ASMedia USB to SATA bridge, which may reset under large transfers",
+        buffer_size: Some(64 << 10),
+        direct: false,
+        read_retries: Some(3),
+        slc_cache_size: None,
+    },
+    KnownDevice {
+        vendor: "Generic",
+        model: "Flash Disk",
+        note: "unbranded flash drive controller, the identity of many counterfeit drives",
+        buffer_size: None,
+        direct: true,
+        read_retries: Some(2),
+        slc_cache_size: None,
+    },
+];
+
+/// The entry for the device at the given path, if its model is known.
+pub fn lookup(path: &Path) -> Option<&'static KnownDevice> {
+    let device = sysfs::disk_dir(path).ok()?.join("device");
+    let vendor = sysfs::read_attribute(&device.join("vendor"))?;
+    let model = sysfs::read_attribute(&device.join("model"))?;
+    KNOWN_DEVICES.iter().find(|known| {
+        known.vendor.eq_ignore_ascii_case(&vendor) && known.model.eq_ignore_ascii_case(&model)
+    })
+}
@@ -0,0 +1,313 @@
+//! Ed25519 signatures (RFC 8032) for signing the report of --report-out with --sign-key.
+//!
+//! Only signing is needed, so only signing is implemented, following the field and group
+//! arithmetic of TweetNaCl: field elements are 16 limbs of 16 bits in `i64`s, points are in
+//! extended coordinates, and the scalar multiplication runs in constant time.
+
+use sha2::{Digest, Sha512};
+
+type Field = [i64; 16];
+type Point = [Field; 4];
+
+const ZERO: Field = [0; 16];
+const ONE: Field = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// 2 * d, where d is the constant of the curve.
+const D2: Field = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+
+/// The coordinates of the base point.
+const BASE_X: Field = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+const BASE_Y: Field = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+];
+
+/// The order of the base point, little-endian.
+const L: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+/// A private key, and the public key that verifies its signatures.
+pub struct SigningKey {
+    /// The clamped scalar and the prefix of the nonces, both from the hash of the private key.
+    expanded: [u8; 64],
+    public_key: [u8; 32],
+}
+
+impl SigningKey {
+    /// The key for the 32 bytes of an Ed25519 private key.
+    pub fn from_bytes(private_key: &[u8; 32]) -> SigningKey {
+        let mut expanded = [0; 64];
+        expanded.copy_from_slice(&Sha512::digest(private_key));
+        expanded[0] &= 248;
+        expanded[31] &= 127;
+        expanded[31] |= 64;
+        let public_key = pack(&mut scalar_base(&expanded[..32]));
+        SigningKey {
+            expanded,
+            public_key,
+        }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public_key
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        let r = reduce(
+            &Sha512::new()
+                .chain(&self.expanded[32..])
+                .chain(message)
+                .finalize(),
+        );
+        let big_r = pack(&mut scalar_base(&r));
+        let h = reduce(
+            &Sha512::new()
+                .chain(big_r)
+                .chain(self.public_key)
+                .chain(message)
+                .finalize(),
+        );
+
+        let mut x = [0i64; 64];
+        for (x, r) in x.iter_mut().zip(r) {
+            *x = r as i64;
+        }
+        for (i, h) in h.iter().enumerate() {
+            for (j, a) in self.expanded[..32].iter().enumerate() {
+                x[i + j] += *h as i64 * *a as i64;
+            }
+        }
+        let mut signature = [0; 64];
+        signature[..32].copy_from_slice(&big_r);
+        signature[32..].copy_from_slice(&mod_l(&mut x));
+        signature
+    }
+}
+
+fn carry(o: &mut Field) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Swap `p` and `q` if `b` is 1, in constant time.
+fn select(p: &mut Field, q: &mut Field, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack_field(n: &Field) -> [u8; 32] {
+    let mut t = *n;
+    carry(&mut t);
+    carry(&mut t);
+    carry(&mut t);
+    let mut m = ZERO;
+    for _ in 0..2 {
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        select(&mut t, &mut m, 1 - b);
+    }
+    let mut o = [0; 32];
+    for i in 0..16 {
+        o[2 * i] = t[i] as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    o
+}
+
+fn add(a: &Field, b: &Field) -> Field {
+    std::array::from_fn(|i| a[i] + b[i])
+}
+
+fn sub(a: &Field, b: &Field) -> Field {
+    std::array::from_fn(|i| a[i] - b[i])
+}
+
+fn mul(a: &Field, b: &Field) -> Field {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = ZERO;
+    o.copy_from_slice(&t[..16]);
+    carry(&mut o);
+    carry(&mut o);
+    o
+}
+
+fn invert(i: &Field) -> Field {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = mul(&c, &c);
+        if a != 2 && a != 4 {
+            c = mul(&c, i);
+        }
+    }
+    c
+}
+
+/// Add `q` to `p`.
+fn add_point(p: &mut Point, q: &Point) {
+    let a = mul(&sub(&p[1], &p[0]), &sub(&q[1], &q[0]));
+    let b = mul(&add(&p[0], &p[1]), &add(&q[0], &q[1]));
+    let c = mul(&mul(&p[3], &q[3]), &D2);
+    let d = mul(&p[2], &q[2]);
+    let d = add(&d, &d);
+    let e = sub(&b, &a);
+    let f = sub(&d, &c);
+    let g = add(&d, &c);
+    let h = add(&b, &a);
+    p[0] = mul(&e, &f);
+    p[1] = mul(&h, &g);
+    p[2] = mul(&g, &f);
+    p[3] = mul(&e, &h);
+}
+
+fn pack(p: &mut Point) -> [u8; 32] {
+    let zi = invert(&p[2]);
+    let x = mul(&p[0], &zi);
+    let y = mul(&p[1], &zi);
+    let mut r = pack_field(&y);
+    r[31] ^= (pack_field(&x)[0] & 1) << 7;
+    r
+}
+
+/// The base point multiplied by the little-endian scalar `s`.
+fn scalar_base(s: &[u8]) -> Point {
+    let mut p = [ZERO, ONE, ONE, ZERO];
+    let mut q = [BASE_X, BASE_Y, ONE, mul(&BASE_X, &BASE_Y)];
+    for i in (0..256).rev() {
+        let b = ((s[i / 8] >> (i & 7)) & 1) as i64;
+        for (p, q) in p.iter_mut().zip(q.iter_mut()) {
+            select(p, q, b);
+        }
+        add_point(&mut q, &p);
+        let doubled = p;
+        add_point(&mut p, &doubled);
+        for (p, q) in p.iter_mut().zip(q.iter_mut()) {
+            select(p, q, b);
+        }
+    }
+    p
+}
+
+/// `x` modulo the order of the base point.
+fn mod_l(x: &mut [i64; 64]) -> [u8; 32] {
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += carry - 16 * x[i] * L[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+            j += 1;
+        }
+        x[j] += carry;
+        x[i] = 0;
+    }
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * L[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * L[j];
+    }
+    let mut r = [0; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = (x[i] & 255) as u8;
+    }
+    r
+}
+
+/// A 64-byte hash modulo the order of the base point.
+fn reduce(hash: &[u8]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for (x, h) in x.iter_mut().zip(hash) {
+        *x = *h as i64;
+    }
+    mod_l(&mut x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SigningKey;
+
+    /// Sign `message` with the private key given in hex, returning the public key and signature
+    /// in hex.
+    fn sign(private_key: &str, message: &[u8]) -> (String, String) {
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(private_key, &mut bytes).unwrap();
+        let key = SigningKey::from_bytes(&bytes);
+        (
+            hex::encode(key.public_key()),
+            hex::encode(key.sign(message)),
+        )
+    }
+
+    #[test]
+    fn signs_rfc_8032_test_vector_1() {
+        let (public_key, signature) = sign(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+            b"",
+        );
+        assert_eq!(
+            public_key,
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        assert_eq!(
+            signature,
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+             5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+    }
+
+    #[test]
+    fn signs_rfc_8032_test_vector_2() {
+        let (public_key, signature) = sign(
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+            &[0x72],
+        );
+        assert_eq!(
+            public_key,
+            "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c"
+        );
+        assert_eq!(
+            signature,
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+             085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Estimating how random data looks, without knowing what was written.
+
+/// The Shannon entropy of the bytes in bits per byte, from 0 for a single repeated byte to 8 for
+/// uniformly distributed bytes.
+pub fn shannon_entropy(buf: &[u8]) -> f64 {
+    if buf.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0u64; 256];
+    for &b in buf {
+        counts[b as usize] += 1;
+    }
+    let len = buf.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+/// How many standard deviations from what random data gives the statistics of
+/// `randomness_failure` may be, so working generators essentially never fail.
+const MAX_DEVIATIONS: f64 = 8.0;
+
+/// Check that the bytes look like the output of a working pseudo-random generator, and describe
+/// what does not if they do not. The checks are the monobit test, the chi-square test of the byte
+/// frequencies, and that no 8-byte word at an 8-byte boundary repeats, which catches a generator
+/// stuck repeating a short period whose bytes are still evenly distributed. Needs a few kilobytes
+/// to be meaningful.
+pub fn randomness_failure(buf: &[u8]) -> Option<String> {
+    let bits = buf.len() as f64 * 8.0;
+    let ones: u64 = buf.iter().map(|b| u64::from(b.count_ones())).sum();
+    let deviation = (ones as f64 - bits / 2.0).abs() / (bits / 4.0).sqrt();
+    if deviation > MAX_DEVIATIONS {
+        return Some(format!(
+            "{:.3}% of the bits are ones, rather than about half",
+            ones as f64 / bits * 100.0
+        ));
+    }
+
+    let mut counts = [0u64; 256];
+    for &b in buf {
+        counts[b as usize] += 1;
+    }
+    let expected = buf.len() as f64 / 256.0;
+    let chi_square: f64 = counts
+        .iter()
+        .map(|&count| (count as f64 - expected).powi(2) / expected)
+        .sum();
+    // With 255 degrees of freedom, the mean is 255 and the variance twice that.
+    if chi_square > 255.0 + MAX_DEVIATIONS * 510f64.sqrt() {
+        return Some(format!(
+            "the byte frequencies are uneven, with a chi-square of {:.0} where about 255 is expected",
+            chi_square
+        ));
+    }
+
+    let mut words = std::collections::HashSet::new();
+    for (i, word) in buf.chunks_exact(8).enumerate() {
+        if !words.insert(word) {
+            return Some(format!(
+                "the 8 bytes at offset {} of the sample repeat earlier ones",
+                i * 8
+            ));
+        }
+    }
+    None
+}
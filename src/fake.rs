@@ -0,0 +1,91 @@
+//! A simulated counterfeit device for --simulate-fake, to check the detection of fakes end to end
+//! without having to find one.
+//!
+//! The device is a regular file, usually a sparse one, whose size is the capacity the fake claims
+//! to have. Only its first `real_size` bytes are ever used: offsets beyond them wrap around
+//! modulo the real size, so writing past the real capacity overwrites what was written to the
+//! start, and reading there returns whatever was last written to the offset it wraps to. This is
+//! how the most common fake flash drives behave, with a controller reprogrammed to report a larger
+//! size than its flash has.
+
+use crate::device::DeviceHandle;
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+
+pub struct FakeDevice {
+    file: File,
+    /// The number of bytes the device really stores.
+    real_size: u64,
+    /// The size the device claims to have, that of the file.
+    claimed_size: u64,
+    position: u64,
+}
+
+impl FakeDevice {
+    pub fn new(file: File, real_size: u64) -> std::io::Result<FakeDevice> {
+        let claimed_size = file.metadata()?.len();
+        Ok(FakeDevice {
+            file,
+            real_size,
+            claimed_size,
+            position: 0,
+        })
+    }
+
+    /// Where in the file the next `len` bytes at the position are stored, and how many of them
+    /// can be transferred at once without wrapping around or going past the claimed size.
+    fn next_span(&self, len: usize) -> (u64, usize) {
+        let offset = self.position % self.real_size;
+        let len = (len as u64)
+            .min(self.real_size - offset)
+            .min(self.claimed_size.saturating_sub(self.position));
+        (offset, len as usize)
+    }
+}
+
+impl Read for FakeDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let (offset, len) = self.next_span(buf.len());
+        let n = self.file.read_at(&mut buf[..len], offset)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for FakeDevice {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let (offset, len) = self.next_span(buf.len());
+        let n = self.file.write_at(&buf[..len], offset)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Seek for FakeDevice {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.claimed_size.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl DeviceHandle for FakeDevice {
+    fn file(&self) -> &File {
+        &self.file
+    }
+}
@@ -0,0 +1,156 @@
+//! Fingerprints of earlier runs for --compare-previous, to notice a device getting worse over
+//! time.
+//!
+//! The fingerprint of a run is the list of regions of mismatching bytes it found, stored in a
+//! file named after the serial number of the device as `name = value` lines, like the keystream
+//! descriptor:
+//!
+//! ```text
+//! disk_size = 1048576
+//! finished_at = 1614834367
+//! bad = 4096..8192
+//! ```
+
+use anyhow::{bail, Context, Result};
+
+use crate::keystream::parse_pairs;
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The outcome of a run, as needed to compare it with later runs.
+#[derive(Debug)]
+pub struct Fingerprint {
+    pub disk_size: u64,
+    /// Unix time at which the run finished.
+    pub finished_at: u64,
+    /// Regions of mismatching bytes, in ascending order.
+    pub bad: Vec<Range<u64>>,
+}
+
+impl Fingerprint {
+    /// The fingerprint of the last run on the device with the given serial number, if there was
+    /// one.
+    pub fn load(dir: &Path, serial: &str) -> Result<Option<Fingerprint>> {
+        let path = path(dir, serial);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Unable to read fingerprint '{}'", path.display()))
+            }
+        };
+        Self::parse(&contents)
+            .map(Some)
+            .with_context(|| format!("Invalid fingerprint '{}'", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Fingerprint> {
+        let mut disk_size = None;
+        let mut finished_at = None;
+        let mut bad = Vec::new();
+        for (name, value) in parse_pairs(contents)? {
+            match name {
+                "disk_size" => disk_size = Some(value.parse().context("Invalid disk_size")?),
+                "finished_at" => finished_at = Some(value.parse().context("Invalid finished_at")?),
+                "bad" => bad.push(parse_range(value)?),
+                _ => bail!("Unknown name '{}'", name),
+            }
+        }
+        Ok(Fingerprint {
+            disk_size: disk_size.context("Missing disk_size")?,
+            finished_at: finished_at.context("Missing finished_at")?,
+            bad,
+        })
+    }
+
+    /// Store the fingerprint as the last run on the device with the given serial number.
+    pub fn save(&self, dir: &Path, serial: &str) -> Result<()> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Unable to create history directory '{}'", dir.display()))?;
+        let mut contents = format!(
+            "disk_size = {}\nfinished_at = {}\n",
+            self.disk_size, self.finished_at
+        );
+        for range in &self.bad {
+            contents.push_str(&format!("bad = {}..{}\n", range.start, range.end));
+        }
+        let path = path(dir, serial);
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Unable to write fingerprint '{}'", path.display()))
+    }
+
+    /// The parts of the bad regions of this run that were not bad in the `previous` run.
+    pub fn newly_bad(&self, previous: &Fingerprint) -> Vec<Range<u64>> {
+        subtract(&self.bad, &previous.bad)
+    }
+}
+
+/// The parts of the `ranges` not covered by any of the `other` ranges. Both must be in ascending
+/// order and not overlap.
+pub fn subtract(ranges: &[Range<u64>], other: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut difference = Vec::new();
+    for range in ranges {
+        let mut start = range.start;
+        for other in other {
+            if other.end <= start {
+                continue;
+            }
+            if other.start >= range.end {
+                break;
+            }
+            if other.start > start {
+                difference.push(start..other.start);
+            }
+            start = start.max(other.end);
+            if start >= range.end {
+                break;
+            }
+        }
+        if start < range.end {
+            difference.push(start..range.end);
+        }
+    }
+    difference
+}
+
+/// Where fingerprints are stored without --history-dir: `verify-device-capacity` in
+/// $XDG_STATE_HOME, or in ~/.local/state if that is not set.
+pub fn default_dir() -> Result<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".local/state"),
+            None => bail!("Neither XDG_STATE_HOME nor HOME is set, please give --history-dir"),
+        },
+    };
+    Ok(state_home.join("verify-device-capacity"))
+}
+
+fn path(dir: &Path, serial: &str) -> PathBuf {
+    // Serial numbers are chosen by the vendor, so must not be able to name another directory.
+    let name: String = serial
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    dir.join(format!("{}.fingerprint", name))
+}
+
+fn parse_range(s: &str) -> Result<Range<u64>> {
+    let (start, end) = s
+        .split_once("..")
+        .with_context(|| format!("Invalid range '{}', expected start..end", s))?;
+    let start = start.parse().context("Invalid range start")?;
+    let end = end.parse().context("Invalid range end")?;
+    if start >= end {
+        bail!("Range '{}' is empty", s);
+    }
+    Ok(start..end)
+}
@@ -0,0 +1,122 @@
+//! Clean shutdown of the write and read phases on Ctrl-C, on SIGTERM from a service manager or
+//! job scheduler, or on `stop` being written to the file given with --control-file by a program
+//! that cannot send signals.
+//!
+//! Once `init` is called, the first signal only sets a flag, which the phases check after every
+//! buffer to sync the device, report how far they got and stop. A second signal exits right away,
+//! in case stopping cleanly hangs on a stuck device. The control file is read by a thread of its
+//! own, which sets a flag of its own.
+
+use anyhow::{bail, Context, Result};
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// How often the control file is read.
+const CONTROL_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The signal received, or 0 if none has been.
+static SIGNAL: OnceLock<Arc<AtomicUsize>> = OnceLock::new();
+
+/// The control file watched, and whether it said to stop.
+static CONTROL_FILE: OnceLock<PathBuf> = OnceLock::new();
+static CONTROL_FILE_STOP: AtomicBool = AtomicBool::new(false);
+
+pub fn init() -> Result<()> {
+    let signal = Arc::new(AtomicUsize::new(0));
+    let received = Arc::new(AtomicBool::new(false));
+    for number in [SIGINT, SIGTERM] {
+        // Registered before the flag is set, so only a second signal exits.
+        signal_hook::flag::register_conditional_shutdown(number, 128 + number, received.clone())
+            .context("Unable to register signal handler")?;
+        signal_hook::flag::register(number, received.clone())
+            .context("Unable to register signal handler")?;
+        signal_hook::flag::register_usize(number, signal.clone(), number as usize)
+            .context("Unable to register signal handler")?;
+    }
+    let _ = SIGNAL.set(signal);
+    Ok(())
+}
+
+/// Stop the run like a signal does once the file at `path` contains `stop`, ignoring surrounding
+/// whitespace. The file does not have to exist until then. Fails if it already says to stop, as
+/// that is left over from stopping an earlier run.
+pub fn watch_control_file(path: &Path) -> Result<()> {
+    if says_stop(path)? {
+        bail!(
+            "Control file '{}' already says to stop, clear it before starting the run",
+            path.display()
+        );
+    }
+    let _ = CONTROL_FILE.set(path.to_path_buf());
+    let path = path.to_path_buf();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(CONTROL_FILE_POLL_INTERVAL);
+        // The controller may be replacing the file, so failing to read it is not a reason to stop.
+        if says_stop(&path).unwrap_or(false) {
+            CONTROL_FILE_STOP.store(true, Ordering::Relaxed);
+            break;
+        }
+    });
+    Ok(())
+}
+
+fn says_stop(path: &Path) -> Result<bool> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.trim() == "stop"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => {
+            Err(e).with_context(|| format!("Unable to read control file '{}'", path.display()))
+        }
+    }
+}
+
+/// What interrupted the run, if anything has.
+pub fn received() -> Option<Interrupted> {
+    let signal = SIGNAL
+        .get()
+        .map_or(0, |signal| signal.load(Ordering::Relaxed));
+    if signal != 0 {
+        return Some(Interrupted::Signal(signal as i32));
+    }
+    CONTROL_FILE_STOP
+        .load(Ordering::Relaxed)
+        .then_some(Interrupted::ControlFile)
+}
+
+/// Error for a stopped run. Attached to the error chain so `main` can exit with the conventional
+/// code of 128 plus the signal number, which for the control file is that of SIGINT.
+#[derive(Debug, Clone, Copy)]
+pub enum Interrupted {
+    Signal(i32),
+    ControlFile,
+}
+
+impl Interrupted {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Interrupted::Signal(signal) => 128 + signal,
+            Interrupted::ControlFile => 128 + SIGINT,
+        }
+    }
+}
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Interrupted::Signal(SIGINT) => f.write_str("Interrupted by SIGINT"),
+            Interrupted::Signal(SIGTERM) => f.write_str("Interrupted by SIGTERM"),
+            Interrupted::Signal(signal) => write!(f, "Interrupted by signal {}", signal),
+            Interrupted::ControlFile => match CONTROL_FILE.get() {
+                Some(path) => write!(f, "Stopped by control file '{}'", path.display()),
+                None => f.write_str("Stopped by control file"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for Interrupted {}
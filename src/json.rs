@@ -0,0 +1,138 @@
+//! Minimal JSON support for the flat objects exchanged with other tools.
+
+use anyhow::{bail, Context, Result};
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Encode a string as a JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Encode an optional string as a JSON string literal, or null.
+pub fn optional_string(s: Option<&str>) -> String {
+    s.map(string).unwrap_or_else(|| "null".to_string())
+}
+
+/// Build a single-line JSON object from names and already encoded values.
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}", string(name), value))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// Parse a JSON object whose values are all strings, numbers, booleans or null, like those built
+/// by `object`. Strings are returned with their escapes decoded, numbers and booleans as written,
+/// and null as None.
+pub fn parse_flat_object(s: &str) -> Result<Vec<(String, Option<String>)>> {
+    let mut chars = s.chars().peekable();
+    let mut fields = Vec::new();
+
+    expect(&mut chars, '{')?;
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let name = parse_string(&mut chars)?;
+            expect(&mut chars, ':')?;
+            skip_whitespace(&mut chars);
+            let value = match chars.peek() {
+                Some('"') => Some(parse_string(&mut chars)?),
+                Some(_) => {
+                    let mut literal = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == ',' || c == '}' || c.is_whitespace() {
+                            break;
+                        }
+                        literal.push(c);
+                        chars.next();
+                    }
+                    match literal.as_str() {
+                        "null" => None,
+                        "true" | "false" => Some(literal),
+                        _ if literal.parse::<f64>().is_ok() => Some(literal),
+                        _ => bail!("Invalid value '{}' for '{}'", literal, name),
+                    }
+                }
+                None => bail!("Unexpected end of input"),
+            };
+            fields.push((name, value));
+
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                Some(c) => bail!("Expected ',' or '}}', found '{}'", c),
+                None => bail!("Unexpected end of input"),
+            }
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    if let Some(c) = chars.next() {
+        bail!("Unexpected '{}' after the end of the object", c);
+    }
+    Ok(fields)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<()> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => bail!("Expected '{}', found '{}'", expected, c),
+        None => bail!("Expected '{}', found end of input", expected),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String> {
+    expect(chars, '"')?;
+    let mut out = String::new();
+    loop {
+        match chars.next().context("Unterminated string")? {
+            '"' => return Ok(out),
+            '\\' => match chars.next().context("Unterminated string")? {
+                '"' => out.push('"'),
+                '\\' => out.push('\\'),
+                '/' => out.push('/'),
+                'b' => out.push('\u{8}'),
+                'f' => out.push('\u{c}'),
+                'n' => out.push('\n'),
+                'r' => out.push('\r'),
+                't' => out.push('\t'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16)
+                        .with_context(|| format!("Invalid escape '\\u{}'", hex))?;
+                    out.push(char::from_u32(code).context("Unsupported surrogate escape")?);
+                }
+                c => bail!("Invalid escape '\\{}'", c),
+            },
+            c => out.push(c),
+        }
+    }
+}
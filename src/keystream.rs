@@ -0,0 +1,234 @@
+//! The pseudorandom stream of bytes written to and expected back from the device.
+
+use anyhow::{bail, Context, Result};
+
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::{ChaCha12Rng, ChaCha20Rng, ChaCha8Rng};
+
+use std::path::Path;
+
+/// The variants of the ChaCha stream cipher that can generate the keystream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    ChaCha8,
+    ChaCha12,
+    ChaCha20,
+}
+
+/// The algorithm used unless a keystream descriptor says otherwise.
+pub const DEFAULT_ALGORITHM: Algorithm = Algorithm::ChaCha12;
+
+impl std::str::FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Algorithm> {
+        match s.to_ascii_lowercase().as_str() {
+            "chacha8" => Ok(Algorithm::ChaCha8),
+            "chacha12" => Ok(Algorithm::ChaCha12),
+            "chacha20" => Ok(Algorithm::ChaCha20),
+            _ => bail!(
+                "Unknown algorithm '{}', expected one of chacha8, chacha12 or chacha20",
+                s
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Algorithm::ChaCha8 => "chacha8",
+            Algorithm::ChaCha12 => "chacha12",
+            Algorithm::ChaCha20 => "chacha20",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A keystream generator for one of the supported algorithms.
+#[derive(Debug, Clone)]
+pub enum Keystream {
+    ChaCha8(ChaCha8Rng),
+    ChaCha12(ChaCha12Rng),
+    ChaCha20(ChaCha20Rng),
+}
+
+impl Keystream {
+    pub fn new(algorithm: Algorithm, key: [u8; 32]) -> Keystream {
+        match algorithm {
+            Algorithm::ChaCha8 => Keystream::ChaCha8(ChaCha8Rng::from_seed(key)),
+            Algorithm::ChaCha12 => Keystream::ChaCha12(ChaCha12Rng::from_seed(key)),
+            Algorithm::ChaCha20 => Keystream::ChaCha20(ChaCha20Rng::from_seed(key)),
+        }
+    }
+
+    /// Set the 64-bit nonce, called the stream by rand_chacha.
+    pub fn set_stream(&mut self, stream: u64) {
+        match self {
+            Keystream::ChaCha8(rng) => rng.set_stream(stream),
+            Keystream::ChaCha12(rng) => rng.set_stream(stream),
+            Keystream::ChaCha20(rng) => rng.set_stream(stream),
+        }
+    }
+
+    /// Set the position in the keystream, counted in 32-bit words.
+    pub fn set_word_pos(&mut self, word_pos: u128) {
+        match self {
+            Keystream::ChaCha8(rng) => rng.set_word_pos(word_pos),
+            Keystream::ChaCha12(rng) => rng.set_word_pos(word_pos),
+            Keystream::ChaCha20(rng) => rng.set_word_pos(word_pos),
+        }
+    }
+}
+
+impl RngCore for Keystream {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Keystream::ChaCha8(rng) => rng.next_u32(),
+            Keystream::ChaCha12(rng) => rng.next_u32(),
+            Keystream::ChaCha20(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Keystream::ChaCha8(rng) => rng.next_u64(),
+            Keystream::ChaCha12(rng) => rng.next_u64(),
+            Keystream::ChaCha20(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Keystream::ChaCha8(rng) => rng.fill_bytes(dest),
+            Keystream::ChaCha12(rng) => rng.fill_bytes(dest),
+            Keystream::ChaCha20(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_chacha::rand_core::Error> {
+        match self {
+            Keystream::ChaCha8(rng) => rng.try_fill_bytes(dest),
+            Keystream::ChaCha12(rng) => rng.try_fill_bytes(dest),
+            Keystream::ChaCha20(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+/// Describes a keystream generated by some other tool, so that a device filled by that tool can
+/// be verified.
+///
+/// The keystream is the output of the original (64-bit nonce, 64-bit block counter) ChaCha
+/// cipher with the given number of rounds, i.e. ChaCha encryption of an all-zero plaintext. The
+/// block counter occupies state words 12 and 13 and the nonce words 14 and 15, both little
+/// endian, and each 64-byte block is output as its 16 state words in little endian order.
+///
+/// The descriptor file consists of `name = value` lines, with `#` starting a comment:
+///
+/// ```text
+/// algorithm = chacha20    # chacha8, chacha12 or chacha20
+/// key = <64 hex characters>
+/// nonce = 0               # optional, defaults to 0
+/// counter = 0             # optional, the first block counter, defaults to 0
+/// ```
+#[derive(Debug, Clone)]
+pub struct KeystreamSpec {
+    pub algorithm: Algorithm,
+    pub key: [u8; 32],
+    pub nonce: u64,
+    pub counter: u64,
+}
+
+impl KeystreamSpec {
+    pub fn read(path: &Path) -> Result<KeystreamSpec> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read keystream descriptor '{}'", path.display()))?;
+        Self::parse(&contents)
+            .with_context(|| format!("Invalid keystream descriptor '{}'", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<KeystreamSpec> {
+        let pairs = parse_pairs(contents)?;
+        for (name, _) in pairs.iter() {
+            if !["algorithm", "key", "nonce", "counter"].contains(name) {
+                bail!("Unknown name '{}'", name);
+            }
+        }
+        Self::from_pairs(&pairs)
+    }
+
+    /// Build the spec from the `algorithm`, `key`, `nonce` and `counter` pairs, ignoring any
+    /// other names.
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Result<KeystreamSpec> {
+        let mut algorithm = None;
+        let mut key = None;
+        let mut nonce = 0;
+        let mut counter = 0;
+
+        for &(name, value) in pairs {
+            match name {
+                "algorithm" => algorithm = Some(value.parse()?),
+                "key" => {
+                    let mut buf = [0u8; 32];
+                    hex::decode_to_slice(value, &mut buf)
+                        .context("key must be exactly 64 hexadecimal characters")?;
+                    key = Some(buf);
+                }
+                "nonce" => nonce = value.parse().context("nonce must be an integer")?,
+                "counter" => counter = value.parse().context("counter must be an integer")?,
+                _ => (),
+            }
+        }
+
+        Ok(KeystreamSpec {
+            algorithm: algorithm.context("Missing algorithm")?,
+            key: key.context("Missing key")?,
+            nonce,
+            counter,
+        })
+    }
+
+    pub fn keystream(&self) -> Keystream {
+        let mut keystream = Keystream::new(self.algorithm, self.key);
+        keystream.set_stream(self.nonce);
+        // Each block is 16 words.
+        keystream.set_word_pos(u128::from(self.counter) * 16);
+        keystream
+    }
+
+    /// Get the keystream positioned at the start of the 32-bit word containing the given byte
+    /// offset into it.
+    pub fn keystream_at(&self, offset: u64) -> Keystream {
+        let mut keystream = self.keystream();
+        keystream.set_word_pos(u128::from(self.counter) * 16 + u128::from(offset / 4));
+        keystream
+    }
+
+    /// Generate `len` bytes of the keystream starting at the given byte offset into it.
+    pub fn bytes_at(&self, offset: u64, len: usize) -> Vec<u8> {
+        let mut keystream = self.keystream_at(offset);
+        // Whole words are generated, so generate from the start of the word and drop the bytes
+        // before the offset.
+        let skip = (offset % 4) as usize;
+        let mut buf = vec![0; skip + len];
+        keystream.fill_bytes(&mut buf);
+        buf.drain(..skip);
+        buf
+    }
+}
+
+/// Parse `name = value` lines, ignoring blank lines and anything after a `#`.
+pub fn parse_pairs(contents: &str) -> Result<Vec<(&str, &str)>> {
+    let mut pairs = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((name, value)) => pairs.push((name.trim(), value.trim())),
+            None => bail!("Line {} is not of the form 'name = value'", i + 1),
+        }
+    }
+    Ok(pairs)
+}
@@ -1,25 +1,102 @@
-use anyhow::{bail, Context, Error, Result};
+mod affinity;
+mod buffer;
+mod checkpoint;
+mod checksum;
+mod color;
+mod compare;
+mod device;
+mod device_db;
+mod ed25519;
+mod entropy;
+mod fake;
+mod history;
+mod interrupt;
+mod json;
+mod keystream;
+mod marker;
+mod messages;
+mod metrics;
+mod multi;
+mod output;
+mod policy;
+mod progress;
+mod region;
+mod report;
+mod schema;
+mod sd_notify;
+mod seed_service;
+mod size;
+mod slc;
+mod source;
+mod state;
+mod strict;
+mod sysfs;
+mod syslog;
+mod thermal;
+mod tui;
+#[cfg(target_os = "linux")]
+mod uring;
+mod usage;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
 
 use rand_chacha::rand_core::RngCore;
-use rand_chacha::rand_core::SeedableRng;
-use rand_chacha::ChaCha12Rng;
 
 use structopt::StructOpt;
 
+use crossterm::style::Color;
 use crossterm::tty::IsTty;
-use crossterm::ExecutableCommand;
 
-use std::convert::TryInto;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
+
+use buffer::Buffer;
+use checkpoint::{Checkpoint, Phase};
+use checksum::{ChecksumVerifier, ChecksumWriter};
+use color::ColorMode;
+use device::{open_device, DeviceHandle, Mode, OpenFlags};
+use history::Fingerprint;
+use keystream::{Algorithm, Keystream, KeystreamSpec, DEFAULT_ALGORITHM};
+use messages::{Locale, Message, Verb};
+use output::emit;
+use policy::Policy;
+use progress::{Progress, ProgressOptions};
+use report::Report;
+use source::{DataSource, KeystreamSource, PrefetchSource};
+use state::RunState;
+use strict::Condition;
+use thermal::ThermalGuard;
+
+/// Usage examples shown at the end of --help.
+const EXAMPLES: &str = "EXAMPLES:
+    Write the whole device, then read it back and verify it. This is the default when neither
+    --write nor --read is given:
+        verify-device-capacity /dev/sdX
+
+    Only write, with a seed of your choosing so the device can be verified later. Without a seed
+    a random one is used, and printed as the raw seed to pass to --raw-seed instead:
+        verify-device-capacity --write --seed my-seed /dev/sdX
 
-const UPDATE_FREQUENCY: Duration = Duration::from_secs(60);
+    Only read and verify a device written earlier with that seed:
+        verify-device-capacity --read --seed my-seed /dev/sdX
+
+    Write, then power-cycle or replug the device and verify it, with the seed and sizes saved in
+    between:
+        verify-device-capacity --stage write --state-file sdX.state /dev/sdX
+        verify-device-capacity --stage verify --state-file sdX.state /dev/sdX
+
+    Re-check only the blocks at the offsets listed in a file, one per line:
+        verify-device-capacity --read --seed my-seed --offsets-in offsets.txt /dev/sdX";
 
 /// Write a pseudorandom string of bytes to the given device. Then try to read them back to confirm
 /// they match what was originally written.
 #[derive(Debug, StructOpt)]
+#[structopt(after_help = EXAMPLES)]
 struct Args {
     /// Specify the seed. If set, the input is sha256-hashed and the hash is used as the RNG seed.
     ///
@@ -36,6 +113,61 @@ struct Args {
     #[structopt(long = "raw-seed")]
     raw_seed: Option<String>,
 
+    /// Derive the seed from the given master key and the serial number of the device, so every
+    /// device gets a distinct but reproducible seed without having to store it.
+    ///
+    /// The seed is the sha256 hash of the master key, followed by a single zero byte, followed by
+    /// the device serial number, all as UTF-8. The serial number is read from sysfs, from the
+    /// first available of the device's `serial` attribute, its `wwid` attribute, and the serial
+    /// number of the USB device it is attached through.
+    ///
+    /// Exclusive with --seed and --raw-seed.
+    #[structopt(long = "master-key")]
+    master_key: Option<String>,
+
+    /// Fetch the seed from the seed service at the given https:// URL, so the seeds are held by
+    /// one server and never stored on the machine running the test.
+    ///
+    /// The serial number, vendor, model and size of the device are POSTed to the URL as a JSON
+    /// object, and the service must answer with the seed as 64 hexadecimal characters, like
+    /// --raw-seed. The request is made with curl, which must be installed, and is retried a few
+    /// times on transient failures.
+    ///
+    /// Exclusive with --seed, --raw-seed and --master-key.
+    #[structopt(long = "seed-url")]
+    seed_url: Option<String>,
+
+    /// Start the keystream this many bytes into the stream generated from the seed, instead of at
+    /// its start. Must be a multiple of 64, the ChaCha block size.
+    ///
+    /// The device is always written from its first byte, so to test several partitions of one
+    /// device with the same seed in separate runs, pass each partition as the device and give each
+    /// a stream offset of at least the combined size of the partitions before it. Their contents
+    /// then come from disjoint segments of the stream, so data that ends up on the wrong partition
+    /// is detected rather than matching by accident.
+    ///
+    /// Only valid with --seed, --raw-seed, --master-key or --seed-url, as --keystream descriptors
+    /// and saved state record their own starting position.
+    #[structopt(long = "stream-offset", parse(try_from_str = size::parse_size))]
+    stream_offset: Option<u64>,
+
+    /// Verify against a keystream described by the given descriptor file, rather than one
+    /// generated from a seed. This allows verifying devices filled by other tools.
+    ///
+    /// The file consists of `name = value` lines giving the `algorithm` (chacha8, chacha12 or
+    /// chacha20), the 256-bit `key` in hexadecimal, and optionally the 64-bit `nonce` and the
+    /// starting 64-bit block `counter`, both defaulting to 0. The keystream is the original
+    /// (non-IETF) ChaCha cipher applied to an all-zero plaintext.
+    ///
+    /// Exclusive with --seed, --raw-seed, --master-key and --seed-url.
+    #[structopt(long = "keystream")]
+    keystream: Option<PathBuf>,
+
+    /// Print more details. May be given multiple times for more verbosity. With -vv, progress
+    /// lines also show the CPU usage and resident memory of the process.
+    #[structopt(long = "verbose", short = "v", parse(from_occurrences))]
+    verbose: u8,
+
     /// Write to the given device.
     #[structopt(long = "write", short = "w")]
     write: bool,
@@ -44,273 +176,5229 @@ struct Args {
     #[structopt(long = "read", short = "r")]
     read: bool,
 
-    /// The device to test.
+    /// Discard (TRIM) the whole device before writing, so SSDs start the write from a trimmed
+    /// state. Only supported on Linux.
+    ///
+    /// Failure to trim is reported but does not abort the run, since many USB bridges do not pass
+    /// discard requests through.
+    #[structopt(long = "trim-first")]
+    trim_first: bool,
+
+    /// Give up waiting for the overwrite confirmation after this many seconds, treating it as if
+    /// the answer was no.
+    ///
+    /// If not specified, will wait for a response indefinitely.
+    #[structopt(long = "confirm-timeout")]
+    confirm_timeout: Option<u64>,
+
+    /// Take the overwrite confirmation from the given file instead of asking for it. The file must
+    /// contain exactly the path of the device as given on the command line, so automation has to
+    /// state which device it intends to overwrite. Refused by a site policy of
+    /// `confirmation = interactive`, see --yes.
+    #[structopt(long = "overwrite-confirmation-from")]
+    overwrite_confirmation_from: Option<PathBuf>,
+
+    /// Save a checkpoint of the run to this JSON file every 30 seconds and when interrupted with
+    /// Ctrl-C, SIGTERM or --control-file, so the run can be continued with --resume-from-json
+    /// after an interrupt or crash. The checkpoint is removed once the run completes.
+    #[structopt(long = "checkpoint")]
+    checkpoint: Option<PathBuf>,
+
+    /// Continue the run saved in the given checkpoint file where it left off, with the same seed
+    /// and sizes. Fails if the device's size or serial number differ from when the checkpoint
+    /// was saved. An interrupted write phase is followed by reading back the whole device.
+    /// Checkpoints keep being saved to the same file unless --checkpoint is given.
+    #[structopt(long = "resume-from-json")]
+    resume_from_json: Option<PathBuf>,
+
+    /// Overwrite the device without asking for confirmation.
+    ///
+    /// Can also be given by setting the VDC_ASSUME_YES environment variable to 1, true or yes,
+    /// or made the default for all users with `confirmation = assume-yes` in the site policy
+    /// /etc/verify-device-capacity/policy. Setting VDC_ASSUME_YES to 0, false or no asks despite
+    /// the site policy. A site policy of `confirmation = interactive` overrides all of these and
+    /// always asks, refusing to run with --yes, --overwrite-confirmation-from or VDC_ASSUME_YES
+    /// set to yes.
+    #[structopt(long = "yes", short = "y")]
+    yes: bool,
+
+    /// On the first mismatch while reading, pause and open an interactive prompt to inspect the
+    /// surrounding bytes, re-read the failing block, or continue reading.
+    ///
+    /// Requires stdin and stderr to be a TTY.
+    #[structopt(long = "pause-on-mismatch")]
+    pause_on_mismatch: bool,
+
+    /// After a mismatch, check whether the device returned the data written to another offset
+    /// instead, as fake devices that wrap their address space around do, and report the wrap
+    /// period if so. Periods that are a multiple of 1 MiB are detected.
+    #[structopt(long = "detect-wraparound")]
+    detect_wraparound: bool,
+
+    /// After writing, re-read the first block of the device and check that it still holds what
+    /// was written to it. A device that is smaller than it claims overwrites the start when
+    /// writes wrap around, so this detects fake capacity before the read phase.
+    #[structopt(long = "check-no-wrap")]
+    check_no_wrap: bool,
+
+    /// Verify the device with this many threads reading disjoint ranges in parallel, so
+    /// verification is not limited by a single core. The first mismatch reported is the one at
+    /// the lowest offset.
+    ///
+    /// The write phase must have used a buffer size that is a multiple of 4 bytes.
+    #[structopt(long = "verify-parallel-readers")]
+    verify_parallel_readers: Option<usize>,
+
+    /// Keep reading after a mismatch rather than stopping, and report every region of mismatching
+    /// bytes at the end. The verified size is still up to the first mismatch.
+    #[structopt(long = "continue-on-error")]
+    continue_on_error: bool,
+
+    /// With --continue-on-error, stop reading once this many separate regions of mismatching
+    /// bytes have been found, to bound the run time and output on hopelessly broken devices.
+    #[structopt(long = "abort-after-errors")]
+    abort_after_errors: Option<usize>,
+
+    /// With --continue-on-error, stop reading as soon as a single region of mismatching bytes
+    /// grows to this size, e.g. 256M, and report where it starts as the effective capacity. A fake
+    /// device returns nothing but mismatches past its real capacity, so this saves reading the
+    /// rest of it, at the cost of not mapping the mismatches beyond.
+    #[structopt(long = "fail-fast-threshold", parse(try_from_str = size::parse_size))]
+    fail_fast_threshold: Option<u64>,
+
+    /// How read data is compared with what was written: `fail-fast` stops at the first mismatch,
+    /// while `ber` reads the whole device like --continue-on-error and also counts the bits that
+    /// differ, reporting the raw bit error rate to grade the quality of marginal media.
+    #[structopt(long = "compare-mode", default_value = "fail-fast")]
+    compare_mode: CompareMode,
+
+    /// After reading, compare the regions of mismatching bytes with those found by the last run
+    /// with this option on the same device, identified by its serial number, and report the
+    /// regions that newly went bad, to notice aging media before it fails completely. Reads the
+    /// whole device like --continue-on-error, and stores the regions found for the next run.
+    #[structopt(long = "compare-previous")]
+    compare_previous: bool,
+
+    /// After reading the device from start to end, read it again from end to start and check that
+    /// both passes find the same regions of mismatching bytes. A device whose contents depend on
+    /// the order it is read in is playing caching or address tricks, so any disagreement fails the
+    /// run, listing where the passes disagree. Reads the whole device like --continue-on-error.
+    #[structopt(long = "bidirectional")]
+    bidirectional: bool,
+
+    /// After reading, write the numbers of the blocks holding mismatching bytes to the given file,
+    /// one per line, as taken by `mke2fs -l` and `e2fsck -l`, so the good part of a partially
+    /// bad device can still be used. Blocks are counted from the start of the device in blocks of
+    /// --remap-block-size, so the filesystem has to be created on the whole device with that block
+    /// size, e.g. `mke2fs -b 4096 -l FILE DEVICE`. Reads the whole device like
+    /// --continue-on-error.
+    #[structopt(long = "repair-remap")]
+    repair_remap: Option<PathBuf>,
+
+    /// Block size in bytes of the filesystem the --repair-remap list is for.
+    #[structopt(
+        long = "remap-block-size",
+        default_value = "4096",
+        parse(try_from_str = size::parse_size)
+    )]
+    remap_block_size: u64,
+
+    /// Directory of the results stored by --compare-previous. Defaults to verify-device-capacity
+    /// in $XDG_STATE_HOME, or in ~/.local/state.
+    #[structopt(long = "history-dir")]
+    history_dir: Option<PathBuf>,
+
+    /// Record every I/O error and mismatch encountered during the run to the given file, one per
+    /// line, independently of what is printed to stderr.
+    #[structopt(long = "error-log")]
+    error_log: Option<PathBuf>,
+
+    /// Number of decimal places to show for the completion percentage in progress updates.
+    #[structopt(long = "progress-precision", default_value = "2")]
+    progress_precision: usize,
+
+    /// Print a progress update each time this many more bytes have been processed, e.g. 1G,
+    /// instead of once a minute, so logs have the same structure however fast the device is.
+    #[structopt(long = "progress-every", parse(try_from_str = size::parse_size))]
+    progress_every: Option<u64>,
+
+    /// When to color output: `auto` colors it only if stderr is a TTY and the NO_COLOR environment
+    /// variable is not set, `always` and `never` override that.
+    #[structopt(long = "color", default_value = "auto")]
+    color: ColorMode,
+
+    /// Prefix every progress and status line with the time as an RFC 3339 timestamp in UTC, to
+    /// correlate the run with kernel logs.
+    #[structopt(long = "timestamps")]
+    timestamps: bool,
+
+    /// Open the device with these extra flags, as a comma-separated list of `direct`, `sync`,
+    /// `dsync`, `noatime` and `excl` (the corresponding O_* flags), e.g. `direct,noatime`. With
+    /// `direct`, the buffer size is rounded to the logical sector size as usual, so reads and
+    /// writes satisfy the alignment direct I/O requires.
+    #[structopt(long = "open-flags", default_value = "none")]
+    open_flags: OpenFlags,
+
+    /// Refuse to start if the I/O buffers would take more than this much memory in total, e.g.
+    /// 512M. Without it, the run is refused if they would not fit in the available memory.
+    #[structopt(long = "max-memory", parse(try_from_str = size::parse_size))]
+    max_memory: Option<u64>,
+
+    /// Rather than writing the whole device and then reading it back, verify each block while
+    /// writing, once this many more bytes (e.g. 4G) have been written after it. A fake controller
+    /// that serves recently written blocks from a small real cache cannot cache blocks that far
+    /// back, so every read hits the media. Choose a distance larger than any plausible cache.
+    ///
+    /// The pattern is: write block N, then read and verify the block written `distance` bytes
+    /// earlier. Writes are synced every `distance` bytes so the block read is on the media rather
+    /// than only in the page cache, whose copy is dropped before reading. The last `distance`
+    /// bytes are verified after rewriting the already verified first `distance` bytes of the
+    /// device with the same data, which pushes them out of any cache.
+    #[structopt(long = "cache-bust-distance", parse(try_from_str = size::parse_size))]
+    cache_bust_distance: Option<u64>,
+
+    /// Rather than writing and reading the whole device, quickly find its real capacity by writing
+    /// and reading back single blocks at offsets chosen by binary search. After each block is
+    /// written, every block written so far that held its data is read back too, so a write that
+    /// wraps around onto one of them is noticed. Offsets at powers of two are probed first, which
+    /// is where devices that ignore the upper address bits wrap around.
+    ///
+    /// Takes a few dozen block operations rather than a pass over the whole device, but only
+    /// catches the common ways of faking capacity. A device that passes should still be verified
+    /// with a full run.
+    #[structopt(long = "probe-capacity")]
+    probe_capacity: bool,
+
+    /// After writing each buffer, sync it and read it back right away, failing at the first
+    /// buffer that does not read back as written. This pinpoints where writes start failing, at
+    /// the cost of a sync and a read per buffer. Fake devices can still return recently written
+    /// data from a cache, so the read phase still follows unless --write is given alone.
+    #[structopt(long = "write-verify")]
+    write_verify: bool,
+
+    /// Also send status lines, periodic progress and the verdict to syslog, with warnings and
+    /// failures at matching severities, so the run shows up in the system journal.
+    #[structopt(long = "syslog")]
+    syslog: bool,
+
+    /// Language of the prompts, progress and results: `en` or `de`. Defaults to the language of
+    /// the environment (LC_ALL, LC_MESSAGES or LANG) if it is translated, and English otherwise.
+    /// Other messages are only in English.
+    #[structopt(long = "lang")]
+    lang: Option<Locale>,
+
+    /// When running as a systemd service with `Type=notify`, report readiness, progress as the
+    /// service status shown by `systemctl status`, and the end of the run to systemd, and keep the
+    /// service watchdog from firing during long phases. Does nothing when not run by systemd.
+    #[structopt(long = "sd-notify")]
+    sd_notify: bool,
+
+    /// Serve Prometheus metrics of the run on the given port on all interfaces, at `/metrics`:
+    /// the bytes written and read so far, the current rate, the phase running, and with --soak the
+    /// number of passed and failed cycles. The server stops with the run.
+    #[structopt(long = "metrics-port")]
+    metrics_port: Option<u16>,
+
+    /// Show a full-screen dashboard with a progress bar, rate graph, ETA and recent errors, rather
+    /// than progress lines. Requires stderr to be a TTY.
+    #[structopt(long = "tui")]
+    tui: bool,
+
+    /// Size in bytes of each read and write issued to the device. Like all sizes, may be given with
+    /// a binary unit such as 4K or 4KiB, or an SI unit such as 4KB.
+    ///
+    /// If not specified, the block size of the device is used.
+    #[structopt(long = "buffer-size", parse(try_from_str = size::parse_size))]
+    buffer_size: Option<u64>,
+
+    /// Align the buffer size and all seeks to the given boundary in bytes, typically 4096,
+    /// regardless of the block size reported by the device. Some USB bridges report a block size
+    /// that leads to misaligned or slow I/O.
+    ///
+    /// Defaults to the logical sector size of the device. The buffer size is rounded up to a
+    /// multiple of the alignment if needed.
+    #[structopt(long = "align", parse(try_from_str = size::parse_size))]
+    align: Option<u64>,
+
+    /// Before writing, benchmark a few buffer sizes by writing to the start of the device and use
+    /// the fastest one for the run. The benchmarked region is overwritten by the run afterwards.
+    ///
+    /// Exclusive with --buffer-size.
+    #[structopt(long = "auto-tune")]
+    auto_tune: bool,
+
+    /// Run only one stage of the verification, so the device can be power-cycled in between and
+    /// nothing is served from its volatile cache. Either `write` or `verify`.
+    ///
+    /// The write stage writes the device and saves everything the verify stage needs, including
+    /// the seed, to --state-file. After power-cycling or replugging the device, the verify stage
+    /// reads the state file and checks the device without writing to it.
+    ///
+    /// Exclusive with --write and --read.
+    #[structopt(long = "stage")]
+    stage: Option<Stage>,
+
+    /// Number of times to automatically retry reading after an I/O error, resuming from the last
+    /// good offset rather than starting over.
+    ///
+    /// If not specified and running interactively, asks whether to retry instead.
+    #[structopt(long = "read-retry")]
+    read_retry: Option<u32>,
+
+    /// Number of times to retry opening the device, half a second apart, if it is missing or busy.
+    /// A freshly plugged in device can take a moment to settle, which otherwise fails automated
+    /// runs started as soon as it appears.
+    #[structopt(long = "open-retries", default_value = "0")]
+    open_retries: u32,
+
+    /// Whether reads may be served from the page cache: `cold` drops the device's cached pages
+    /// before reading so the data really comes from the device, `warm` leaves the cache alone.
+    ///
+    /// Verifying capacity requires cold reads, but warm reads can be useful for measuring
+    /// performance.
+    #[structopt(long = "cache", default_value = "cold")]
+    cache: CacheMode,
+
+    /// How the data written is flushed to the device when writing finishes or is interrupted:
+    /// `fsync` flushes the metadata of the device along with it, `fdatasync` only what is needed
+    /// to read the data back, which suffices for block devices and can be faster, and `none`
+    /// does not flush at all.
+    ///
+    /// With `none`, the data may still be in the page cache rather than on the device when it is
+    /// read back, so only use it together with --open-flags direct, sync or dsync. Modes that have
+    /// to flush to check the device, like --write-verify and --cache-bust-distance, always use
+    /// fdatasync.
+    #[structopt(long = "sync-mode", default_value = "fsync")]
+    sync_mode: SyncMode,
+
+    /// Print the projected duration of the run and exit without testing the device.
+    ///
+    /// The read rate is measured by reading the start of the device. The write rate is only
+    /// measured with --estimate-write, and otherwise assumed to equal the read rate. Use
+    /// --assumed-rate to skip measuring altogether.
+    #[structopt(long = "estimate")]
+    estimate: bool,
+
+    /// For --estimate, use this rate in bytes per second for both writing and reading instead of
+    /// measuring it.
+    #[structopt(long = "assumed-rate")]
+    assumed_rate: Option<f64>,
+
+    /// For --estimate, measure the write rate by writing to the start of the device. This
+    /// overwrites data on the device.
+    #[structopt(long = "estimate-write")]
+    estimate_write: bool,
+
+    /// Write the keystream for the whole size of the device to stdout instead of testing the
+    /// device, e.g. to compare it with `cmp` or hash it externally. The device is only opened to
+    /// find its size.
+    #[structopt(long = "emit-stream")]
+    emit_stream: bool,
+
+    /// Back the I/O buffers with huge pages to reduce TLB pressure at very high throughput. Falls
+    /// back to normal pages with a warning if huge pages are unavailable, e.g. because none are
+    /// reserved in /proc/sys/vm/nr_hugepages. Only supported on Linux.
+    #[structopt(long = "hugepages")]
+    hugepages: bool,
+
+    /// Generate the pseudo-random data on a separate thread, a few buffers ahead of the writes
+    /// and of the comparisons of what is read back, so generating it overlaps with the I/O rather
+    /// than the device waiting while each buffer is generated. Needs a spare CPU core to help,
+    /// and helps most when generating the data takes about as long as the I/O, see --bench-rng.
+    #[structopt(long = "rng-thread")]
+    rng_thread: bool,
+
+    /// Write and read the device through io_uring, keeping several buffers in flight rather than
+    /// waiting for each to complete before issuing the next, so fast NVMe devices are kept busy.
+    /// Needs Linux 5.6 or later, and only applies to the write and read phases.
+    #[structopt(long = "io-uring")]
+    io_uring: bool,
+
+    /// How many buffers --io-uring keeps in flight at once, from 1 to 4096.
+    #[structopt(long = "io-uring-depth", default_value = "16")]
+    io_uring_depth: usize,
+
+    /// Check that the data being written looks random, at the start of writing and again every
+    /// gigabyte, and abort if it does not. Guards against a broken random number
+    /// generator writing patterned data, which a device could hold without its capacity being
+    /// genuine.
+    #[structopt(long = "entropy-check")]
+    entropy_check: bool,
+
+    /// Stop the run cleanly, like Ctrl-C does, once the given file contains `stop`. The file is
+    /// read every second and need not exist until then, for programs that control runs but cannot
+    /// send signals. The device is synced, how far the run got is reported and any checkpoint is
+    /// saved, so the run can be continued with --resume-from-json. Exits with the code of SIGINT.
+    #[structopt(long = "control-file")]
+    control_file: Option<PathBuf>,
+
+    /// Do not use the settings built in for known device models. Without it, a device whose
+    /// vendor and model are known gets the buffer size, direct I/O and read retries recommended
+    /// for it, for whichever of --buffer-size, --open-flags and --read-retry are not given.
+    #[structopt(long = "ignore-device-db")]
+    ignore_device_db: bool,
+
+    /// Test the tool itself against a simulated counterfeit device that really stores only the
+    /// given number of bytes. The device must be a regular file, whose size is the capacity the
+    /// simulated device claims, and is best created sparse, e.g. with `truncate -s 64G fake.img`.
+    /// Offsets beyond the real size wrap around to the start, like on the most common fake flash
+    /// drives, so a run should detect the fake, and --detect-wraparound report its real size.
+    /// Implies --allow-file.
+    #[structopt(long = "simulate-fake", parse(try_from_str = size::parse_size))]
+    simulate_fake: Option<u64>,
+
+    /// Measure how fast each keystream algorithm can be generated on this CPU and exit, without
+    /// touching any device. If generating is slower than the device, the CPU is the bottleneck.
+    #[structopt(long = "bench-rng")]
+    bench_rng: bool,
+
+    /// Print the JSON Schema of the JSON documents this tool writes and reads, the state of
+    /// --emit-seed-json and --verify-json-in and the checkpoints of --checkpoint, and exit.
+    #[structopt(long = "json-schema")]
+    json_schema: bool,
+
+    /// Poll the device temperature during the run, and pause whenever it exceeds this many degrees
+    /// Celsius until it has cooled down, so thermal errors are not mistaken for capacity problems.
+    /// Skipped with a warning if the temperature of the device is not available.
+    #[structopt(long = "thermal-guard")]
+    thermal_guard: Option<f64>,
+
+    /// Pin threads to the given comma-separated list of CPU cores, e.g. `0,2`. The thread doing
+    /// the device I/O is pinned to the first core in the list, and its buffers are allocated on
+    /// that core's NUMA node.
+    #[structopt(long = "pin-cpus", use_delimiter = true)]
+    pin_cpus: Vec<usize>,
+
+    /// While writing, record the sha256 hash of every block of --checksum-block-size bytes written
+    /// to the device in the given file.
+    #[structopt(long = "checksum-out")]
+    checksum_out: Option<PathBuf>,
+
+    /// While reading, verify each block against the hashes in the given file written earlier with
+    /// --checksum-out, rather than regenerating the keystream. A mismatch is reported for the
+    /// whole block, since the position of the differing byte within it is not known.
+    ///
+    /// The seed is not needed when reading with --checksum-in.
+    #[structopt(long = "checksum-in")]
+    checksum_in: Option<PathBuf>,
+
+    /// Size in bytes of each block hashed for --checksum-out, independent of --buffer-size.
+    #[structopt(
+        long = "checksum-block-size",
+        default_value = "1M",
+        parse(try_from_str = size::parse_size)
+    )]
+    checksum_block_size: u64,
+
+    /// Rather than reading the whole device, only verify the blocks of --buffer-size bytes
+    /// containing each of the byte offsets listed in the given file, one per line in decimal or
+    /// with a 0x prefix in hexadecimal, and report the result for each of them. Useful for
+    /// re-checking the offsets recorded with --error-log in an earlier run.
+    ///
+    /// The write phase must have used a buffer size that is a multiple of 4 bytes.
+    #[structopt(long = "offsets-in")]
+    offsets_in: Option<PathBuf>,
+
+    /// After writing, print the seed, device serial number and sizes to stdout as a JSON object,
+    /// so that another station can verify the device with --verify-json-in. Requires writing
+    /// without reading.
+    #[structopt(long = "emit-seed-json")]
+    emit_seed_json: bool,
+
+    /// Only read and verify the device, taking the seed and sizes from a JSON object printed by
+    /// --emit-seed-json. Fails if the device serial number differs from the one recorded.
+    #[structopt(long = "verify-json-in")]
+    verify_json_in: Option<PathBuf>,
+
+    /// Soak test the device by repeating write and read cycles until interrupted with Ctrl-C, then
+    /// print the tally of cycles, failures and bytes transferred. Each cycle uses a different seed
+    /// derived from the given one, printed at the start of the cycle so a failure can be
+    /// re-checked with --raw-seed.
+    #[structopt(long = "soak")]
+    soak: bool,
+
+    /// Test only this region of the device, given as `offset:length:seed` like `1G:512M:my-seed`,
+    /// with its own keystream generated from the seed as with --seed. May be given multiple times
+    /// to test several regions, each with its own seed, in a single pass, leaving the rest of the
+    /// device untouched. Regions must not overlap.
+    #[structopt(long = "region", number_of_values = 1)]
+    regions: Vec<region::Region>,
+
+    /// After the device passes, write a small marker to its first 4 KiB recording when it was
+    /// tested, a hash of the seed and the verified size, so it can later be checked with
+    /// --check-tested. This overwrites the start of the verified data.
+    #[structopt(long = "mark-tested")]
+    mark_tested: bool,
+
+    /// Only read the marker written by --mark-tested from the device, print it and exit.
+    #[structopt(long = "check-tested")]
+    check_tested: bool,
+
+    /// Without a seed, sample the device at evenly spaced regions and report whether its contents
+    /// look like random data throughout, as after a full write by this or a similar tool, or
+    /// contain regions of zeros or other low-entropy data. Fails if any region does not look
+    /// random. Nothing is written.
+    #[structopt(long = "dry-verify")]
+    dry_verify: bool,
+
+    /// Print the size, block and sector sizes and identity of the device to stdout and exit,
+    /// without writing to or reading from it.
+    #[structopt(long = "info")]
+    info: bool,
+
+    /// The state file written by the write stage and read by the verify stage of --stage.
+    #[structopt(long = "state-file")]
+    state_file: Option<PathBuf>,
+
+    /// Fail on anything unexpected, rather than only warning about it. Each condition fails with
+    /// its own exit code: 10 if the device block size is not a power of two, 11 if the device size
+    /// changed between the start and the end of the run, 12 if the device is a device-mapper
+    /// target, 13 if reading ended before the claimed size of the device, 14 if writing ended
+    /// before the claimed size of the device, and 15 if the device is thinly provisioned.
+    ///
+    /// Without --strict, conditions 13 and 14 fail with exit code 1 and the rest are warnings.
+    #[structopt(long = "strict")]
+    strict: bool,
+
+    /// After reading, write a report of what was verified to the given file: the device and its
+    /// serial number, the seed, the claimed, written and verified sizes, the first mismatch,
+    /// start and end times, the tool version and the verdict.
+    #[structopt(long = "report-out")]
+    report_out: Option<PathBuf>,
+
+    /// Sign the report of --report-out with the Ed25519 private key in the given file, so it can
+    /// be shown not to have been changed since. The file holds the 32-byte key as 64 hexadecimal
+    /// characters, which for a key made with `openssl genpkey -algorithm ed25519` are printed by
+    /// `openssl pkey -in key.pem -outform DER | tail -c 32 | xxd -p -c 32`. The signature and
+    /// the public key to check it with are written next to the report, with `.sig` appended to
+    /// its name.
+    #[structopt(long = "sign-key")]
+    sign_key: Option<PathBuf>,
+
+    /// Collect the artifacts of the run in a new subdirectory of the given directory, named by the
+    /// start time and device, e.g. `20210304T050607Z-sdb`: a transcript of the status output in
+    /// `output.log`, `errors.log` as with --error-log, `report.txt` as with --report-out, and
+    /// after writing the seed and sizes in `state`, to verify again later with
+    /// `--stage verify --state-file`. --error-log and --report-out override their paths.
+    #[structopt(long = "output-dir")]
+    output_dir: Option<PathBuf>,
+
+    /// The subdirectory of --output-dir that this run's artifacts are collected in.
+    #[structopt(skip)]
+    run_dir: Option<PathBuf>,
+
+    /// If writing ends before the claimed size of the device, still read back the bytes that were
+    /// written rather than failing right away, to see where reading diverges as well. The run
+    /// still fails afterwards.
+    #[structopt(long = "read-anyway")]
+    read_anyway: bool,
+
+    /// On a thinly provisioned device, whose backing store may legitimately hold less than its
+    /// claimed size, succeed if everything the device accepted before it filled up reads back
+    /// correctly, and report the usable capacity next to the claimed one, rather than failing
+    /// because the claimed size could not be written. Data that does not read back as written
+    /// still fails the run. Refused for devices not detected as thinly provisioned, since a device
+    /// without a backing store that holds less than it claims is counterfeit.
+    ///
+    /// Writes through the page cache only find out that the backing store is full when they are
+    /// flushed, so use --open-flags direct to have the write phase stop where it fills up.
+    #[structopt(long = "compare-claimed-to-usable")]
+    compare_claimed_to_usable: bool,
+
+    /// Allow testing a regular file rather than a block device. The file is overwritten up to its
+    /// current size, but never extended.
+    #[structopt(long = "allow-file")]
+    allow_file: bool,
+
+    /// The device to test. May also be given with --device.
+    ///
+    /// Several devices may be given to verify them all concurrently, each in a process of its own
+    /// with its own seed. Their output is shown prefixed with the name of the device, or with
+    /// --tui as a table of the latest line of each, followed by a summary of the outcome for every
+    /// device. The overwrite is confirmed once for all of them.
+    #[structopt(name = "DEVICE")]
+    device_arg: Vec<PathBuf>,
+
+    /// The device to test. Exclusive with giving the device as a positional argument.
+    #[structopt(long = "device")]
+    device_flag: Option<PathBuf>,
+
+    /// Find the device to test by a stable identifier rather than its path, for media that is
+    /// removed and reinserted between the write and read phases and may come back under another
+    /// path. The identifier is a serial number or WWN as recorded in the state file, or a name
+    /// under /dev/disk/by-id or /dev/disk/by-uuid. Exclusive with giving the device by path.
+    #[structopt(long = "find-by-id")]
+    find_by_id: Option<String>,
+
+    /// The device to test, as given by the positional argument, --device or --find-by-id.
+    #[structopt(skip)]
     device: PathBuf,
 }
 
-fn main() -> Result<()> {
-    _main()
+/// A stage of a run split with --stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Write,
+    Verify,
 }
 
-fn _main() -> Result<()> {
-    let mut args = Args::from_args();
-    if !args.write && !args.read {
-        args.write = true;
-        args.read = true;
-    }
-    let args = args;
+impl std::str::FromStr for Stage {
+    type Err = Error;
 
-    let seed = get_seed(&args).context("Unable to get seed")?;
-    if let Some(input_seed) = &args.seed {
-        eprintln!("Using seed {}", input_seed);
-    } else {
-        eprintln!("Using raw seed {}", hex::encode(seed));
-    };
-    let rng = ChaCha12Rng::from_seed(seed);
+    fn from_str(s: &str) -> Result<Stage> {
+        match s {
+            "write" => Ok(Stage::Write),
+            "verify" => Ok(Stage::Verify),
+            _ => bail!("Unknown stage '{}', expected write or verify", s),
+        }
+    }
+}
 
-    let block_size = get_block_size(&args.device).with_context(|| {
-        format!(
-            "Unable to get block size of device at '{}'",
-            args.device.display()
-        )
-    })?;
+/// How read data is compared with what was written, chosen with --compare-mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareMode {
+    FailFast,
+    BitErrorRate,
+}
 
-    let disk_size = get_disk_size(&args.device).with_context(|| {
-        format!(
-            "Unable to get disk size of device at '{}'",
-            args.device.display()
-        )
-    })?;
+impl std::str::FromStr for CompareMode {
+    type Err = Error;
 
-    if args.write {
-        eprintln!("Will write pseudo-random stream of data to device '{}'. This will overwrite all data on the device. Are you sure you want to continue? (y/N)", args.device.display());
-        let mut response = String::new();
-        std::io::stdin()
-            .read_line(&mut response)
-            .context("Error reading from stdin")?;
-        let response = response.trim();
-        if response != "y" && response != "Y" {
-            bail!("Did not accept overwriting data on device. Safely exiting . . .");
+    fn from_str(s: &str) -> Result<CompareMode> {
+        match s {
+            "fail-fast" => Ok(CompareMode::FailFast),
+            "ber" => Ok(CompareMode::BitErrorRate),
+            _ => bail!("Unknown compare mode '{}', expected fail-fast or ber", s),
         }
     }
+}
 
-    if args.write {
-        let written_bytes = write_device(&args, rng.clone(), block_size, disk_size)
-            .with_context(|| format!("Error writing to device '{}'", args.device.display()))?;
-        if written_bytes != disk_size {
-            bail!(
-                "Wrote {} bytes, but expected disk size to be {} bytes",
-                written_bytes,
-                disk_size
-            );
-        }
-    }
+/// Page cache behavior for reads, chosen with --cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CacheMode {
+    Warm,
+    Cold,
+}
 
-    if args.read {
-        let read_bytes = read_device(&args, rng, block_size, disk_size)
-            .with_context(|| format!("Error reading from device '{}'", args.device.display()))?;
+impl std::str::FromStr for CacheMode {
+    type Err = Error;
 
-        if read_bytes != disk_size {
-            bail!(
-                "Read {} bytes, but expected disk size to be {} bytes",
-                read_bytes,
-                disk_size
-            );
+    fn from_str(s: &str) -> Result<CacheMode> {
+        match s {
+            "warm" => Ok(CacheMode::Warm),
+            "cold" => Ok(CacheMode::Cold),
+            _ => bail!("Unknown cache mode '{}', expected warm or cold", s),
         }
     }
+}
 
-    Ok(())
+/// How written data is flushed to the device, chosen with --sync-mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncMode {
+    Fsync,
+    Fdatasync,
+    None,
 }
 
-fn get_seed(args: &Args) -> Result<[u8; 32]> {
-    use sha2::Digest;
+impl std::str::FromStr for SyncMode {
+    type Err = Error;
 
-    match (&args.seed, &args.raw_seed) {
-        (Some(_), Some(_)) => {
-            bail!("--seed and --raw-seed are mutually exclusive, please specify only one of them");
-        }
-        (Some(seed), None) => {
-            let hash = sha2::Sha256::digest(seed.as_bytes());
-            Ok(hash.try_into()?)
+    fn from_str(s: &str) -> Result<SyncMode> {
+        match s {
+            "fsync" => Ok(SyncMode::Fsync),
+            "fdatasync" => Ok(SyncMode::Fdatasync),
+            "none" => Ok(SyncMode::None),
+            _ => bail!(
+                "Unknown sync mode '{}', expected fsync, fdatasync or none",
+                s
+            ),
         }
-        (None, Some(raw_seed)) => {
-            if raw_seed.len() != 64 {
-                bail!(
-                    "--raw-seed has invalid length {}, expected 64 characters",
-                    raw_seed.len()
-                );
-            }
+    }
+}
 
-            let mut buf = [0u8; 32];
-            hex::decode_to_slice(raw_seed.as_bytes(), &mut buf)?;
-            Ok(buf)
-        }
-        (None, None) => {
-            if !args.write && args.read {
-                bail!("Cannot read but not write when using random seed.");
+impl SyncMode {
+    /// Flush what was written to `file` to the device.
+    fn sync(self, file: &File) -> std::io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        match self {
+            SyncMode::Fsync => file.sync_all(),
+            SyncMode::Fdatasync => {
+                if unsafe { libc::fdatasync(file.as_raw_fd()) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
             }
-            Ok(rand::random())
+            SyncMode::None => Ok(()),
         }
     }
 }
 
-fn get_block_size(path: &Path) -> Result<u64> {
-    use std::os::unix::fs::FileTypeExt;
-    use std::os::unix::fs::MetadataExt;
-
-    let metadata = std::fs::metadata(path)?;
-
-    if !metadata.file_type().is_block_device() {
-        bail!("Not a block device");
+fn main() {
+    let result = _main();
+    sd_notify::stopping(&match &result {
+        Ok(()) => "Finished".to_string(),
+        Err(e) => format!("Error: {}", e),
+    });
+    if let Err(e) = result {
+        emit!("{} {:?}", color::paint("Error:", Color::Red), e);
+        let exit_code = match (
+            e.downcast_ref::<strict::Violation>(),
+            e.downcast_ref::<interrupt::Interrupted>(),
+        ) {
+            (Some(violation), _) => violation.0.exit_code(),
+            (None, Some(interrupted)) => interrupted.exit_code(),
+            (None, None) => 1,
+        };
+        std::process::exit(exit_code);
     }
+}
 
-    let block_size = metadata.blksize();
+fn _main() -> Result<()> {
+    // Leaked since clap needs it for the lifetime of the program anyway.
+    let long_version: &'static str = Box::leak(long_version().into_boxed_str());
+    let mut args = Args::from_clap(&Args::clap().long_version(long_version).get_matches());
+    color::init(args.color);
+    messages::init(args.lang.unwrap_or_else(Locale::from_env));
+    output::init(args.timestamps, args.syslog);
+    if args.sd_notify {
+        sd_notify::init();
+    }
+    if args.bench_rng {
+        bench_rng();
+        return Ok(());
+    }
+    if args.json_schema {
+        println!("{}", schema::JSON_SCHEMA);
+        return Ok(());
+    }
+    if let Some(stage) = args.stage {
+        if args.write || args.read {
+            bail!("--stage cannot be combined with --write or --read");
+        }
+        if args.state_file.is_none() {
+            bail!("--stage requires --state-file");
+        }
+        args.write = stage == Stage::Write;
+        args.read = stage == Stage::Verify;
+    }
+    let resume = match &args.resume_from_json {
+        Some(path) => {
+            if args.write || args.read || args.stage.is_some() || args.verify_json_in.is_some() {
+                bail!("The phase to continue is taken from the checkpoint, so --resume-from-json cannot be combined with --write, --read, --stage or --verify-json-in");
+            }
+            let checkpoint = Checkpoint::read(path)?;
+            args.write = checkpoint.phase == Phase::Write;
+            args.read = true;
+            Some(checkpoint)
+        }
+        None => None,
+    };
+    if args.verify_json_in.is_some() {
+        if args.write || args.stage.is_some() {
+            bail!("--verify-json-in only reads from the device, and cannot be combined with --write or --stage");
+        }
+        args.read = true;
+    }
+    if !args.write && !args.read {
+        args.write = true;
+        args.read = true;
+    }
+    if args.write {
+        args.yes = Policy::load(Path::new(policy::SITE_POLICY_PATH))?
+            .assume_yes(args.yes, args.overwrite_confirmation_from.is_some())?;
+    }
+    if args.device_arg.len() > 1 {
+        return verify_multiple(&args);
+    }
+    args.device = match (
+        args.device_arg.pop(),
+        args.device_flag.take(),
+        args.find_by_id.take(),
+    ) {
+        (Some(device), None, None) | (None, Some(device), None) => device,
+        (None, None, Some(id)) => {
+            let device = sysfs::find_by_id(&id)?;
+            emit!("Found device {} with identifier '{}'", device.display(), id);
+            device
+        }
+        (None, None, None) => {
+            bail!("No device given, please specify the device to test");
+        }
+        _ => {
+            bail!("The device was given more than once with the positional argument, --device or --find-by-id, please specify only one of them");
+        }
+    };
+    if args.compare_mode == CompareMode::BitErrorRate
+        || args.compare_previous
+        || args.bidirectional
+        || args.repair_remap.is_some()
+    {
+        // Counting every bit error, or finding every bad region, means reading the whole device.
+        args.continue_on_error = true;
+    }
+    if args.simulate_fake.is_some() {
+        args.allow_file = true;
+    }
+    if let Some(dir) = &args.output_dir {
+        let run_dir = create_run_dir(dir, &run_dir_name(&args.device, SystemTime::now()))?;
+        output::log_to_file(&run_dir.join("output.log"))?;
+        emit!(
+            "Collecting the artifacts of this run in '{}'",
+            run_dir.display()
+        );
+        args.error_log.get_or_insert(run_dir.join("errors.log"));
+        if args.read && !args.soak {
+            args.report_out.get_or_insert(run_dir.join("report.txt"));
+        }
+        args.run_dir = Some(run_dir);
+    }
+    if !args.ignore_device_db {
+        if let Some(known) = device_db::lookup(&args.device) {
+            apply_known_device(&mut args, known);
+        }
+    }
+    let args = args;
 
-    eprintln!("Disk block size is {} bytes", block_size);
+    retry_open(&args, || check_device_path(&args.device, args.allow_file))?;
 
-    Ok(block_size)
-}
+    if let Some(port) = args.metrics_port {
+        metrics::init(port, &args.device)?;
+    }
 
-fn get_disk_size(path: &Path) -> Result<u64> {
-    let mut d = File::open(path)?;
-    d.seek(SeekFrom::End(0))?;
-    let size = d.stream_position()?;
-    eprintln!("Disk size is {} bytes", size);
-    Ok(size)
-}
+    if args.check_tested {
+        return check_tested(&args.device);
+    }
 
-fn write_device(args: &Args, mut rng: ChaCha12Rng, block_size: u64, disk_size: u64) -> Result<u64> {
-    let mut d = File::create(&args.device)?;
-    let mut buf = vec![0; block_size as usize];
+    if args.info {
+        return print_info(&args.device);
+    }
 
-    let tty = std::io::stderr().is_tty();
+    if args.dry_verify {
+        return dry_verify(&args);
+    }
 
-    eprintln!("Writing to device {}", args.device.display());
-    if tty {
-        eprintln!();
+    if args.mark_tested && !args.read {
+        bail!("--mark-tested requires reading from the device");
     }
 
-    let mut written_bytes: usize = 0;
-    let mut last_update = Instant::now();
-    let mut last_update_bytes = 0;
-    loop {
-        if tty {
-            let duration = last_update.elapsed();
-            if duration > UPDATE_FREQUENCY {
-                let newly_written_bytes = written_bytes - last_update_bytes;
-                let rate = (newly_written_bytes as f64) / duration.as_secs_f64();
-                let completion = (written_bytes as f64) / (disk_size as f64);
-                std::io::stderr()
-                    .execute(crossterm::cursor::MoveToPreviousLine(1))
-                    .context("Error moving cursor")?;
-                eprintln!(
-                    "Written {} bytes total. {:.0} bytes/second. {:.4} complete.",
-                    written_bytes, rate, completion
-                );
-                last_update = Instant::now();
-                last_update_bytes = written_bytes;
-            }
+    // Loaded up front, so a bad key fails the run before it starts rather than after it.
+    let signing_key = match &args.sign_key {
+        Some(_) if args.report_out.is_none() => {
+            bail!("--sign-key signs the report, so requires --report-out or --output-dir")
         }
+        Some(path) => Some(read_signing_key(path)?),
+        None => None,
+    };
 
-        rng.try_fill_bytes(&mut buf)
-            .context("Error generating random bytes")?;
+    if args.progress_every == Some(0) {
+        bail!("--progress-every must be greater than 0");
+    }
 
-        let mut to_write = buf.as_slice();
-        while !to_write.is_empty() {
-            match d.write(to_write) {
-                Ok(0) => {
-                    bail!(
-                        "Could not write any data to device. Had successfully written {} bytes.",
-                        written_bytes
-                    );
-                }
-                Ok(n) => {
-                    written_bytes += n;
-                    to_write = &to_write[n..];
-                }
-                Err(e) => {
-                    if let Some(error_code) = e.raw_os_error() {
-                        if error_code == 28 {
-                            eprintln!("Successfully wrote {} bytes", written_bytes);
-                            d.sync_all().context("Error while trying to call fsync")?;
-                            return written_bytes
-                                .try_into()
-                                .context("usize could not be converted to u64");
-                        }
-                    }
+    if args.trim_first && !args.write {
+        bail!("--trim-first can only be used when writing to the device");
+    }
 
-                    return Err(Error::from(e).context(format!(
-                        "Encountered error writing to device. Had successfully written {} bytes.",
-                        written_bytes
-                    )));
-                }
-            }
-        }
+    if args.estimate_write && !(args.estimate && args.write) {
+        bail!("--estimate-write requires --estimate and writing to the device");
     }
-}
 
-fn read_device(args: &Args, mut rng: ChaCha12Rng, block_size: u64, disk_size: u64) -> Result<u64> {
-    let mut d = File::open(&args.device)?;
-    let mut device_buf = vec![0; block_size as usize];
-    let mut rng_buf = vec![0; block_size as usize];
+    if args.estimate && (args.trim_first || args.auto_tune) {
+        bail!("--estimate cannot be combined with --trim-first or --auto-tune");
+    }
 
-    let tty = std::io::stderr().is_tty();
+    if args.auto_tune && (!args.write || args.buffer_size.is_some()) {
+        bail!(
+            "--auto-tune requires writing to the device, and cannot be combined with --buffer-size"
+        );
+    }
 
-    eprintln!("Reading from device {}", args.device.display());
-    if tty {
-        eprintln!();
+    if args.checksum_out.is_some() && !args.write {
+        bail!("--checksum-out requires writing to the device");
     }
 
-    let mut read_bytes: usize = 0;
-    let mut last_update = Instant::now();
-    let mut last_update_bytes = 0;
-    loop {
-        if tty {
-            let duration = last_update.elapsed();
-            if duration > UPDATE_FREQUENCY {
-                let newly_read_bytes = read_bytes - last_update_bytes;
-                let rate = (newly_read_bytes as f64) / duration.as_secs_f64();
-                let completion = (read_bytes as f64) / (disk_size as f64);
-                std::io::stderr()
-                    .execute(crossterm::cursor::MoveToPreviousLine(1))
-                    .context("Error moving cursor")?;
-                eprintln!(
-                    "Read {} bytes total. {:.0} bytes/second. {:.4} complete.",
-                    read_bytes, rate, completion
+    if let Some(real_size) = args.simulate_fake {
+        if !std::fs::metadata(&args.device)?.is_file() {
+            bail!("--simulate-fake requires the device to be a regular file, whose size is the size the simulated device claims");
+        }
+        if real_size == 0 || !real_size.is_multiple_of(512) {
+            bail!("--simulate-fake must be a positive multiple of 512 bytes");
+        }
+        if args.write_verify
+            || args.bidirectional
+            || args.probe_capacity
+            || args.cache_bust_distance.is_some()
+            || args.verify_parallel_readers.is_some()
+            || args.offsets_in.is_some()
+            || args.pause_on_mismatch
+            || args.mark_tested
+        {
+            bail!("--simulate-fake only simulates the fake for the write and read phases and the wraparound checks, and cannot be combined with --write-verify, --bidirectional, --probe-capacity, --cache-bust-distance, --verify-parallel-readers, --offsets-in, --pause-on-mismatch or --mark-tested");
+        }
+        emit!(
+            "Simulating a counterfeit device that claims {} bytes but only stores {} bytes, wrapping around to the start beyond them",
+            std::fs::metadata(&args.device)?.len(),
+            real_size
+        );
+    }
+
+    if args.io_uring {
+        if !cfg!(target_os = "linux") {
+            bail!("--io-uring is only supported on Linux");
+        }
+        if args.simulate_fake.is_some()
+            || args.write_verify
+            || args.checksum_out.is_some()
+            || args.bidirectional
+            || args.probe_capacity
+            || args.cache_bust_distance.is_some()
+            || args.verify_parallel_readers.is_some()
+            || args.offsets_in.is_some()
+            || !args.regions.is_empty()
+        {
+            bail!("--io-uring only applies to the write and read phases, and cannot be combined with --simulate-fake, --write-verify, --checksum-out, --bidirectional, --probe-capacity, --cache-bust-distance, --verify-parallel-readers, --offsets-in or --region");
+        }
+        if !(1..=4096).contains(&args.io_uring_depth) {
+            bail!("--io-uring-depth must be from 1 to 4096");
+        }
+    }
+
+    if args.compare_mode == CompareMode::BitErrorRate
+        && (!args.read || args.checksum_in.is_some() || args.verify_parallel_readers.is_some())
+    {
+        bail!("--compare-mode ber requires reading from the device, and cannot be combined with --checksum-in or --verify-parallel-readers");
+    }
+
+    if args.checksum_in.is_some()
+        && (!args.read || args.pause_on_mismatch || args.continue_on_error)
+    {
+        bail!("--checksum-in requires reading from the device, and cannot be combined with --pause-on-mismatch or --continue-on-error");
+    }
+
+    if let Some(readers) = args.verify_parallel_readers {
+        if readers == 0 {
+            bail!("--verify-parallel-readers must be at least 1");
+        }
+        if !args.read
+            || args.pause_on_mismatch
+            || args.continue_on_error
+            || args.checksum_in.is_some()
+            || args.soak
+        {
+            bail!("--verify-parallel-readers requires reading from the device, and cannot be combined with --pause-on-mismatch, --continue-on-error, --checksum-in or --soak");
+        }
+    }
+
+    if args.continue_on_error && !args.read {
+        bail!("--continue-on-error requires reading from the device");
+    }
+
+    if args.abort_after_errors.is_some() && !args.continue_on_error {
+        bail!("--abort-after-errors requires --continue-on-error");
+    }
+
+    if let Some(threshold) = args.fail_fast_threshold {
+        if !args.continue_on_error {
+            bail!("--fail-fast-threshold requires --continue-on-error, since reading otherwise stops at the first mismatch anyway");
+        }
+        if threshold == 0 {
+            bail!("--fail-fast-threshold must be greater than 0");
+        }
+    }
+
+    if args.compare_previous {
+        if !args.read || args.soak || args.offsets_in.is_some() {
+            bail!("--compare-previous requires reading the whole device, and cannot be combined with --soak or --offsets-in");
+        }
+        if sysfs::device_serial(&args.device)?.is_none() {
+            bail!("--compare-previous requires a device with a serial number to identify it by");
+        }
+    } else if args.history_dir.is_some() {
+        bail!("--history-dir requires --compare-previous");
+    }
+
+    if args.repair_remap.is_some() {
+        if !args.read || args.soak || args.offsets_in.is_some() {
+            bail!("--repair-remap requires reading the whole device, and cannot be combined with --soak or --offsets-in");
+        }
+        // The block sizes ext2, ext3 and ext4 support.
+        if !args.remap_block_size.is_power_of_two()
+            || !(1024..=65536).contains(&args.remap_block_size)
+        {
+            bail!("--remap-block-size must be a power of two from 1024 to 65536");
+        }
+    }
+
+    if args.bidirectional
+        && (!args.read
+            || args.soak
+            || args.offsets_in.is_some()
+            || args.checksum_in.is_some()
+            || args.verify_parallel_readers.is_some()
+            || args.checkpoint.is_some()
+            || args.resume_from_json.is_some())
+    {
+        bail!("--bidirectional requires reading the whole device, and cannot be combined with --soak, --offsets-in, --checksum-in, --verify-parallel-readers or checkpoints");
+    }
+
+    if args.offsets_in.is_some() && (args.write || args.report_out.is_some()) {
+        bail!("--offsets-in requires --read, and cannot be combined with writing to the device or --report-out");
+    }
+
+    if args.read_anyway && !(args.write && args.read) {
+        bail!("--read-anyway requires both writing to and reading from the device");
+    }
+
+    if args.compare_claimed_to_usable {
+        if !(args.write && args.read)
+            || args.read_anyway
+            || args.soak
+            || args.cache_bust_distance.is_some()
+            || args.probe_capacity
+        {
+            bail!("--compare-claimed-to-usable requires both writing to and reading from the device, and cannot be combined with --read-anyway, --soak, --cache-bust-distance or --probe-capacity");
+        }
+        if sysfs::thin_provisioning(&args.device).is_none() {
+            bail!("--compare-claimed-to-usable only applies to thinly provisioned devices, and '{}' is not detected as one. A device without a backing store that holds less than it claims is counterfeit, so leave out --compare-claimed-to-usable to verify its claimed size", args.device.display());
+        }
+    }
+
+    if args.emit_seed_json && (!args.write || args.read) {
+        bail!("--emit-seed-json requires writing to the device without reading");
+    }
+
+    if args.soak
+        && (!(args.write && args.read)
+            || args.stage.is_some()
+            || args.tui
+            || args.estimate
+            || args.report_out.is_some())
+    {
+        bail!("--soak requires both writing and reading, and cannot be combined with --stage, --tui, --estimate or --report-out");
+    }
+
+    if args.cache_bust_distance.is_some()
+        && (!(args.write && args.read)
+            || args.soak
+            || args.stage.is_some()
+            || args.estimate
+            || args.checkpoint.is_some()
+            || args.resume_from_json.is_some()
+            || args.verify_parallel_readers.is_some()
+            || args.checksum_in.is_some()
+            || args.checksum_out.is_some())
+    {
+        bail!("--cache-bust-distance requires both writing and reading, and cannot be combined with --soak, --stage, --estimate, checkpoints, --verify-parallel-readers or checksums");
+    }
+
+    if args.control_file.is_some()
+        && (args.soak
+            || args.cache_bust_distance.is_some()
+            || args.probe_capacity
+            || !args.regions.is_empty())
+    {
+        bail!("--control-file cannot be used with --soak, --cache-bust-distance, --probe-capacity or --region");
+    }
+
+    if args.probe_capacity
+        && (!(args.write && args.read)
+            || args.soak
+            || args.stage.is_some()
+            || args.estimate
+            || args.cache_bust_distance.is_some()
+            || args.checkpoint.is_some()
+            || args.resume_from_json.is_some()
+            || args.report_out.is_some())
+    {
+        bail!("--probe-capacity writes and reads its own blocks, and cannot be combined with --write, --read, --stage, --soak, --estimate, --cache-bust-distance, checkpoints or --report-out");
+    }
+
+    if args.write_verify && !args.write {
+        bail!("--write-verify requires writing to the device");
+    }
+
+    if args.report_out.is_some() && !args.read {
+        bail!("--report-out requires reading from the device");
+    }
+
+    if args.tui && (args.pause_on_mismatch || !std::io::stderr().is_tty()) {
+        bail!("--tui requires stderr to be a TTY, and cannot be combined with --pause-on-mismatch");
+    }
+
+    if args.pause_on_mismatch && !(std::io::stdin().is_tty() && std::io::stderr().is_tty()) {
+        bail!("--pause-on-mismatch requires stdin and stderr to be a TTY");
+    }
+
+    let mut error_log = ErrorLog::open(args.error_log.as_deref())?;
+
+    let state = match (args.stage, &args.state_file, &args.verify_json_in) {
+        _ if resume.is_some() => resume.as_ref().map(|checkpoint| checkpoint.state.clone()),
+        (Some(Stage::Verify), Some(path), _) => Some(RunState::read(path)?),
+        (_, _, Some(path)) => Some(RunState::read_json(path)?),
+        _ => None,
+    };
+
+    if !args.regions.is_empty() {
+        if args.seed.is_some()
+            || args.raw_seed.is_some()
+            || args.master_key.is_some()
+            || args.seed_url.is_some()
+            || args.keystream.is_some()
+            || state.is_some()
+        {
+            bail!("Each region has its own seed, so --region cannot be used with --seed, --raw-seed, --master-key, --seed-url, --keystream or saved state");
+        }
+        if args.soak || args.offsets_in.is_some() || args.verify_parallel_readers.is_some() {
+            bail!("--region cannot be used with --soak, --offsets-in or --verify-parallel-readers");
+        }
+        return test_regions(&args, &mut error_log);
+    }
+
+    let spec = if let Some(state) = &state {
+        if args.seed.is_some()
+            || args.raw_seed.is_some()
+            || args.master_key.is_some()
+            || args.seed_url.is_some()
+            || args.keystream.is_some()
+        {
+            bail!("The seed is taken from the saved state, so --seed, --raw-seed, --master-key, --seed-url and --keystream cannot be given");
+        }
+        if args.stream_offset.is_some() {
+            bail!("The stream offset is taken from the saved state, so --stream-offset cannot be given");
+        }
+        emit!(
+            "Using seed from saved state written for device '{}'",
+            state.device.display()
+        );
+        state.keystream.clone()
+    } else if let Some(path) = &args.keystream {
+        if args.seed.is_some()
+            || args.raw_seed.is_some()
+            || args.master_key.is_some()
+            || args.seed_url.is_some()
+        {
+            bail!("--keystream is mutually exclusive with --seed, --raw-seed, --master-key and --seed-url, please specify only one of them");
+        }
+        if args.stream_offset.is_some() {
+            bail!("--stream-offset cannot be used with --keystream, set the counter in the descriptor instead");
+        }
+        let spec = KeystreamSpec::read(path)?;
+        emit!(
+            "Using {} keystream with nonce {} starting at block counter {}",
+            spec.algorithm,
+            spec.nonce,
+            spec.counter
+        );
+        spec
+    } else {
+        let seed = get_seed(&args).context("Unable to get seed")?;
+        if let Some(input_seed) = &args.seed {
+            emit!("Using seed {}", input_seed);
+        } else {
+            emit!("Using raw seed {}", hex::encode(seed));
+        };
+        let stream_offset = args.stream_offset.unwrap_or(0);
+        if stream_offset % 64 != 0 {
+            bail!(
+                "Stream offset {} is not a multiple of the ChaCha block size of 64 bytes",
+                stream_offset
+            );
+        }
+        if stream_offset != 0 {
+            emit!("Starting {} bytes into the keystream", stream_offset);
+        }
+        KeystreamSpec {
+            algorithm: DEFAULT_ALGORITHM,
+            key: seed,
+            nonce: 0,
+            counter: stream_offset / 64,
+        }
+    };
+    let rng = spec.keystream();
+
+    let block_size = get_block_size(&args.device).with_context(|| {
+        format!(
+            "Unable to get block size of device at '{}'",
+            args.device.display()
+        )
+    })?;
+
+    let logical_sector_size = get_logical_sector_size(&args.device).with_context(|| {
+        format!(
+            "Unable to get sector sizes of device at '{}'",
+            args.device.display()
+        )
+    })?;
+
+    let disk_size = retry_open(&args, || get_disk_size(&args.device)).with_context(|| {
+        format!(
+            "Unable to get disk size of device at '{}'",
+            args.device.display()
+        )
+    })?;
+
+    if args.mark_tested && disk_size < marker::MARKER_SIZE as u64 {
+        bail!(
+            "--mark-tested requires a device of at least {} bytes to hold the marker",
+            marker::MARKER_SIZE
+        );
+    }
+
+    if !block_size.is_power_of_two() {
+        warn_or_fail(
+            &args,
+            Condition::NonPowerOfTwoBlockSize,
+            format!("Device block size {} is not a power of two", block_size),
+        )?;
+    }
+
+    if sysfs::is_device_mapper(&args.device) {
+        warn_or_fail(
+            &args,
+            Condition::DeviceMapper,
+            "Device is a device-mapper target, so the result reflects the mapping rather than the physical media".to_string(),
+        )?;
+    }
+
+    if let Some(backing_devices) = sysfs::dm_crypt_backing_devices(&args.device) {
+        let backing_devices: Vec<String> = backing_devices
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        emit!("NOTE: Device is a dm-crypt mapping, so data is encrypted on its way to {} and throughput reflects the encryption overhead. Capacity is still verified, but for a pure capacity check test the underlying device directly.", backing_devices.join(", "));
+    }
+
+    if let Some(provisioning) = sysfs::thin_provisioning(&args.device) {
+        warn_or_fail(
+            &args,
+            Condition::ThinProvisioned,
+            format!("Device is thinly provisioned ({}), so its size is virtual and the result reflects the backing store rather than the capacity of any physical media", provisioning),
+        )?;
+    }
+
+    if let Some(state) = &state {
+        if state.disk_size != disk_size {
+            bail!(
+                "Device reports a size of {} bytes, but was {} bytes when written",
+                disk_size,
+                state.disk_size
+            );
+        }
+        if let (Some(expected), Ok(Some(serial))) =
+            (&state.device_serial, sysfs::device_serial(&args.device))
+        {
+            if *expected != serial {
+                bail!(
+                    "Device has serial number '{}', but was written as a device with serial number '{}'",
+                    serial,
+                    expected
+                );
+            }
+        }
+    }
+
+    if args.emit_stream {
+        return emit_stream(rng, disk_size);
+    }
+
+    if args.write && (!args.estimate || args.estimate_write) {
+        check_overwrite_confirmed(&args)?;
+    }
+
+    if args.trim_first {
+        match trim_device(&args.device, disk_size) {
+            Ok(()) => emit!("Successfully trimmed {} bytes", disk_size),
+            Err(e) => emit!("Unable to trim device, continuing without trim: {:#}", e),
+        }
+    }
+
+    let buffer_size = if args.auto_tune {
+        let buffer_size = auto_tune_buffer_size(&args.device, args.open_flags, disk_size)
+            .context("Error benchmarking buffer sizes")?;
+        emit!("Using fastest buffer size of {} bytes", buffer_size);
+        buffer_size
+    } else if let (Some(state), None) = (&state, args.buffer_size) {
+        state.buffer_size
+    } else {
+        args.buffer_size.unwrap_or(block_size)
+    };
+    let (buffer_size, alignment) = io_sizes(
+        &args,
+        buffer_size,
+        block_size,
+        logical_sector_size,
+        disk_size,
+    )?;
+
+    if args.estimate {
+        return print_estimate(&args, buffer_size, disk_size);
+    }
+
+    if let Some(path) = &args.offsets_in {
+        return verify_offsets(&args, &spec, path, buffer_size, disk_size, &mut error_log);
+    }
+
+    if let Some(&cpu) = args.pin_cpus.first() {
+        affinity::pin_current_thread(cpu)?;
+        emit!("Pinned I/O thread to CPU {}", cpu);
+    }
+
+    let checkpoint_path = args.checkpoint.as_ref().or(args.resume_from_json.as_ref());
+    if checkpoint_path.is_some()
+        && (args.soak
+            || args.verify_parallel_readers.is_some()
+            || args.checksum_in.is_some()
+            || args.checksum_out.is_some())
+    {
+        bail!("Checkpoints cannot be used with --soak, --verify-parallel-readers, --checksum-in or --checksum-out");
+    }
+    // Where to continue the write and read phases from.
+    let (write_start, read_start) = match &resume {
+        Some(checkpoint) if checkpoint.offset > disk_size => bail!(
+            "Checkpoint offset {} is beyond the end of the device",
+            checkpoint.offset
+        ),
+        Some(checkpoint) => {
+            emit!(
+                "Continuing the {} phase from offset {}",
+                checkpoint.phase,
+                checkpoint.offset
+            );
+            match checkpoint.phase {
+                Phase::Write => (checkpoint.offset, 0),
+                Phase::Read => (0, checkpoint.offset),
+            }
+        }
+        None => (0, 0),
+    };
+
+    if args.probe_capacity {
+        return probe_capacity(&args, &spec, block_size, disk_size);
+    }
+
+    if let Some(distance) = args.cache_bust_distance {
+        return interleaved_write_read(
+            &args,
+            &spec,
+            buffer_size,
+            distance,
+            disk_size,
+            &mut error_log,
+        );
+    }
+
+    if args.soak {
+        return soak(
+            &args,
+            &spec,
+            buffer_size,
+            alignment,
+            disk_size,
+            &mut error_log,
+        );
+    }
+
+    let started_at = report::unix_time();
+    let mut summary = Summary {
+        claimed_bytes: disk_size,
+        usable_bytes: None,
+        verified_bytes: 0,
+        write: None,
+        read: None,
+    };
+
+    // The number of bytes to read back, and the error to fail with after reading if writing ended
+    // early with --read-anyway.
+    let mut read_size = disk_size;
+    let mut short_write = None;
+    // How far writing got, if it ran, to check that reading back gets exactly as far.
+    let mut written_total = None;
+
+    interrupt::init()?;
+    if let Some(path) = &args.control_file {
+        interrupt::watch_control_file(path)?;
+    }
+    if let Some(path) = checkpoint_path {
+        let state = RunState {
+            keystream: spec.clone(),
+            device: args.device.clone(),
+            device_serial: sysfs::device_serial(&args.device).unwrap_or(None),
+            disk_size,
+            block_size,
+            buffer_size,
+            written_bytes: state.as_ref().map_or(0, |state| state.written_bytes),
+        };
+        checkpoint::init(path, state);
+    }
+
+    let mut terminal = if args.tui {
+        Some(tui::Terminal::enter()?)
+    } else {
+        None
+    };
+
+    if args.write {
+        let start = Instant::now();
+        let started = SystemTime::now();
+        let WriteResult {
+            written_bytes,
+            write_sizes,
+            speed_cliff,
+            verify_duration,
+        } = write_device(
+            &args,
+            &mut *data_source(&args, &spec, buffer_size),
+            buffer_size,
+            write_start,
+            disk_size,
+            &mut error_log,
+        )
+        .with_context(|| format!("Error writing to device '{}'", args.device.display()))?;
+        checkpoint::save(Phase::Read, 0)?;
+        if args.verbose > 0 || !write_sizes.is_uniform() {
+            write_sizes.print("write");
+        }
+        if let Some(cliff) = speed_cliff {
+            emit!(
+                "Write rate dropped from {:.0} to {:.0} bytes/second after {} bytes, which suggests a write cache (such as the SLC cache of flash) of about {} bytes",
+                cliff.fast_rate,
+                cliff.slow_rate,
+                cliff.offset,
+                cliff.offset
+            );
+            if !args.open_flags.is_direct() {
+                emit!("NOTE: Writes go through the page cache, which can also absorb the first writes at full speed. Use --open-flags direct to measure the device alone.");
+            }
+        }
+        written_total = Some(written_bytes);
+        summary.write = Some(PhaseStats {
+            bytes: written_bytes - write_start,
+            duration: start.elapsed(),
+            started,
+            finished: SystemTime::now(),
+        });
+        if let Some(verify_duration) = verify_duration {
+            let duration = start.elapsed();
+            emit!(
+                "Read back every buffer after writing it. Syncing and reading back took {:.1} of {:.1} seconds, {:.0} bytes/second would have been written without it",
+                verify_duration.as_secs_f64(),
+                duration.as_secs_f64(),
+                (written_bytes - write_start) as f64
+                    / (duration - verify_duration).as_secs_f64()
+            );
+        }
+        if args.check_no_wrap {
+            if let Some(position) = check_no_wrap(&args, &spec, block_size, disk_size)
+                .context("Error re-reading the first block")?
+            {
+                drop(terminal.take());
+                emit!(
+                    "{} The first block of the device was overwritten while writing the rest of it, from position {}. The device is smaller than it claims and wraps writes around to the start",
+                    color::paint("FAIL:", Color::Red),
+                    position
+                );
+                if args.detect_wraparound {
+                    detect_wraparound(&args, &spec, position, disk_size)
+                        .context("Error while checking for wraparound")?;
+                }
+                bail!(
+                    "The start of the device was overwritten at position {}",
+                    position
+                );
+            }
+            emit!("The first block of the device is intact after writing");
+        }
+        if !args.read {
+            drop(terminal.take());
+            if let Some(write) = &summary.write {
+                write.print_times("Write");
+            }
+        }
+        if !args.read && !check_disk_size_unchanged(&args.device, disk_size) {
+            warn_or_fail(
+                &args,
+                Condition::DiskSizeChanged,
+                "Device size changed".to_string(),
+            )?;
+        }
+        if written_bytes != disk_size && args.compare_claimed_to_usable {
+            emit!(
+                "Thinly provisioned device filled up after {} of its claimed {} bytes, verifying those as its usable capacity",
+                written_bytes,
+                disk_size
+            );
+            read_size = written_bytes;
+        } else if written_bytes != disk_size {
+            let message = format!(
+                "Wrote {} bytes, but expected disk size to be {} bytes",
+                written_bytes, disk_size
+            );
+            if !args.read_anyway {
+                return Err(condition_error(&args, Condition::ShortWrite, message));
+            }
+            emit!(
+                "{}. Reading back the written bytes anyway because of --read-anyway",
+                message
+            );
+            read_size = written_bytes;
+            short_write = Some(condition_error(&args, Condition::ShortWrite, message));
+        }
+
+        let state = RunState {
+            keystream: spec.clone(),
+            device: args.device.clone(),
+            device_serial: sysfs::device_serial(&args.device).unwrap_or(None),
+            disk_size,
+            block_size,
+            buffer_size,
+            written_bytes,
+        };
+        if let (Some(Stage::Write), Some(path)) = (args.stage, &args.state_file) {
+            state
+                .write(path)
+                .with_context(|| format!("Error saving state to '{}'", path.display()))?;
+            emit!("Saved state to '{}'. Now power-cycle or replug the device, then run the verify stage with --stage verify --state-file '{}'", path.display(), path.display());
+            if let Some(serial) = &state.device_serial {
+                emit!(
+                    "If the device comes back under another path, find it with --find-by-id '{}'",
+                    serial
+                );
+            }
+        }
+        if args.emit_seed_json {
+            println!("{}", state.to_json());
+        }
+        if let Some(dir) = &args.run_dir {
+            let path = dir.join("state");
+            state
+                .write(&path)
+                .with_context(|| format!("Error saving state to '{}'", path.display()))?;
+        }
+    }
+
+    if args.read {
+        let start = Instant::now();
+        let started = SystemTime::now();
+        let result = match args.verify_parallel_readers {
+            Some(readers) => read_device_parallel(
+                &args,
+                &spec,
+                buffer_size,
+                read_size,
+                readers,
+                &mut error_log,
+            ),
+            None => read_device(
+                &args,
+                &mut *data_source(&args, &spec, buffer_size),
+                buffer_size,
+                alignment,
+                read_start..read_size,
+                &mut error_log,
+            ),
+        }
+        .with_context(|| format!("Error reading from device '{}'", args.device.display()))?;
+        checkpoint::remove()?;
+        drop(terminal.take());
+        summary.read = Some(PhaseStats {
+            bytes: result.read_bytes - read_start,
+            duration: start.elapsed(),
+            started,
+            finished: SystemTime::now(),
+        });
+        summary.verified_bytes = result.matched_bytes;
+        if args.compare_claimed_to_usable {
+            summary.usable_bytes = Some(read_size);
+        }
+        let size_unchanged = check_disk_size_unchanged(&args.device, disk_size);
+        if args.verbose > 0 || !result.read_sizes.is_uniform() {
+            result.read_sizes.print("read");
+        }
+
+        if args.continue_on_error || args.pause_on_mismatch {
+            result.mismatch_regions.print();
+        }
+        if args.compare_previous {
+            compare_with_previous_run(&args, disk_size, &result.mismatch_regions)?;
+        }
+        if let Some(path) = &args.repair_remap {
+            write_bad_blocks(path, &result.mismatch_regions, args.remap_block_size)?;
+        }
+        let mut passes_disagree = false;
+        if args.bidirectional {
+            let backward = read_backward(
+                &args,
+                &spec,
+                buffer_size,
+                read_start..read_size,
+                &mut error_log,
+            )
+            .with_context(|| {
+                format!(
+                    "Error reading backward from device '{}'",
+                    args.device.display()
+                )
+            })?;
+            passes_disagree = !cross_check_passes(&result.mismatch_regions, &backward);
+        }
+        if args.compare_mode == CompareMode::BitErrorRate {
+            let compared_bits = (result.read_bytes - read_start) * 8;
+            emit!(
+                "Compared {} bits, {} bits in error. Bit error rate: {:.3e}",
+                compared_bits,
+                result.bit_errors,
+                result.bit_errors as f64 / compared_bits as f64
+            );
+        }
+
+        if let Some(Mismatch {
+            position,
+            kind: MismatchKind::Byte { .. },
+        }) = &result.mismatch
+        {
+            if args.detect_wraparound {
+                detect_wraparound(&args, &spec, *position, read_size)
+                    .context("Error while checking for wraparound")?;
+            }
+        }
+
+        summary.print();
+
+        if let Some(path) = &args.report_out {
+            let report = Report {
+                device: args.device.clone(),
+                device_serial: sysfs::device_serial(&args.device).unwrap_or(None),
+                keystream: spec.clone(),
+                claimed_bytes: disk_size,
+                usable_bytes: summary.usable_bytes,
+                written_bytes: summary.write.map(|write| write.bytes),
+                write_phase: summary.write.map(|write| (write.started, write.finished)),
+                read_phase: summary.read.map(|read| (read.started, read.finished)),
+                verified_bytes: result.matched_bytes,
+                first_mismatch: result.mismatch.as_ref().map(|m| m.position),
+                started_at,
+                finished_at: report::unix_time(),
+            };
+            report
+                .write(path, signing_key.as_ref())
+                .with_context(|| format!("Error writing report to '{}'", path.display()))?;
+            match &signing_key {
+                Some(key) => emit!(
+                    "Wrote report to '{}', signed by public key {}",
+                    path.display(),
+                    hex::encode(key.public_key())
+                ),
+                None => emit!("Wrote report to '{}'", path.display()),
+            }
+        }
+
+        if passes_disagree {
+            bail!("Reading the device forward and backward found different regions of mismatching bytes");
+        }
+
+        if let Some(mismatch) = result.mismatch {
+            bail!("{}", mismatch);
+        }
+
+        if let Some(error) = short_write {
+            return Err(error);
+        }
+
+        // Fewer bytes read back than written is a short read, caught below. More is only possible
+        // if the device returns data past where it stopped accepting writes. A thin device does
+        // that legitimately, as its virtual size goes on after its backing store is full.
+        if let (Some(written_bytes), None, false) = (
+            written_total,
+            &result.mismatch,
+            args.compare_claimed_to_usable,
+        ) {
+            if reads_past(&args, written_bytes, alignment) {
+                bail!(
+                    "Device returned data on read past the {} bytes it accepted on write. Genuine media reads back exactly as many bytes as it accepted, so the geometry the device reports is suspect",
+                    written_bytes
+                );
+            }
+        }
+
+        if args.compare_claimed_to_usable {
+            if result.matched_bytes != read_size {
+                return Err(condition_error(
+                    &args,
+                    Condition::ShortRead,
+                    format!(
+                        "Read {} bytes, but the device accepted {} bytes before filling up",
+                        result.matched_bytes, read_size
+                    ),
+                ));
+            }
+        } else if result.matched_bytes != disk_size {
+            return Err(condition_error(
+                &args,
+                Condition::ShortRead,
+                format!(
+                    "Read {} bytes, but expected disk size to be {} bytes",
+                    result.matched_bytes, disk_size
+                ),
+            ));
+        }
+
+        if !size_unchanged {
+            warn_or_fail(
+                &args,
+                Condition::DiskSizeChanged,
+                "Device size changed".to_string(),
+            )?;
+        } else if args.mark_tested {
+            marker::Marker::new(&spec.key, result.matched_bytes)
+                .write(&args.device)
+                .context("Error writing tested marker to device")?;
+            emit!("Wrote tested marker to the start of the device");
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-read the first block of the device, bypassing the page cache, and return the position of
+/// the first byte that differs from what was written to it, if any.
+fn check_no_wrap(
+    args: &Args,
+    spec: &KeystreamSpec,
+    block_size: u64,
+    disk_size: u64,
+) -> Result<Option<u64>> {
+    let len = block_size.min(disk_size) as usize;
+    let mut d = open_handle(args, Mode::Read, OpenFlags::default())?;
+    drop_page_cache(d.file())?;
+    let mut block = vec![0; len];
+    d.read_exact(&mut block)?;
+    Ok(block
+        .iter()
+        .zip(spec.bytes_at(0, len))
+        .position(|(actual, expected)| *actual != expected)
+        .map(|i| i as u64))
+}
+
+/// Find the real capacity of the device for --probe-capacity, by binary search for the first block
+/// that does not hold what is written to it, and fail if it is smaller than claimed.
+fn probe_capacity(
+    args: &Args,
+    spec: &KeystreamSpec,
+    block_size: u64,
+    disk_size: u64,
+) -> Result<()> {
+    let blocks = disk_size / block_size;
+    if blocks == 0 {
+        bail!("--probe-capacity requires a device of at least one block");
+    }
+    let mut prober = Prober {
+        writer: open_device(&args.device, Mode::Write, args.open_flags)?,
+        reader: open_device(&args.device, Mode::Read, args.open_flags)?,
+        source: KeystreamSource::new(spec),
+        expected: Buffer::new(block_size as usize, false),
+        found: Buffer::new(block_size as usize, false),
+        good: Vec::new(),
+        probes: 0,
+        verbose: args.verbose > 0,
+    };
+    emit!(
+        "Probing the capacity of device {} with blocks of {} bytes",
+        args.device.display(),
+        block_size
+    );
+
+    if !prober.probe(0)? {
+        bail!("The first block of the device does not hold what was written to it");
+    }
+    // Every block before `good_end` holds data, and the block at `bad` does not, if it exists.
+    let mut good_end = 1;
+    let mut bad = blocks;
+    let mut candidate = 1;
+    while candidate < blocks {
+        if !prober.probe(candidate)? {
+            bad = candidate;
+            break;
+        }
+        good_end = candidate + 1;
+        candidate *= 2;
+    }
+    if bad == blocks && good_end < blocks && !prober.probe(blocks - 1)? {
+        bad = blocks - 1;
+    }
+    if bad == blocks {
+        good_end = blocks;
+    }
+    while good_end < bad {
+        let mid = good_end + (bad - good_end) / 2;
+        if prober.probe(mid)? {
+            good_end = mid + 1;
+        } else {
+            bad = mid;
+        }
+    }
+
+    let capacity = if bad == blocks {
+        disk_size
+    } else {
+        bad * block_size
+    };
+    emit!(
+        "Probed {} blocks. {}",
+        prober.probes,
+        Message::Claimed { bytes: disk_size }
+    );
+    if capacity == disk_size {
+        emit!(
+            "{} every probed block holds its data. Only a full run verifies every block",
+            color::paint("PASS:", Color::Green)
+        );
+        return Ok(());
+    }
+    emit!(
+        "{} the device only holds data up to position {}, {:.1}% of its claimed size",
+        color::paint("FAIL:", Color::Red),
+        capacity,
+        capacity as f64 / disk_size as f64 * 100.0
+    );
+    bail!(
+        "Device holds {} bytes, but claims to hold {} bytes",
+        capacity,
+        disk_size
+    );
+}
+
+/// Writes and reads back single blocks for --probe-capacity.
+struct Prober {
+    writer: File,
+    reader: File,
+    source: KeystreamSource,
+    expected: Buffer,
+    found: Buffer,
+    /// Offsets of the blocks found to hold their data, which are checked again after every write.
+    good: Vec<u64>,
+    probes: u64,
+    verbose: bool,
+}
+
+impl Prober {
+    /// Write the block with the given index and read it back, along with every block that held
+    /// its data before. Returns whether all of them still hold their data. If the write clobbered
+    /// an earlier block, that block is restored.
+    fn probe(&mut self, block: u64) -> Result<bool> {
+        use std::os::unix::fs::FileExt;
+
+        self.probes += 1;
+        let offset = block * self.expected.len() as u64;
+        self.source.fill(&mut self.expected, offset);
+        // An error writing or reading the block is as good as the device not holding it.
+        let written = self
+            .writer
+            .write_all_at(&self.expected, offset)
+            .and_then(|()| self.writer.sync_data());
+        let holds_data = written.is_ok() && self.holds_data(offset)?;
+
+        let mut clobbered = Vec::new();
+        for earlier in self.good.clone() {
+            if !self.holds_data(earlier)? {
+                clobbered.push(earlier);
+            }
+        }
+        for &earlier in &clobbered {
+            self.source.fill(&mut self.expected, earlier);
+            self.writer
+                .write_all_at(&self.expected, earlier)
+                .and_then(|()| self.writer.sync_data())
+                .with_context(|| format!("Error restoring the block at offset {}", earlier))?;
+        }
+
+        let result = holds_data && clobbered.is_empty();
+        if self.verbose {
+            match (result, clobbered.first()) {
+                (true, _) => emit!("Block at offset {} holds its data", offset),
+                (false, Some(earlier)) => emit!(
+                    "Writing the block at offset {} overwrote the block at offset {}",
+                    offset,
+                    earlier
+                ),
+                (false, None) => emit!("Block at offset {} does not hold its data", offset),
+            }
+        }
+        if result {
+            self.good.push(offset);
+        }
+        Ok(result)
+    }
+
+    /// Whether the block at `offset` reads back as what is written there, bypassing the page
+    /// cache.
+    fn holds_data(&mut self, offset: u64) -> Result<bool> {
+        use std::os::unix::fs::FileExt;
+
+        self.source.fill(&mut self.expected, offset);
+        drop_cached_range(&self.reader, offset, self.found.len() as u64)?;
+        Ok(self.reader.read_exact_at(&mut self.found, offset).is_ok()
+            && compare::first_mismatch(&self.found, &self.expected).is_none())
+    }
+}
+
+/// The granularity of the wrap periods that --detect-wraparound looks for.
+const WRAPAROUND_STEP: u64 = 1 << 20;
+
+/// How many bytes at the mismatch are compared with the keystream at other offsets. Long enough
+/// that a match is never a coincidence.
+const WRAPAROUND_SAMPLE_SIZE: usize = 64;
+
+/// Check whether the bytes on the device at the mismatching `position` are what was written to
+/// another offset, at a multiple of `WRAPAROUND_STEP` bytes away, which means the device wraps its
+/// address space around.
+fn detect_wraparound(
+    args: &Args,
+    spec: &KeystreamSpec,
+    position: u64,
+    disk_size: u64,
+) -> Result<()> {
+    let len = (disk_size - position).min(WRAPAROUND_SAMPLE_SIZE as u64) as usize;
+    let mut sample = vec![0; len];
+    // An unaligned read of a few bytes, so --open-flags is not applied.
+    let mut d = open_handle(args, Mode::Read, OpenFlags::default())?;
+    d.seek(SeekFrom::Start(position))?;
+    d.read_exact(&mut sample)?;
+
+    // Data written after the wrap point overwrites data at the start of the device, so the
+    // mismatching bytes are usually what was written to a later offset, but check both
+    // directions, nearest first, so the smallest period is found.
+    let max_distance = position.max(disk_size - position);
+    let mut distance = WRAPAROUND_STEP;
+    while distance <= max_distance {
+        let candidates = [
+            position.checked_sub(distance),
+            position.checked_add(distance),
+        ];
+        for offset in candidates.into_iter().flatten() {
+            if offset + len as u64 <= disk_size && spec.bytes_at(offset, len) == sample {
+                let period = wraparound_period(&mut *d, &sample, position, distance, disk_size)?;
+                emit!(
+                    "Device wraps around every {} bytes: position {} holds the data written to position {}",
+                    period,
+                    position,
+                    offset
+                );
+                return Ok(());
+            }
+        }
+        distance += WRAPAROUND_STEP;
+    }
+    emit!(
+        "No wraparound found: the data at position {} does not match what was written to any position a multiple of {} bytes away",
+        position,
+        WRAPAROUND_STEP
+    );
+    Ok(())
+}
+
+/// Find the wrap period given that `sample`, read at `position`, was written `distance` bytes away.
+/// The distance is a multiple of the period, and positions a period apart read back the same
+/// bytes, so the period is the smallest divisor of the distance for which they do.
+fn wraparound_period(
+    d: &mut dyn DeviceHandle,
+    sample: &[u8],
+    position: u64,
+    distance: u64,
+    disk_size: u64,
+) -> Result<u64> {
+    let mut buf = vec![0; sample.len()];
+    let mut period = WRAPAROUND_STEP;
+    while period < distance {
+        if distance.is_multiple_of(period) {
+            let other = if position + period + sample.len() as u64 <= disk_size {
+                position + period
+            } else {
+                position - period
+            };
+            d.seek(SeekFrom::Start(other))?;
+            d.read_exact(&mut buf)?;
+            if buf == sample {
+                return Ok(period);
+            }
+        }
+        period += WRAPAROUND_STEP;
+    }
+    Ok(distance)
+}
+
+/// Refuse to start if the I/O buffers would take more memory than --max-memory allows, or than is
+/// available, rather than risk being killed for running out of memory partway through the run.
+fn check_memory(args: &Args, buffer_size: u64) -> Result<()> {
+    let buffer = Buffer::allocation_size(buffer_size as usize, args.hugepages) as u64;
+    // Writing uses a single buffer, reading one for the device and one for the keystream per
+    // reader, and --io-uring another one for each operation in flight.
+    let mut buffers = if args.read {
+        2 * args.verify_parallel_readers.unwrap_or(1) as u64
+    } else {
+        1
+    };
+    if args.io_uring {
+        buffers += args.io_uring_depth as u64;
+    }
+    let total = buffer * buffers;
+
+    let (limit, source) = match args.max_memory {
+        Some(max_memory) => (max_memory, "--max-memory"),
+        None => match available_memory() {
+            Some(available) => (available, "the available memory"),
+            None => return Ok(()),
+        },
+    };
+    if total > limit {
+        bail!(
+            "The I/O buffers would take {} bytes ({} buffers of {} bytes), more than the {} bytes allowed by {}. Use a smaller --buffer-size or fewer --verify-parallel-readers.",
+            total,
+            buffers,
+            buffer,
+            limit,
+            source
+        );
+    }
+    if args.verbose > 0 {
+        emit!(
+            "I/O buffers take {} bytes, within the {} bytes allowed by {}",
+            total,
+            limit,
+            source
+        );
+    }
+    Ok(())
+}
+
+/// Get the memory available for starting new programs without swapping, from /proc/meminfo.
+fn available_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Print the marker written by --mark-tested for --check-tested.
+fn check_tested(device: &Path) -> Result<()> {
+    let marker = marker::Marker::read(device)
+        .with_context(|| format!("Error reading tested marker from '{}'", device.display()))?;
+    match marker {
+        Some(marker) => {
+            emit!(
+                "Device '{}' was verified at {}",
+                device.display(),
+                output::timestamp(std::time::UNIX_EPOCH + Duration::from_secs(marker.tested_at))
+            );
+            emit!("Verified: {} bytes", marker.verified_bytes);
+            emit!("Seed hash: {}", marker.seed_hash);
+            emit!("Tool version: {}", marker.tool_version);
+        }
+        None => emit!("No tested marker found on device '{}'", device.display()),
+    }
+    Ok(())
+}
+
+/// Report a condition that is only a warning unless --strict is given, in which case it fails the
+/// run.
+fn warn_or_fail(args: &Args, condition: Condition, message: String) -> Result<()> {
+    if args.strict {
+        return Err(condition_error(args, condition, message));
+    }
+    emit!("{} {}", color::paint("WARNING:", Color::Yellow), message);
+    Ok(())
+}
+
+/// Create the error for a condition that fails the run, carrying the condition's exit code if
+/// --strict is given.
+fn condition_error(args: &Args, condition: Condition, message: String) -> Error {
+    if args.strict {
+        Error::new(strict::Violation(condition)).context(message)
+    } else {
+        Error::msg(message)
+    }
+}
+
+/// Read throughput divided by write throughput above which the difference is reported as
+/// anomalous. Reads are commonly several times faster than writes on flash, but not this much.
+const MAX_READ_WRITE_RATIO: f64 = 20.0;
+
+/// Read throughput divided by write throughput below which the difference is reported as
+/// anomalous. Genuine media does not write much faster than it reads.
+const MIN_READ_WRITE_RATIO: f64 = 0.5;
+
+/// Statistics of a single phase of the run.
+#[derive(Debug, Clone, Copy)]
+struct PhaseStats {
+    bytes: u64,
+    /// Measured with a monotonic clock, so it is not thrown off by the wall clock being changed.
+    duration: Duration,
+    /// Wall clock time the phase started, for correlating with other logs.
+    started: SystemTime,
+    finished: SystemTime,
+}
+
+impl PhaseStats {
+    /// Average throughput in bytes per second.
+    fn rate(&self) -> f64 {
+        (self.bytes as f64) / self.duration.as_secs_f64()
+    }
+
+    fn print_times(&self, phase: &str) {
+        emit!(
+            "{} phase started at {} and finished at {}, taking {:.1} seconds",
+            phase,
+            output::local_timestamp(self.started),
+            output::local_timestamp(self.finished),
+            self.duration.as_secs_f64()
+        );
+    }
+}
+
+/// The overall result of the run.
+#[derive(Debug)]
+struct Summary {
+    /// The size the device claims to have.
+    claimed_bytes: u64,
+    /// With --compare-claimed-to-usable, the number of bytes the device accepted before filling
+    /// up, which is then verified instead of the claimed size.
+    usable_bytes: Option<u64>,
+    /// The number of bytes that were read back and matched what was written.
+    verified_bytes: u64,
+    write: Option<PhaseStats>,
+    read: Option<PhaseStats>,
+}
+
+impl Summary {
+    /// Print the headline result of the run: the claimed size, the verified size, and whether
+    /// those two agree, followed by the throughput of each phase.
+    fn print(&self) {
+        emit!(
+            "{}",
+            Message::Claimed {
+                bytes: self.claimed_bytes
+            }
+        );
+        emit!(
+            "{}",
+            Message::Verified {
+                bytes: self.verified_bytes
+            }
+        );
+        match self.usable_bytes {
+            Some(usable_bytes) => {
+                emit!(
+                    "{}",
+                    Message::Usable {
+                        bytes: usable_bytes,
+                        claimed: self.claimed_bytes
+                    }
+                );
+                emit!(
+                    "{}",
+                    Message::UsableVerdict {
+                        pass: self.verified_bytes == usable_bytes
+                    }
+                );
+            }
+            None => emit!(
+                "{}",
+                Message::Verdict {
+                    pass: self.verified_bytes == self.claimed_bytes
+                }
+            ),
+        }
+
+        if let Some(write) = &self.write {
+            emit!("{}", Message::WriteRate { rate: write.rate() });
+        }
+        if let Some(read) = &self.read {
+            emit!("{}", Message::ReadRate { rate: read.rate() });
+        }
+        if let (Some(write), Some(read)) = (&self.write, &self.read) {
+            let ratio = read.rate() / write.rate();
+            if !(MIN_READ_WRITE_RATIO..=MAX_READ_WRITE_RATIO).contains(&ratio) {
+                emit!("{}", Message::UnusualRateRatio { ratio });
+            }
+        }
+
+        if let Some(write) = &self.write {
+            write.print_times("Write");
+        }
+        if let Some(read) = &self.read {
+            read.print_times("Read");
+        }
+        if let (Some(write), Some(read)) = (&self.write, &self.read) {
+            emit!(
+                "Both phases took {:.1} seconds in total, from {} to {}",
+                (write.duration + read.duration).as_secs_f64(),
+                output::local_timestamp(write.started),
+                output::local_timestamp(read.finished)
+            );
+        }
+    }
+}
+
+/// Confirm that the device may be overwritten, either from --overwrite-confirmation-from, --yes, or
+/// by asking. Fails unless confirmed.
+fn check_overwrite_confirmed(args: &Args) -> Result<()> {
+    if let Some(path) = &args.overwrite_confirmation_from {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read confirmation file '{}'", path.display()))?;
+        let confirmed = contents.strip_suffix('\n').unwrap_or(&contents);
+        if confirmed.as_bytes() != args.device.as_os_str().as_bytes() {
+            bail!(
+                "Confirmation file '{}' does not contain exactly the device path '{}'. Safely exiting . . .",
+                path.display(),
+                args.device.display()
+            );
+        }
+        emit!(
+            "Overwriting data on device '{}' as confirmed by '{}'",
+            args.device.display(),
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let devices = vec![args.device.clone()];
+    let assume_yes = args.yes;
+    let confirmed = with_timeout(args.confirm_timeout, move || {
+        confirm_overwrite(
+            &devices,
+            std::io::stdin().lock(),
+            std::io::stderr(),
+            assume_yes,
+        )
+    })?;
+    if !confirmed {
+        bail!("Did not accept overwriting data on device. Safely exiting . . .");
+    }
+    Ok(())
+}
+
+/// Verify each of several devices given on the command line in a process of its own, after
+/// confirming the overwrite of all of them at once.
+fn verify_multiple(args: &Args) -> Result<()> {
+    let devices = &args.device_arg;
+    if args.device_flag.is_some() || args.find_by_id.is_some() {
+        bail!("Several devices were given with the positional argument, so --device and --find-by-id cannot be given as well");
+    }
+    if args.seed.is_some() || args.raw_seed.is_some() || args.keystream.is_some() {
+        bail!("Each device gets a seed of its own when verifying several, so --seed, --raw-seed and --keystream cannot be used. Use --master-key or --seed-url to get the seeds by the device serial numbers instead");
+    }
+    if args.overwrite_confirmation_from.is_some()
+        || args.checkpoint.is_some()
+        || args.resume_from_json.is_some()
+        || args.error_log.is_some()
+        || args.checksum_out.is_some()
+        || args.checksum_in.is_some()
+        || args.verify_json_in.is_some()
+        || args.state_file.is_some()
+        || args.report_out.is_some()
+    {
+        bail!("Files for a single device cannot be used when verifying several, please use --output-dir to collect the files of each run instead");
+    }
+    if args.tui && !std::io::stderr().is_tty() {
+        bail!("--tui requires stderr to be a TTY");
+    }
+    if args.metrics_port.is_some() {
+        bail!("--metrics-port cannot be used when verifying several devices, since they would all serve on the same port");
+    }
+
+    if args.write {
+        let devices = devices.clone();
+        let assume_yes = args.yes;
+        let confirmed = with_timeout(args.confirm_timeout, move || {
+            confirm_overwrite(
+                &devices,
+                std::io::stdin().lock(),
+                std::io::stderr(),
+                assume_yes,
+            )
+        })?;
+        if !confirmed {
+            bail!("Did not accept overwriting data on the devices. Safely exiting . . .");
+        }
+    }
+
+    // The children are interrupted along with us by Ctrl-C, so we only need to outlive them.
+    interrupt::init()?;
+    multi::run(devices, args.write, args.tui)
+}
+
+/// Ask whether the devices may be overwritten, writing the question to `writer` and reading the
+/// answer from `reader`. Only a line of `y` or `Y` confirms, anything else including the end of
+/// the input does not. With `assume_yes` nothing is read and the overwrite is confirmed.
+fn confirm_overwrite(
+    devices: &[PathBuf],
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    assume_yes: bool,
+) -> Result<bool> {
+    if assume_yes {
+        for device in devices {
+            writeln!(writer, "{}", Message::OverwritingBecauseOfYes { device })?;
+        }
+        return Ok(true);
+    }
+
+    match devices {
+        [device] => writeln!(writer, "{}", Message::ConfirmOverwrite { device })?,
+        _ => writeln!(writer, "{}", Message::ConfirmOverwriteDevices { devices })?,
+    }
+    let mut response = String::new();
+    reader
+        .read_line(&mut response)
+        .context("Error reading from stdin")?;
+    Ok(messages::is_yes(&response))
+}
+
+/// Read a line of response from stdin. If a timeout is given and no line arrives in time, an error
+/// is returned.
+fn read_confirmation(timeout: Option<u64>) -> Result<String> {
+    with_timeout(timeout, || {
+        let mut response = String::new();
+        std::io::stdin()
+            .read_line(&mut response)
+            .context("Error reading from stdin")?;
+        Ok(response)
+    })
+}
+
+/// Run `f`, which waits for a response from the user, giving up after `timeout` seconds if given.
+fn with_timeout<T: Send + 'static>(
+    timeout: Option<u64>,
+    f: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let timeout = match timeout {
+        Some(timeout) => Duration::from_secs(timeout),
+        None => return f(),
+    };
+
+    // The thread is left blocked waiting for the response if we time out, but since we're about to
+    // exit that doesn't matter.
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(f());
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => bail!(
+            "Did not receive a response within {} seconds. Safely exiting . . .",
+            timeout.as_secs()
+        ),
+    }
+}
+
+fn get_seed(args: &Args) -> Result<[u8; 32]> {
+    use sha2::Digest;
+
+    if let Some(url) = &args.seed_url {
+        if args.seed.is_some() || args.raw_seed.is_some() || args.master_key.is_some() {
+            bail!("--seed-url is mutually exclusive with --seed, --raw-seed and --master-key, please specify only one of them");
+        }
+        let seed = seed_service::fetch_seed(url, &args.device)?;
+        return decode_raw_seed(&seed, "The seed from the seed service");
+    }
+
+    if let Some(master_key) = &args.master_key {
+        if args.seed.is_some() || args.raw_seed.is_some() {
+            bail!("--master-key is mutually exclusive with --seed and --raw-seed, please specify only one of them");
+        }
+        let serial = sysfs::device_serial(&args.device)
+            .context("Unable to look up device serial number")?
+            .context("Device has no serial number, which is required for --master-key")?;
+        emit!(
+            "Deriving seed from master key and device serial '{}'",
+            serial
+        );
+
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(master_key.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(serial.as_bytes());
+        return Ok(hasher.finalize().into());
+    }
+
+    match (&args.seed, &args.raw_seed) {
+        (Some(_), Some(_)) => {
+            bail!("--seed and --raw-seed are mutually exclusive, please specify only one of them");
+        }
+        (Some(seed), None) => {
+            let hash = sha2::Sha256::digest(seed.as_bytes());
+            Ok(hash.into())
+        }
+        (None, Some(raw_seed)) => decode_raw_seed(raw_seed, "--raw-seed"),
+        (None, None) => {
+            if !args.write && args.read && args.checksum_in.is_none() {
+                bail!("Cannot read but not write when using random seed.");
+            }
+            Ok(rand::random())
+        }
+    }
+}
+
+/// Read the Ed25519 private key of --sign-key from the given file.
+fn read_signing_key(path: &Path) -> Result<ed25519::SigningKey> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read signing key '{}'", path.display()))?;
+    let key = decode_raw_seed(
+        contents.trim(),
+        &format!("signing key '{}'", path.display()),
+    )?;
+    Ok(ed25519::SigningKey::from_bytes(&key))
+}
+
+/// Decode a raw seed given as 64 hexadecimal characters, naming where it came from in errors.
+fn decode_raw_seed(raw_seed: &str, source: &str) -> Result<[u8; 32]> {
+    if raw_seed.len() != 64 {
+        bail!(
+            "{} has invalid length {}, expected 64 characters",
+            source,
+            raw_seed.len()
+        );
+    }
+
+    let mut buf = [0u8; 32];
+    hex::decode_to_slice(raw_seed.as_bytes(), &mut buf).map_err(|e| match e {
+        hex::FromHexError::InvalidHexCharacter { c, index } => anyhow!(
+            "Invalid hex in {} at position {}: '{}' is not a hex digit",
+            source,
+            index + 1,
+            c
+        ),
+        e => Error::from(e).context(format!("Invalid hex in {}", source)),
+    })?;
+    Ok(buf)
+}
+
+/// Check that the device path is something we can test, with targeted messages for the common
+/// mistakes.
+fn check_device_path(path: &Path, allow_file: bool) -> Result<()> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::from(e).context(format!("Path '{}' does not exist", path.display())));
+        }
+        Err(e) => {
+            return Err(Error::from(e).context(format!("Unable to access '{}'", path.display())));
+        }
+    };
+
+    if metadata.is_dir() {
+        bail!(
+            "Path '{}' is a directory, expected a block device",
+            path.display()
+        );
+    }
+    if metadata.is_file() && !allow_file {
+        bail!(
+            "Path '{}' is a regular file, expected a block device (use --allow-file to test it anyway)",
+            path.display()
+        );
+    }
+    if !metadata.is_file() && !metadata.file_type().is_block_device() {
+        bail!(
+            "Path '{}' is {}, expected a block device",
+            path.display(),
+            device::describe_file_type(metadata.file_type())
+        );
+    }
+    Ok(())
+}
+
+fn get_block_size(path: &Path) -> Result<u64> {
+    let block_size = device::block_size(path)?;
+    emit!("Disk block size is {} bytes", block_size);
+    Ok(block_size)
+}
+
+/// Get the logical sector size of a block device, printing it along with the physical sector size.
+/// Returns None for regular files.
+fn get_logical_sector_size(path: &Path) -> Result<Option<u64>> {
+    let sizes = device::sector_sizes(path)?;
+    if let Some(sizes) = sizes {
+        emit!(
+            "Logical sector size is {} bytes, physical sector size is {} bytes",
+            sizes.logical,
+            sizes.physical
+        );
+    }
+    Ok(sizes.map(|sizes| sizes.logical))
+}
+
+fn get_disk_size(path: &Path) -> Result<u64> {
+    let size = device::disk_size(path)?;
+    if size == 0 {
+        bail!("Device reports size 0, cannot verify");
+    }
+    emit!("Disk size is {} bytes", size);
+    Ok(size)
+}
+
+/// How many regions --dry-verify samples.
+const DRY_VERIFY_REGIONS: u64 = 256;
+
+/// How many bytes --dry-verify reads at the start of each region.
+const DRY_VERIFY_SAMPLE_SIZE: u64 = 1 << 20;
+
+/// Samples with less entropy than this, in bits per byte, do not look random. A megabyte of random
+/// data is within a thousandth of a bit of the maximum of 8.
+const DRY_VERIFY_MIN_ENTROPY: f64 = 7.9;
+
+/// Sample the device for --dry-verify and report whether it looks like it is filled with random
+/// data.
+fn dry_verify(args: &Args) -> Result<()> {
+    let disk_size = get_disk_size(&args.device)?;
+    let regions = DRY_VERIFY_REGIONS.min(disk_size.div_ceil(DRY_VERIFY_SAMPLE_SIZE));
+    let region_size = disk_size / regions;
+    emit!(
+        "Sampling {} bytes at the start of each of {} regions of {} bytes",
+        DRY_VERIFY_SAMPLE_SIZE.min(region_size),
+        regions,
+        region_size
+    );
+
+    let mut d = open_device(&args.device, Mode::Read, args.open_flags)?;
+    let mut buf = Buffer::new(DRY_VERIFY_SAMPLE_SIZE.min(region_size) as usize, false);
+    let mut low_entropy = 0;
+    for region in 0..regions {
+        let offset = region * region_size;
+        d.seek(SeekFrom::Start(offset))?;
+        d.read_exact(&mut buf)
+            .with_context(|| format!("Error reading the sample at offset {}", offset))?;
+        let entropy = entropy::shannon_entropy(&buf);
+        if entropy < DRY_VERIFY_MIN_ENTROPY {
+            low_entropy += 1;
+            let kind = if buf.iter().all(|&b| b == 0) {
+                "all zeros"
+            } else if buf.iter().all(|&b| b == buf[0]) {
+                "a repeated byte"
+            } else {
+                "not random"
+            };
+            emit!(
+                "Region at offset {}: {:.3} bits per byte, {}",
+                offset,
+                entropy,
+                kind
+            );
+        } else if args.verbose > 0 {
+            emit!("Region at offset {}: {:.3} bits per byte", offset, entropy);
+        }
+    }
+
+    if low_entropy > 0 {
+        bail!(
+            "{} of {} regions do not look like random data, so the device was not fully written with random data, or does not hold what was written",
+            low_entropy,
+            regions
+        );
+    }
+    emit!(
+        "{} all {} regions look like random data",
+        color::paint("PASS:", Color::Green),
+        regions
+    );
+    Ok(())
+}
+
+/// Use the settings recommended for a known device model where the command line leaves them unset.
+fn apply_known_device(args: &mut Args, known: &device_db::KnownDevice) {
+    let mut applied = Vec::new();
+    // Auto-tuning and continuing a run choose the buffer size themselves.
+    if let (Some(size), None) = (known.buffer_size, args.buffer_size) {
+        if !args.auto_tune
+            && args.state_file.is_none()
+            && args.verify_json_in.is_none()
+            && args.resume_from_json.is_none()
+        {
+            args.buffer_size = Some(size);
+            applied.push(format!("--buffer-size {}", size));
+        }
+    }
+    if known.direct && args.open_flags == OpenFlags::default() {
+        args.open_flags = args.open_flags.with_direct();
+        applied.push("--open-flags direct".to_string());
+    }
+    if let (Some(retries), None) = (known.read_retries, args.read_retry) {
+        args.read_retry = Some(retries);
+        applied.push(format!("--read-retry {}", retries));
+    }
+    emit!(
+        "Recognized {} {}, {}",
+        known.vendor,
+        known.model,
+        known.note
+    );
+    if !applied.is_empty() {
+        emit!(
+            "Using the settings known to suit it: {} (disable with --ignore-device-db)",
+            applied.join(", ")
+        );
+    }
+    if let Some(size) = known.slc_cache_size {
+        emit!(
+            "Its SLC cache is known to hold {} bytes, choose a larger --cache-bust-distance to read past it",
+            size
+        );
+    }
+}
+
+/// Print what is known about the device for --info, without writing to or reading its contents.
+fn print_info(path: &Path) -> Result<()> {
+    println!("Device: {}", path.display());
+    println!("Disk size: {} bytes", device::disk_size(path)?);
+    println!("Block size: {} bytes", device::block_size(path)?);
+    if let Some(sizes) = device::sector_sizes(path)? {
+        println!("Logical sector size: {} bytes", sizes.logical);
+        println!("Physical sector size: {} bytes", sizes.physical);
+    }
+    if let Ok(disk) = sysfs::disk_dir(path) {
+        let device = disk.join("device");
+        for (label, attribute) in [("Vendor", "vendor"), ("Model", "model")] {
+            if let Some(value) = sysfs::read_attribute(&device.join(attribute)) {
+                println!("{}: {}", label, value);
+            }
+        }
+    }
+    if let Some(serial) = sysfs::device_serial(path).unwrap_or(None) {
+        println!("Serial: {}", serial);
+    }
+    if let Some(known) = device_db::lookup(path) {
+        println!("Known device: {}", known.note);
+    }
+    Ok(())
+}
+
+/// Query the size of the device again and warn if it differs from the size it had at the start of
+/// the run. Some counterfeit devices and misbehaving USB adapters change their reported size after
+/// heavy I/O.
+///
+/// Returns whether the size is unchanged.
+fn check_disk_size_unchanged(path: &Path, disk_size: u64) -> bool {
+    match device::disk_size(path) {
+        Ok(size) if size == disk_size => true,
+        Ok(size) => {
+            emit!("{} Device size changed during the run from {} bytes to {} bytes. This is a strong indicator of a counterfeit or malfunctioning device.", color::paint("WARNING:", Color::Yellow), disk_size, size);
+            false
+        }
+        Err(e) => {
+            emit!(
+                "{} Unable to query device size at the end of the run: {:#}",
+                color::paint("WARNING:", Color::Yellow),
+                e
+            );
+            false
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn trim_device(path: &Path, disk_size: u64) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    /// `_IO(0x12, 119)` from linux/fs.h
+    const BLKDISCARD: libc::c_ulong = 0x1277;
+
+    let d = open_device(path, Mode::Write, OpenFlags::default())?;
+    let range: [u64; 2] = [0, disk_size];
+    let ret = unsafe { libc::ioctl(d.as_raw_fd(), BLKDISCARD as _, range.as_ptr()) };
+    if ret != 0 {
+        return Err(Error::from(std::io::Error::last_os_error()).context("BLKDISCARD ioctl failed"));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn trim_device(_path: &Path, _disk_size: u64) -> Result<()> {
+    bail!("Trimming is only supported on Linux");
+}
+
+fn progress_options(args: &Args) -> ProgressOptions {
+    ProgressOptions {
+        precision: args.progress_precision,
+        dashboard: args.tui,
+        every_bytes: args.progress_every,
+        resource_usage: args.verbose >= 2,
+    }
+}
+
+/// Write the first `len` bytes of the keystream to stdout.
+fn emit_stream(mut rng: Keystream, len: u64) -> Result<()> {
+    if std::io::stdout().is_tty() {
+        bail!("Refusing to write the binary keystream to a terminal, please redirect stdout");
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut buf = vec![0; 1 << 20];
+    let mut emitted_bytes = 0;
+    while emitted_bytes < len {
+        let n = (len - emitted_bytes).min(buf.len() as u64) as usize;
+        rng.fill_bytes(&mut buf[..n]);
+        match stdout.write_all(&buf[..n]) {
+            Ok(()) => emitted_bytes += n as u64,
+            // The consumer has seen all it wants, e.g. cmp found a difference.
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(e) => return Err(Error::from(e).context("Error writing to stdout")),
+        }
+    }
+    stdout.flush().context("Error writing to stdout")?;
+    emit!("Emitted {} bytes", emitted_bytes);
+    Ok(())
+}
+
+/// Candidate buffer sizes for --auto-tune.
+const AUTO_TUNE_BUFFER_SIZES: [u64; 5] = [64 << 10, 256 << 10, 1 << 20, 4 << 20, 16 << 20];
+
+/// Number of bytes written to benchmark each candidate buffer size with --auto-tune.
+const AUTO_TUNE_BYTES: u64 = 64 << 20;
+
+/// Find the fastest buffer size to write to the device with, by timing a write of random data to
+/// the start of the device with each of the candidate sizes.
+fn auto_tune_buffer_size(path: &Path, flags: OpenFlags, disk_size: u64) -> Result<u64> {
+    let bench_bytes = AUTO_TUNE_BYTES.min(disk_size);
+
+    let mut best: Option<(u64, f64)> = None;
+    for &buffer_size in AUTO_TUNE_BUFFER_SIZES.iter() {
+        if buffer_size > bench_bytes {
+            break;
+        }
+
+        let rate = benchmark_write(path, flags, buffer_size, bench_bytes)?;
+        emit!(
+            "Buffer size {} bytes: {:.0} bytes/second",
+            buffer_size,
+            rate
+        );
+        if best.is_none_or(|(_, best_rate)| rate > best_rate) {
+            best = Some((buffer_size, rate));
+        }
+    }
+
+    match best {
+        Some((buffer_size, _)) => Ok(buffer_size),
+        None => bail!("Device is too small to benchmark any buffer size"),
+    }
+}
+
+/// Measure the write rate in bytes per second by writing about `bench_bytes` bytes of random data
+/// to the start of the device, `buffer_size` bytes at a time.
+fn benchmark_write(
+    path: &Path,
+    flags: OpenFlags,
+    buffer_size: u64,
+    bench_bytes: u64,
+) -> Result<f64> {
+    let mut rng = Keystream::new(DEFAULT_ALGORITHM, rand::random());
+    let mut d = open_device(path, Mode::Write, flags)?;
+    let mut buf = Buffer::new(buffer_size as usize, false);
+    rng.fill_bytes(&mut buf);
+
+    let start = Instant::now();
+    let mut written_bytes = 0;
+    while written_bytes + buffer_size <= bench_bytes {
+        d.write_all(&buf)?;
+        written_bytes += buffer_size;
+    }
+    d.sync_all().context("Error while trying to call fsync")?;
+    Ok((written_bytes as f64) / start.elapsed().as_secs_f64())
+}
+
+/// Measure the read rate in bytes per second by reading about `bench_bytes` bytes from the start
+/// of the device, `buffer_size` bytes at a time.
+fn benchmark_read(
+    path: &Path,
+    flags: OpenFlags,
+    buffer_size: u64,
+    bench_bytes: u64,
+) -> Result<f64> {
+    let mut d = open_device(path, Mode::Read, flags)?;
+    drop_page_cache(&d).context("Error dropping the page cache of the device")?;
+    let mut buf = Buffer::new(buffer_size as usize, false);
+
+    let start = Instant::now();
+    let mut read_bytes = 0;
+    while read_bytes < bench_bytes {
+        match d.read(&mut buf)? {
+            0 => break,
+            n => read_bytes += n as u64,
+        }
+    }
+    Ok((read_bytes as f64) / start.elapsed().as_secs_f64())
+}
+
+/// The version shown by --version, with the build information needed to tell builds apart.
+fn long_version() -> String {
+    let features = env!("VDC_FEATURES");
+    format!(
+        "{}\ncommit: {}\ntarget: {}\ndefault RNG: {}\nfeatures: {}",
+        env!("CARGO_PKG_VERSION"),
+        env!("VDC_GIT_COMMIT"),
+        env!("VDC_TARGET"),
+        DEFAULT_ALGORITHM,
+        if features.is_empty() {
+            "none"
+        } else {
+            features
+        }
+    )
+}
+
+/// How long each algorithm is benchmarked for by --bench-rng.
+const BENCH_RNG_DURATION: Duration = Duration::from_secs(1);
+
+/// Print the throughput of generating each keystream algorithm for --bench-rng.
+fn bench_rng() {
+    let mut buf = vec![0u8; 1 << 20];
+    for algorithm in [Algorithm::ChaCha8, Algorithm::ChaCha12, Algorithm::ChaCha20] {
+        let mut rng = Keystream::new(algorithm, [0; 32]);
+        let start = Instant::now();
+        let mut bytes: u64 = 0;
+        while start.elapsed() < BENCH_RNG_DURATION {
+            rng.fill_bytes(&mut buf);
+            bytes += buf.len() as u64;
+        }
+        let rate = bytes as f64 / start.elapsed().as_secs_f64();
+        println!("{:<10} {:>8.2} GB/s", algorithm.to_string(), rate / 1e9);
+    }
+}
+
+/// Number of bytes read or written to measure the rate for --estimate.
+const ESTIMATE_BYTES: u64 = 64 << 20;
+
+/// Print the projected duration of each phase for --estimate.
+fn print_estimate(args: &Args, buffer_size: u64, disk_size: u64) -> Result<()> {
+    let bench_bytes = ESTIMATE_BYTES.min(disk_size).max(buffer_size);
+    let (write_rate, read_rate) = match args.assumed_rate {
+        Some(rate) => (rate, rate),
+        None => {
+            let read_rate = benchmark_read(&args.device, args.open_flags, buffer_size, bench_bytes)
+                .context("Error measuring read rate")?;
+            emit!("Measured read rate: {:.0} bytes/second", read_rate);
+            let write_rate = if args.estimate_write {
+                let write_rate =
+                    benchmark_write(&args.device, args.open_flags, buffer_size, bench_bytes)
+                        .context("Error measuring write rate")?;
+                emit!("Measured write rate: {:.0} bytes/second", write_rate);
+                write_rate
+            } else {
+                emit!("Write rate not measured, assuming it equals the read rate");
+                read_rate
+            };
+            (write_rate, read_rate)
+        }
+    };
+    if write_rate <= 0.0 || read_rate <= 0.0 {
+        bail!("Rates must be greater than 0");
+    }
+
+    let mut total = 0.0;
+    if args.write {
+        let seconds = disk_size as f64 / write_rate;
+        emit!(
+            "Projected write time: {}",
+            progress::format_duration(seconds as u64)
+        );
+        total += seconds;
+    }
+    if args.read {
+        let seconds = disk_size as f64 / read_rate;
+        emit!(
+            "Projected read time: {}",
+            progress::format_duration(seconds as u64)
+        );
+        total += seconds;
+    }
+    emit!(
+        "Projected total time: {}",
+        progress::format_duration(total as u64)
+    );
+    Ok(())
+}
+
+/// The outcome of writing to the device.
+#[derive(Debug)]
+struct WriteResult {
+    written_bytes: u64,
+    /// How many bytes each write call accepted.
+    write_sizes: SizeDistribution,
+    /// Where the write rate dropped off a cliff, if it did.
+    speed_cliff: Option<slc::SpeedCliff>,
+    /// Time spent syncing and reading back buffers with --write-verify.
+    verify_duration: Option<Duration>,
+}
+
+/// The source of the data of the write and read phases, generated ahead on a separate thread with
+/// --rng-thread.
+fn data_source(args: &Args, spec: &KeystreamSpec, buffer_size: u64) -> Box<dyn DataSource> {
+    if args.rng_thread {
+        Box::new(PrefetchSource::new(spec, buffer_size as usize))
+    } else {
+        Box::new(KeystreamSource::new(spec))
+    }
+}
+
+/// The buffer size and alignment of the I/O, from the buffer size asked for: validated, rounded up
+/// to the alignment, reduced to what a small device needs and checked to fit in memory.
+fn io_sizes(
+    args: &Args,
+    buffer_size: u64,
+    block_size: u64,
+    logical_sector_size: Option<u64>,
+    disk_size: u64,
+) -> Result<(u64, u64)> {
+    if buffer_size == 0 {
+        bail!("Buffer size must be greater than 0");
+    }
+
+    // Without --align, I/O is aligned to the logical sector size, which is the unit direct I/O
+    // requires, and which the block size reported by the filesystem layer is not guaranteed to be
+    // a multiple of.
+    let alignment = args.align.or(logical_sector_size).unwrap_or(1);
+    if let Some(align) = args.align {
+        if align == 0 {
+            bail!("Alignment must be greater than 0");
+        }
+        emit!(
+            "Device reports a block size of {} bytes, aligning I/O to {} bytes",
+            block_size,
+            align
+        );
+    }
+    let buffer_size = if !buffer_size.is_multiple_of(alignment) {
+        let aligned = (buffer_size / alignment + 1) * alignment;
+        emit!(
+            "Rounding buffer size of {} bytes up to {} bytes to match the alignment",
+            buffer_size,
+            aligned
+        );
+        aligned
+    } else {
+        buffer_size
+    };
+
+    // A device smaller than one buffer is covered by a single I/O either way, so only allocate
+    // what it needs.
+    let buffer_size = if buffer_size > disk_size {
+        let clamped = disk_size.div_ceil(alignment).max(1) * alignment;
+        if clamped < buffer_size {
+            emit!(
+                "Device is smaller than the buffer size, using a buffer size of {} bytes",
+                clamped
+            );
+        }
+        clamped.min(buffer_size)
+    } else {
+        buffer_size
+    };
+
+    check_memory(args, buffer_size)?;
+    Ok((buffer_size, alignment))
+}
+
+/// Whether the device returns any data when read at `offset`, which is where it stopped accepting
+/// writes. Errors count as no data, as they are how most devices refuse to read past their end.
+fn reads_past(args: &Args, offset: u64, alignment: u64) -> bool {
+    let mut buf = Buffer::new(alignment as usize, args.hugepages);
+    open_handle(args, Mode::Read, args.open_flags)
+        .and_then(|mut d| {
+            d.seek(SeekFrom::Start(offset))?;
+            d.read(&mut buf)
+        })
+        .is_ok_and(|n| n > 0)
+}
+
+/// Open the device for the write and read phases, or the simulated fake device on top of it with
+/// --simulate-fake.
+fn open_handle(
+    args: &Args,
+    mode: Mode,
+    flags: OpenFlags,
+) -> std::io::Result<Box<dyn DeviceHandle>> {
+    let file = open_device(&args.device, mode, flags)?;
+    Ok(match args.simulate_fake {
+        Some(real_size) => Box::new(fake::FakeDevice::new(file, real_size)?),
+        None => Box::new(file),
+    })
+}
+
+/// Write the data from `source` to the device from offset `start` until the device is full.
+fn write_device(
+    args: &Args,
+    source: &mut dyn DataSource,
+    buffer_size: u64,
+    start: u64,
+    disk_size: u64,
+    error_log: &mut ErrorLog,
+) -> Result<WriteResult> {
+    #[cfg(target_os = "linux")]
+    if args.io_uring {
+        return write_device_uring(args, source, buffer_size, start, disk_size, error_log);
+    }
+    let mut d = retry_open(args, || {
+        Ok(open_handle(args, Mode::Write, args.open_flags)?)
+    })?;
+    d.seek(SeekFrom::Start(start))?;
+    // A regular file would keep growing rather than running out of space, so stop at its size.
+    let is_file = d.file().metadata()?.is_file();
+    let mut buf = Buffer::new(buffer_size as usize, args.hugepages);
+    let mut checksums = match &args.checksum_out {
+        Some(path) => Some(ChecksumWriter::create(path, args.checksum_block_size)?),
+        None => None,
+    };
+
+    let mut thermal_guard = ThermalGuard::new(&args.device, args.thermal_guard);
+
+    let title = Message::Writing {
+        device: &args.device,
+    }
+    .to_string();
+    emit!("{}", title);
+    let mut progress = Progress::new(Verb::Written, title, disk_size, &progress_options(args));
+
+    progress.resume_from(start);
+
+    let mut written_bytes: u64 = start;
+    let mut write_sizes = SizeDistribution::default();
+    let mut last_len = buf.len();
+    let mut rate_sampler = slc::RateSampler::new(disk_size);
+    let mut entropy_checker = args.entropy_check.then(|| EntropyChecker::new(start));
+    let mut write_verifier = if args.write_verify {
+        Some(WriteVerifier {
+            d: open_device(&args.device, Mode::Read, args.open_flags)?,
+            buf: Buffer::new(buffer_size as usize, args.hugepages),
+            duration: Duration::ZERO,
+        })
+    } else {
+        None
+    };
+    loop {
+        progress.update(written_bytes);
+        rate_sampler.record(written_bytes - start);
+        checkpoint::update(Phase::Write, written_bytes)?;
+        if let Some(interrupted) = interrupt::received() {
+            args.sync_mode
+                .sync(d.file())
+                .context("Error while trying to flush the writes to the device")?;
+            emit!(
+                "{}, stopped after writing {} bytes",
+                interrupted,
+                written_bytes
+            );
+            checkpoint::interrupted(Phase::Write, written_bytes)?;
+            return Err(interrupted.into());
+        }
+        if let Some(thermal_guard) = &mut thermal_guard {
+            thermal_guard.check();
+        }
+
+        if is_file && written_bytes >= disk_size {
+            emit!(
+                "{}",
+                Message::WriteSucceeded {
+                    bytes: written_bytes
+                }
+            );
+            args.sync_mode
+                .sync(d.file())
+                .context("Error while trying to flush the writes to the device")?;
+            if let Some(checksums) = checksums {
+                checksums.finish()?;
+            }
+            return Ok(WriteResult {
+                written_bytes,
+                write_sizes,
+                speed_cliff: rate_sampler.find_cliff(),
+                verify_duration: write_verifier.map(|verifier| verifier.duration),
+            });
+        }
+
+        source.fill(&mut buf, written_bytes);
+        if let Some(checker) = &mut entropy_checker {
+            checker.add(&buf, written_bytes, args.verbose > 0)?;
+        }
+
+        let mut to_write = &buf[..];
+        if is_file {
+            let remaining = disk_size - written_bytes;
+            if remaining < to_write.len() as u64 {
+                to_write = &to_write[..remaining as usize];
+            }
+        }
+        let buffer_offset = written_bytes;
+        let buffer_len = to_write.len();
+        let result = write_buffer(
+            &mut d,
+            to_write,
+            &mut written_bytes,
+            &mut write_sizes,
+            &mut last_len,
+            args.verbose > 0,
+        );
+        if let Some(checksums) = &mut checksums {
+            checksums.update(&to_write[..(written_bytes - buffer_offset) as usize])?;
+        }
+        match result {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::WriteZero => {
+                error_log.log(written_bytes, "write returned 0 bytes")?;
+                bail!(
+                    "Could not write any data to device. Had successfully written {} bytes.",
+                    written_bytes
+                );
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENOSPC) => {
+                // The device is full. The flush can fail at the limit too, which means the last
+                // blocks written may never have made it to the media.
+                if let Err(e) = args.sync_mode.sync(d.file()) {
+                    error_log.io_error(written_bytes, &e)?;
+                    return Err(Error::from(e).context(format!(
+                        "Wrote {} bytes until the device reported it was full, but the final flush to the media failed, so the last blocks written may not be durable",
+                        written_bytes
+                    )));
+                }
+                emit!(
+                    "{}",
+                    Message::WriteSucceeded {
+                        bytes: written_bytes
+                    }
+                );
+                if let Some(checksums) = checksums {
+                    checksums.finish()?;
+                }
+                return Ok(WriteResult {
+                    written_bytes,
+                    write_sizes,
+                    speed_cliff: rate_sampler.find_cliff(),
+                    verify_duration: write_verifier.map(|verifier| verifier.duration),
+                });
+            }
+            Err(e) => {
+                error_log.io_error(written_bytes, &e)?;
+                if e.raw_os_error() == Some(libc::EROFS) {
+                    return Err(Error::from(e).context(format!(
+                        "Device went read-only mid-write at offset {}, likely because the kernel detected hardware errors. Check the kernel log with dmesg.",
+                        written_bytes
+                    )));
+                }
+                return Err(Error::from(e).context(format!(
+                    "Encountered error writing to device. Had successfully written {} bytes.",
+                    written_bytes
+                )));
+            }
+        }
+
+        if let Some(verifier) = &mut write_verifier {
+            verifier.verify(d.file(), buffer_offset, &buf[..buffer_len], error_log)?;
+        }
+    }
+}
+
+/// Write all of `buf` at offset `written_bytes`, calling `write` again for whatever a call left
+/// unwritten. Each call is recorded in `write_sizes` and added to `written_bytes` as it returns,
+/// so the count stays exact when a later call fails. A call that writes nothing fails with
+/// `ErrorKind::WriteZero`.
+fn write_buffer(
+    d: &mut impl Write,
+    buf: &[u8],
+    written_bytes: &mut u64,
+    write_sizes: &mut SizeDistribution,
+    last_len: &mut usize,
+    verbose: bool,
+) -> std::io::Result<()> {
+    let mut to_write = buf;
+    while !to_write.is_empty() {
+        let n = d.write(to_write)?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        write_sizes.record(n);
+        // Only log writes that accept less than half of what was asked, and only when the size
+        // changes, so a device that always accepts small chunks does not log every single one.
+        if verbose && n < to_write.len() / 2 && n != *last_len {
+            emit!(
+                "Short write of {} bytes at offset {}, requested {} bytes",
+                n,
+                written_bytes,
+                to_write.len()
+            );
+        }
+        *last_len = n;
+        *written_bytes += n as u64;
+        to_write = &to_write[n..];
+    }
+    Ok(())
+}
+
+/// Write the data from `source` to the device like `write_device`, with --io-uring keeping
+/// several writes in flight. A write only counts once all writes before it completed, so a device
+/// that fills up stops the count where it stopped accepting data, even if writes queued after
+/// completed first.
+#[cfg(target_os = "linux")]
+fn write_device_uring(
+    args: &Args,
+    source: &mut dyn DataSource,
+    buffer_size: u64,
+    start: u64,
+    disk_size: u64,
+    error_log: &mut ErrorLog,
+) -> Result<WriteResult> {
+    let file = retry_open(args, || {
+        Ok(open_device(&args.device, Mode::Write, args.open_flags)?)
+    })?;
+    // A regular file would keep growing rather than running out of space, so stop at its size.
+    let end = if file.metadata()?.is_file() {
+        disk_size
+    } else {
+        u64::MAX
+    };
+    let mut writer = uring::UringWriter::new(
+        file,
+        args.io_uring_depth,
+        buffer_size as usize,
+        args.hugepages,
+    )
+    .context("Unable to set up io_uring, which needs Linux 5.6 or later")?;
+
+    let mut thermal_guard = ThermalGuard::new(&args.device, args.thermal_guard);
+
+    let title = Message::Writing {
+        device: &args.device,
+    }
+    .to_string();
+    emit!("{}", title);
+    let mut progress = Progress::new(Verb::Written, title, disk_size, &progress_options(args));
+
+    progress.resume_from(start);
+
+    let mut written_bytes: u64 = start;
+    let mut queued_bytes: u64 = start;
+    let mut write_sizes = SizeDistribution::default();
+    let mut rate_sampler = slc::RateSampler::new(disk_size);
+    let mut entropy_checker = args.entropy_check.then(|| EntropyChecker::new(start));
+    // Once interrupted, the writes in flight are waited for but no more are queued.
+    let mut interrupted = None;
+    loop {
+        progress.update(written_bytes);
+        rate_sampler.record(written_bytes - start);
+        checkpoint::update(Phase::Write, written_bytes)?;
+        if interrupted.is_none() {
+            interrupted = interrupt::received();
+        }
+        if let Some(thermal_guard) = &mut thermal_guard {
+            thermal_guard.check();
+        }
+
+        while interrupted.is_none() && queued_bytes < end {
+            let Some(mut buf) = writer.spare_buffer() else {
+                break;
+            };
+            let len = (end - queued_bytes).min(buffer_size) as usize;
+            source.fill(&mut buf[..len], queued_bytes);
+            if let Some(checker) = &mut entropy_checker {
+                checker.add(&buf[..len], queued_bytes, args.verbose > 0)?;
+            }
+            writer.write(buf, len, queued_bytes)?;
+            queued_bytes += len as u64;
+        }
+
+        let Some(result) = writer.next_completion()? else {
+            args.sync_mode
+                .sync(writer.file())
+                .context("Error while trying to flush the writes to the device")?;
+            if let Some(interrupted) = interrupted {
+                emit!(
+                    "{}, stopped after writing {} bytes",
+                    interrupted,
+                    written_bytes
+                );
+                checkpoint::interrupted(Phase::Write, written_bytes)?;
+                return Err(interrupted.into());
+            }
+            emit!(
+                "{}",
+                Message::WriteSucceeded {
+                    bytes: written_bytes
+                }
+            );
+            return Ok(WriteResult {
+                written_bytes,
+                write_sizes,
+                speed_cliff: rate_sampler.find_cliff(),
+                verify_duration: None,
+            });
+        };
+        match result {
+            Ok(0) => {
+                error_log.log(written_bytes, "write returned 0 bytes")?;
+                bail!(
+                    "Could not write any data to device. Had successfully written {} bytes.",
+                    written_bytes
+                );
+            }
+            Ok(n) => {
+                write_sizes.record(n);
+                written_bytes += n as u64;
+            }
+            Err(e) if e.raw_os_error() == Some(libc::ENOSPC) => {
+                // The device is full, and the writes queued after this one fail the same way.
+                if let Err(e) = args.sync_mode.sync(writer.file()) {
+                    error_log.io_error(written_bytes, &e)?;
+                    return Err(Error::from(e).context(format!(
+                        "Wrote {} bytes until the device reported it was full, but the final flush to the media failed, so the last blocks written may not be durable",
+                        written_bytes
+                    )));
+                }
+                emit!(
+                    "{}",
+                    Message::WriteSucceeded {
+                        bytes: written_bytes
+                    }
+                );
+                return Ok(WriteResult {
+                    written_bytes,
+                    write_sizes,
+                    speed_cliff: rate_sampler.find_cliff(),
+                    verify_duration: None,
+                });
+            }
+            Err(e) => {
+                error_log.io_error(written_bytes, &e)?;
+                if e.raw_os_error() == Some(libc::EROFS) {
+                    return Err(Error::from(e).context(format!(
+                        "Device went read-only mid-write at offset {}, likely because the kernel detected hardware errors. Check the kernel log with dmesg.",
+                        written_bytes
+                    )));
+                }
+                return Err(Error::from(e).context(format!(
+                    "Encountered error writing to device. Had successfully written {} bytes.",
+                    written_bytes
+                )));
+            }
+        }
+    }
+}
+
+/// How often --entropy-check checks the data being written, in bytes.
+const ENTROPY_CHECK_INTERVAL: u64 = 1 << 30;
+
+/// How many bytes --entropy-check checks at a time, enough for the statistics to be meaningful.
+const ENTROPY_SAMPLE_SIZE: usize = 1 << 16;
+
+/// Checks that the data being written looks random with --entropy-check, in samples collected
+/// from consecutive buffers, since a single buffer can be as small as a sector.
+struct EntropyChecker {
+    sample: Vec<u8>,
+    /// The offset the next sample starts at or after.
+    next_offset: u64,
+}
+
+impl EntropyChecker {
+    fn new(start: u64) -> EntropyChecker {
+        EntropyChecker {
+            sample: Vec::with_capacity(ENTROPY_SAMPLE_SIZE),
+            next_offset: start,
+        }
+    }
+
+    /// Add the buffer about to be written at `offset` to the sample if one is being collected,
+    /// and check the sample once it is complete.
+    fn add(&mut self, buf: &[u8], offset: u64, verbose: bool) -> Result<()> {
+        if offset < self.next_offset {
+            return Ok(());
+        }
+        if self.sample.is_empty() {
+            self.next_offset = offset;
+        }
+        let len = buf.len().min(ENTROPY_SAMPLE_SIZE - self.sample.len());
+        self.sample.extend_from_slice(&buf[..len]);
+        if self.sample.len() < ENTROPY_SAMPLE_SIZE {
+            return Ok(());
+        }
+
+        let sample_offset = self.next_offset;
+        if let Some(failure) = entropy::randomness_failure(&self.sample) {
+            bail!(
+                "The data generated from offset {} does not look random: {}. The random number generator is broken, and verifying the device with its data would be meaningless",
+                sample_offset,
+                failure
+            );
+        }
+        if verbose {
+            emit!(
+                "The {} bytes generated from offset {} look random",
+                self.sample.len(),
+                sample_offset
+            );
+        }
+        self.sample.clear();
+        self.next_offset = offset + ENTROPY_CHECK_INTERVAL;
+        Ok(())
+    }
+}
+
+/// Reads back each buffer right after it is written with --write-verify.
+struct WriteVerifier {
+    d: File,
+    buf: Buffer,
+    duration: Duration,
+}
+
+impl WriteVerifier {
+    /// Sync the buffer `expected` just written at `offset` through `writer`, read it back from the
+    /// device, bypassing the page cache, and fail if it differs.
+    fn verify(
+        &mut self,
+        writer: &File,
+        offset: u64,
+        expected: &[u8],
+        error_log: &mut ErrorLog,
+    ) -> Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let start = Instant::now();
+        writer
+            .sync_data()
+            .context("Error while trying to call fsync")?;
+        drop_cached_range(&self.d, offset, expected.len() as u64)?;
+        let found = &mut self.buf[..expected.len()];
+        if let Err(e) = self.d.read_exact_at(found, offset) {
+            error_log.io_error(offset, &e)?;
+            return Err(Error::from(e).context(format!(
+                "Encountered error reading back the buffer written at offset {}",
+                offset
+            )));
+        }
+        if let Some(i) = compare::first_mismatch(found, expected) {
+            let mismatch = Mismatch {
+                position: offset + i as u64,
+                kind: MismatchKind::Byte {
+                    found: found[i],
+                    expected: expected[i],
+                },
+            };
+            error_log.mismatch(&mismatch)?;
+            return Err(anyhow!("{}", mismatch).context(format!(
+                "Reading back the buffer written at offset {} found different data",
+                offset
+            )));
+        }
+        self.duration += start.elapsed();
+        Ok(())
+    }
+}
+
+/// How long to wait between attempts to open the device with --open-retries.
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Run `open`, which opens or inspects the device, retrying as often as --open-retries allows
+/// while it fails because the device is not there yet or busy.
+fn retry_open<T>(args: &Args, mut open: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        match open() {
+            Err(e) if retries < args.open_retries && is_settling(&e) => {
+                retries += 1;
+                emit!(
+                    "Unable to open device {} ({:#}), retrying ({} of {})",
+                    args.device.display(),
+                    e,
+                    retries,
+                    args.open_retries
+                );
+                std::thread::sleep(OPEN_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether an error opening the device is one a device that was just plugged in can report until
+/// it settles.
+fn is_settling(error: &Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|e| {
+            matches!(
+                e.raw_os_error(),
+                Some(libc::ENOENT | libc::EBUSY | libc::ENXIO | libc::ENOMEDIUM | libc::EAGAIN)
+            )
+        })
+}
+
+/// How long to wait before retrying a failed read, to give a device that was bumped time to settle.
+const READ_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Decide whether to retry reading after an error at the given offset, either automatically if
+/// --read-retry allows more retries, or by asking if running interactively. `retries` is the number
+/// of retries done so far.
+fn should_retry_read(args: &Args, retries: &mut u32, offset: u64) -> Result<bool> {
+    let retry = match args.read_retry {
+        Some(max_retries) => *retries < max_retries,
+        None if !args.tui && std::io::stdin().is_tty() && std::io::stderr().is_tty() => {
+            emit!(
+                "Retry reading from offset {} without rewriting? (y/N)",
+                offset
+            );
+            let response = read_confirmation(None)?;
+            let response = response.trim();
+            response == "y" || response == "Y"
+        }
+        None => false,
+    };
+
+    if retry {
+        *retries += 1;
+        emit!("Retrying read from offset {} (retry {})", offset, retries);
+    }
+    Ok(retry)
+}
+
+/// Drop all cached pages of the given device, so subsequent reads are served by the device rather
+/// than from memory.
+#[cfg(target_os = "linux")]
+fn drop_page_cache(d: &File) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::io::AsRawFd;
+
+    /// `_IO(0x12, 97)` from linux/fs.h
+    const BLKFLSBUF: libc::c_ulong = 0x1261;
+
+    if d.metadata()?.file_type().is_block_device() {
+        let ret = unsafe { libc::ioctl(d.as_raw_fd(), BLKFLSBUF as _) };
+        if ret != 0 {
+            return Err(
+                Error::from(std::io::Error::last_os_error()).context("BLKFLSBUF ioctl failed")
+            );
+        }
+    }
+
+    let ret = unsafe { libc::posix_fadvise(d.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(
+            Error::from(std::io::Error::from_raw_os_error(ret)).context("posix_fadvise failed")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_page_cache(_d: &File) -> Result<()> {
+    emit!(
+        "{} Dropping the page cache is only supported on Linux, reads may be served from memory",
+        color::paint("WARNING:", Color::Yellow)
+    );
+    Ok(())
+}
+
+/// Drop the cached pages of `len` bytes of the device at `offset`. Only pages that have been
+/// written back are dropped.
+#[cfg(target_os = "linux")]
+fn drop_cached_range(d: &File, offset: u64, len: u64) -> Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let ret = unsafe {
+        libc::posix_fadvise(
+            d.as_raw_fd(),
+            offset as libc::off_t,
+            len as libc::off_t,
+            libc::POSIX_FADV_DONTNEED,
+        )
+    };
+    if ret != 0 {
+        return Err(
+            Error::from(std::io::Error::from_raw_os_error(ret)).context("posix_fadvise failed")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cached_range(_d: &File, _offset: u64, _len: u64) -> Result<()> {
+    Ok(())
+}
+
+/// Report the regions of mismatching bytes that were not bad in the last run with
+/// --compare-previous on the device, and store the regions of this run for the next one.
+fn compare_with_previous_run(
+    args: &Args,
+    disk_size: u64,
+    mismatch_regions: &MismatchRegions,
+) -> Result<()> {
+    let dir = match &args.history_dir {
+        Some(dir) => dir.clone(),
+        None => history::default_dir()?,
+    };
+    let serial = sysfs::device_serial(&args.device)?
+        .context("Device has no serial number, which is required for --compare-previous")?;
+    let fingerprint = Fingerprint {
+        disk_size,
+        finished_at: report::unix_time(),
+        bad: mismatch_regions.regions.clone(),
+    };
+
+    match Fingerprint::load(&dir, &serial)? {
+        None => emit!("No previous run of device '{}' to compare with", serial),
+        Some(previous) => {
+            let when = output::timestamp(
+                std::time::UNIX_EPOCH + Duration::from_secs(previous.finished_at),
+            );
+            if previous.disk_size != disk_size {
+                emit!(
+                    "{} device size changed from {} to {} bytes since the previous run",
+                    color::paint("WARNING:", Color::Yellow),
+                    previous.disk_size,
+                    disk_size
                 );
-                last_update = Instant::now();
-                last_update_bytes = read_bytes;
             }
+            let newly_bad = fingerprint.newly_bad(&previous);
+            if newly_bad.is_empty() {
+                emit!("No regions went bad since the previous run at {}", when);
+            } else {
+                emit!(
+                    "{} {} regions went bad since the previous run at {}:",
+                    color::paint("DEGRADED:", Color::Red),
+                    newly_bad.len(),
+                    when
+                );
+                for region in newly_bad.iter().take(MAX_PRINTED_REGIONS) {
+                    emit!(
+                        "  {} to {} ({} bytes)",
+                        region.start,
+                        region.end,
+                        region.end - region.start
+                    );
+                }
+                if newly_bad.len() > MAX_PRINTED_REGIONS {
+                    emit!("  and {} more", newly_bad.len() - MAX_PRINTED_REGIONS);
+                }
+            }
+        }
+    }
+
+    fingerprint.save(&dir, &serial)
+}
+
+/// Read back the given range of the device again from its end to its start for --bidirectional,
+/// a buffer at a time, and return the regions of mismatching bytes. A buffer that cannot be read
+/// counts as mismatching.
+fn read_backward(
+    args: &Args,
+    spec: &KeystreamSpec,
+    buffer_size: u64,
+    range: Range<u64>,
+    error_log: &mut ErrorLog,
+) -> Result<MismatchRegions> {
+    use std::os::unix::fs::FileExt;
+
+    let d = open_device(&args.device, Mode::Read, args.open_flags)?;
+    // Otherwise the end of the device would still be cached from the forward pass.
+    drop_page_cache(&d).context("Error dropping the page cache of the device")?;
+    let mut device_buf = Buffer::new(buffer_size as usize, args.hugepages);
+    let mut expected = Buffer::new(buffer_size as usize, args.hugepages);
+    let mut source = KeystreamSource::new(spec);
+
+    let title = format!("Reading backward from device {}", args.device.display());
+    emit!("{}", title);
+    let mut progress = Progress::new(
+        Verb::Read,
+        title,
+        range.end - range.start,
+        &progress_options(args),
+    );
+
+    let mut bad = Vec::new();
+    let mut end = range.end;
+    while end > range.start {
+        progress.update(range.end - end);
+        if let Some(interrupted) = interrupt::received() {
+            emit!(
+                "{}, stopped reading backward at offset {}",
+                interrupted,
+                end
+            );
+            return Err(interrupted.into());
+        }
+
+        // Buffers start at the same offsets as in the forward pass, so reads stay aligned.
+        let start = ((end - 1) / buffer_size * buffer_size).max(range.start);
+        let len = (end - start) as usize;
+        source.fill(&mut expected[..len], start);
+        match d.read_exact_at(&mut device_buf[..len], start) {
+            Ok(()) => {
+                let found = &device_buf[..len];
+                if let (Some(first), Some(last)) = (
+                    compare::first_mismatch(found, &expected[..len]),
+                    compare::last_mismatch(found, &expected[..len]),
+                ) {
+                    bad.push(start + first as u64..start + last as u64 + 1);
+                }
+            }
+            Err(e) => {
+                error_log.io_error(start, &e)?;
+                bad.push(start..end);
+            }
+        }
+        end = start;
+    }
+    progress.update(range.end - range.start);
+
+    let mut regions = MismatchRegions::default();
+    for range in bad.into_iter().rev() {
+        regions.record(range, buffer_size);
+    }
+    Ok(regions)
+}
+
+/// Compare the regions of mismatching bytes found reading forward and backward, and report where
+/// they differ. Returns whether they agree.
+fn cross_check_passes(forward: &MismatchRegions, backward: &MismatchRegions) -> bool {
+    let only_forward = history::subtract(&forward.regions, &backward.regions);
+    let only_backward = history::subtract(&backward.regions, &forward.regions);
+    if only_forward.is_empty() && only_backward.is_empty() {
+        emit!(
+            "Reading forward and backward found the same {} regions of mismatching bytes",
+            forward.len()
+        );
+        return true;
+    }
+
+    emit!(
+        "{} reading forward and backward found different regions of mismatching bytes. The contents of the device depend on the order it is read in, which indicates caching or address tricks",
+        color::paint("ANOMALY:", Color::Red)
+    );
+    for (direction, regions) in [("forward", &only_forward), ("backward", &only_backward)] {
+        for region in regions.iter().take(MAX_PRINTED_REGIONS) {
+            emit!(
+                "  {} to {} ({} bytes) only mismatches reading {}",
+                region.start,
+                region.end,
+                region.end - region.start,
+                direction
+            );
+        }
+        if regions.len() > MAX_PRINTED_REGIONS {
+            emit!(
+                "  and {} more only mismatching reading {}",
+                regions.len() - MAX_PRINTED_REGIONS,
+                direction
+            );
+        }
+    }
+    false
+}
+
+/// The outcome of reading back the device.
+#[derive(Debug)]
+struct ReadResult {
+    /// The number of bytes that were read in total.
+    read_bytes: u64,
+    /// The number of bytes that were read and matched the expected contents.
+    matched_bytes: u64,
+    /// Set if reading stopped because the device contents did not match the expected contents.
+    mismatch: Option<Mismatch>,
+    /// How many reads returned each number of bytes.
+    read_sizes: SizeDistribution,
+    /// The regions of mismatching bytes found when reading continued past mismatches.
+    mismatch_regions: MismatchRegions,
+    /// The number of bits that differ from the expected contents in the mismatching regions.
+    bit_errors: u64,
+}
+
+/// Number of mismatch regions listed after reading, so a hopelessly broken device does not flood
+/// the output.
+const MAX_PRINTED_REGIONS: usize = 20;
+
+/// Write the blocks of `block_size` bytes overlapping any of the mismatch regions to `path`, one
+/// block number per line, in the format of `badblocks` that `mke2fs -l` reads.
+fn write_bad_blocks(
+    path: &Path,
+    mismatch_regions: &MismatchRegions,
+    block_size: u64,
+) -> Result<()> {
+    let mut blocks = Vec::new();
+    for region in &mismatch_regions.regions {
+        let first = region.start / block_size;
+        let last = (region.end - 1) / block_size;
+        // Regions closer together than a block share one, which is only listed once.
+        let first = match blocks.last() {
+            Some(&(_, previous_last)) if first <= previous_last => previous_last + 1,
+            _ => first,
+        };
+        if first <= last {
+            blocks.push((first, last));
+        }
+    }
+
+    let mut out = std::io::BufWriter::new(
+        File::create(path)
+            .with_context(|| format!("Unable to create bad block list '{}'", path.display()))?,
+    );
+    let mut count = 0;
+    for &(first, last) in &blocks {
+        for block in first..=last {
+            writeln!(out, "{}", block)?;
+        }
+        count += last - first + 1;
+    }
+    out.flush()
+        .with_context(|| format!("Unable to write bad block list '{}'", path.display()))?;
+    emit!(
+        "Wrote {} bad blocks of {} bytes to '{}', use with e.g. mke2fs -b {} -l on the whole device",
+        count,
+        block_size,
+        path.display(),
+        block_size
+    );
+    Ok(())
+}
+
+/// Regions of mismatching bytes, found when reading continues past mismatches.
+#[derive(Debug, Default)]
+struct MismatchRegions {
+    regions: Vec<std::ops::Range<u64>>,
+}
+
+impl MismatchRegions {
+    /// Record mismatching bytes in `range`. If they are within `gap` bytes of the end of the last
+    /// region, that region is extended rather than a new one started.
+    fn record(&mut self, range: std::ops::Range<u64>, gap: u64) {
+        match self.regions.last_mut() {
+            Some(last) if range.start <= last.end + gap => last.end = range.end,
+            _ => self.regions.push(range),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    fn last(&self) -> Option<&std::ops::Range<u64>> {
+        self.regions.last()
+    }
+
+    fn print(&self) {
+        if self.regions.is_empty() {
+            return;
+        }
+        emit!("Found {} regions of mismatching bytes:", self.regions.len());
+        for region in self.regions.iter().take(MAX_PRINTED_REGIONS) {
+            emit!(
+                "  {} to {} ({} bytes)",
+                region.start,
+                region.end,
+                region.end - region.start
+            );
+        }
+        if self.regions.len() > MAX_PRINTED_REGIONS {
+            emit!("  and {} more", self.regions.len() - MAX_PRINTED_REGIONS);
+        }
+    }
+}
+
+/// Counts how many I/O calls returned each number of bytes. A device that consistently returns
+/// fewer bytes than requested can indicate driver or USB bridge issues.
+#[derive(Debug, Default)]
+struct SizeDistribution {
+    counts: std::collections::BTreeMap<usize, u64>,
+}
+
+impl SizeDistribution {
+    fn record(&mut self, size: usize) {
+        *self.counts.entry(size).or_insert(0) += 1;
+    }
+
+    /// Whether all calls returned the same number of bytes, ignoring the final call which may be
+    /// short if the device size is not a multiple of the buffer size.
+    fn is_uniform(&self) -> bool {
+        self.counts.len() <= 1 || (self.counts.len() == 2 && self.counts.values().any(|&c| c == 1))
+    }
+
+    fn merge(&mut self, other: &SizeDistribution) {
+        for (&size, &count) in other.counts.iter() {
+            *self.counts.entry(size).or_insert(0) += count;
+        }
+    }
+
+    /// Print the distribution, with `operation` naming the kind of call, e.g. "read".
+    fn print(&self, operation: &str) {
+        emit!("Distribution of {} sizes:", operation);
+        for (size, count) in self.counts.iter().rev() {
+            emit!("  {} bytes: {} {}s", size, count, operation);
+        }
+    }
+}
+
+/// A position on the device that does not have the expected contents.
+#[derive(Debug)]
+struct Mismatch {
+    position: u64,
+    kind: MismatchKind,
+}
+
+#[derive(Debug)]
+enum MismatchKind {
+    /// The byte at the position differs from the keystream.
+    Byte { found: u8, expected: u8 },
+    /// The block starting at the position does not match its hash from --checksum-in.
+    Checksum,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.kind {
+            MismatchKind::Byte { found, expected } => Message::ByteMismatch {
+                position: self.position,
+                found,
+                expected,
+            }
+            .fmt(f),
+            MismatchKind::Checksum => Message::ChecksumMismatch {
+                position: self.position,
+            }
+            .fmt(f),
+        }
+    }
+}
+
+/// Log of every I/O error and mismatch encountered, written if --error-log is given.
+///
+/// Each event is written as a single line of space-separated key=value pairs.
+struct ErrorLog {
+    file: Option<File>,
+}
+
+impl ErrorLog {
+    fn open(path: Option<&Path>) -> Result<ErrorLog> {
+        let file = match path {
+            Some(path) => Some(
+                File::create(path)
+                    .with_context(|| format!("Unable to create error log '{}'", path.display()))?,
+            ),
+            None => None,
+        };
+        Ok(ErrorLog { file })
+    }
+
+    fn log(&mut self, offset: u64, message: &str) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            writeln!(
+                file,
+                "event=io_error offset={} message={:?}",
+                offset, message
+            )
+            .context("Error writing to error log")?;
+        }
+        Ok(())
+    }
+
+    fn io_error(&mut self, offset: u64, e: &std::io::Error) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            let errno = e
+                .raw_os_error()
+                .map(|errno| errno.to_string())
+                .unwrap_or_else(|| "none".to_string());
+            writeln!(
+                file,
+                "event=io_error offset={} errno={} message={:?}",
+                offset,
+                errno,
+                e.to_string()
+            )
+            .context("Error writing to error log")?;
+        }
+        Ok(())
+    }
+
+    fn mismatch(&mut self, mismatch: &Mismatch) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            match mismatch.kind {
+                MismatchKind::Byte { found, expected } => writeln!(
+                    file,
+                    "event=mismatch offset={} found=0x{:02x} expected=0x{:02x}",
+                    mismatch.position, found, expected
+                ),
+                MismatchKind::Checksum => {
+                    writeln!(file, "event=checksum_mismatch offset={}", mismatch.position)
+                }
+            }
+            .context("Error writing to error log")?;
+        }
+        Ok(())
+    }
+}
+
+/// Read back the given range of the device and compare it with the data from `source`, with seeks
+/// aligned to `alignment` bytes. Bytes before the range are taken as already verified.
+fn read_device(
+    args: &Args,
+    source: &mut dyn DataSource,
+    buffer_size: u64,
+    alignment: u64,
+    range: Range<u64>,
+    error_log: &mut ErrorLog,
+) -> Result<ReadResult> {
+    if args.cache == CacheMode::Cold {
+        let d = open_device(&args.device, Mode::Read, args.open_flags)?;
+        drop_page_cache(&d).context("Error dropping the page cache of the device")?;
+    }
+    #[cfg(target_os = "linux")]
+    if args.io_uring {
+        let end = range.end;
+        return read_source(
+            args,
+            || {
+                uring::UringReader::new(
+                    open_device(&args.device, Mode::Read, args.open_flags)?,
+                    args.io_uring_depth,
+                    buffer_size as usize,
+                    end,
+                    args.hugepages,
+                )
+            },
+            source,
+            buffer_size,
+            alignment,
+            range,
+            error_log,
+        );
+    }
+    read_source(
+        args,
+        || open_handle(args, Mode::Read, args.open_flags),
+        source,
+        buffer_size,
+        alignment,
+        range,
+        error_log,
+    )
+}
+
+/// Read back the given range and compare it with the data from `source`, like `read_device` but
+/// from anything that can be read and seeked. `open` opens what is read from the start, and is
+/// called again to reopen it when retrying after a read error.
+fn read_source<D: Read + Seek>(
+    args: &Args,
+    open: impl Fn() -> std::io::Result<D>,
+    source: &mut dyn DataSource,
+    buffer_size: u64,
+    alignment: u64,
+    range: Range<u64>,
+    error_log: &mut ErrorLog,
+) -> Result<ReadResult> {
+    let disk_size = range.end;
+    let mut d = retry_open(args, || Ok(open()?))?;
+    d.seek(SeekFrom::Start(range.start))?;
+    let mut device_buf = Buffer::new(buffer_size as usize, args.hugepages);
+    let mut rng_buf = Buffer::new(buffer_size as usize, args.hugepages);
+    let mut checksums = match &args.checksum_in {
+        Some(path) => Some(ChecksumVerifier::open(path)?),
+        None => None,
+    };
+
+    let mut thermal_guard = ThermalGuard::new(&args.device, args.thermal_guard);
+
+    let title = Message::Reading {
+        device: &args.device,
+    }
+    .to_string();
+    emit!("{}", title);
+    let mut progress = Progress::new(Verb::Read, title, disk_size, &progress_options(args));
+
+    progress.resume_from(range.start);
+
+    let mut read_bytes: u64 = range.start;
+    let mut first_mismatch: Option<Mismatch> = None;
+    let mut read_sizes = SizeDistribution::default();
+    let mut mismatch_regions = MismatchRegions::default();
+    let mut bit_errors = 0;
+    let mut retries = 0;
+    let mut last_len = device_buf.len();
+    loop {
+        progress.update(read_bytes);
+        checkpoint::update(Phase::Read, read_bytes)?;
+        if let Some(interrupted) = interrupt::received() {
+            emit!(
+                "{}, stopped after reading {} bytes, of which {} matched",
+                interrupted,
+                read_bytes,
+                first_mismatch.as_ref().map_or(read_bytes, |m| m.position)
+            );
+            checkpoint::interrupted(Phase::Read, read_bytes)?;
+            return Err(interrupted.into());
+        }
+        if let Some(thermal_guard) = &mut thermal_guard {
+            thermal_guard.check();
         }
 
-        let len = match d.read(&mut device_buf) {
+        let result = if read_bytes >= disk_size {
+            Ok(0)
+        } else {
+            let max_len = (disk_size - read_bytes).min(device_buf.len() as u64) as usize;
+            d.read(&mut device_buf[..max_len])
+        };
+        let len = match result {
             Ok(0) => {
-                eprintln!("Successfully read and matched {} bytes", read_bytes);
-                return read_bytes
-                    .try_into()
-                    .context("usize could not be converted to u64");
+                if let Some(position) = checksums.as_mut().and_then(|c| c.finish()) {
+                    let mismatch = Mismatch {
+                        position,
+                        kind: MismatchKind::Checksum,
+                    };
+                    error_log.mismatch(&mismatch)?;
+                    return Ok(ReadResult {
+                        read_bytes,
+                        matched_bytes: position,
+                        mismatch: Some(mismatch),
+                        read_sizes,
+                        mismatch_regions,
+                        bit_errors,
+                    });
+                }
+                if let Some(mismatch) = first_mismatch {
+                    emit!("Finished reading {} bytes", read_bytes);
+                    return Ok(ReadResult {
+                        read_bytes,
+                        matched_bytes: mismatch.position,
+                        mismatch: Some(mismatch),
+                        read_sizes,
+                        mismatch_regions,
+                        bit_errors,
+                    });
+                }
+                emit!("{}", Message::ReadSucceeded { bytes: read_bytes });
+                return Ok(ReadResult {
+                    read_bytes,
+                    matched_bytes: read_bytes,
+                    mismatch: None,
+                    read_sizes,
+                    mismatch_regions,
+                    bit_errors,
+                });
             }
             Ok(x) => x,
             Err(e) => {
+                error_log.io_error(read_bytes, &e)?;
+                emit!("Error reading device at offset {}: {}", read_bytes, e);
+                if !should_retry_read(args, &mut retries, read_bytes)? {
+                    return Err(Error::from(e).context(format!(
+                        "Encountered error reading device. Had successfully read {} bytes.",
+                        read_bytes
+                    )));
+                }
+
+                // The keystream only advances on successful reads, so it is already at the right
+                // position and only the device needs to be reopened where we left off. The seek is
+                // aligned, so the part of the block that was already read is skipped by reading it.
+                std::thread::sleep(READ_RETRY_DELAY);
+                d = open().context("Error reopening device")?;
+                let aligned_offset = read_bytes - read_bytes % alignment;
+                d.seek(SeekFrom::Start(aligned_offset))?;
+                let mut skipped = vec![0; (read_bytes - aligned_offset) as usize];
+                d.read_exact(&mut skipped)
+                    .context("Error reading device after reopening")?;
+                continue;
+            }
+        };
+        read_sizes.record(len);
+        // Only log when the size changes, so a device that always returns short reads does not
+        // log every single one.
+        if args.verbose > 0 && len < device_buf.len() && len != last_len {
+            emit!(
+                "Short read of {} bytes at offset {}, requested {} bytes",
+                len,
+                read_bytes,
+                device_buf.len()
+            );
+        }
+        last_len = len;
+
+        if let Some(checksums) = &mut checksums {
+            if let Some(position) = checksums.update(&device_buf[..len])? {
+                let mismatch = Mismatch {
+                    position,
+                    kind: MismatchKind::Checksum,
+                };
+                error_log.mismatch(&mismatch)?;
+                progress.error(mismatch.to_string());
+                return Ok(ReadResult {
+                    read_bytes: read_bytes + len as u64,
+                    matched_bytes: position,
+                    mismatch: Some(mismatch),
+                    read_sizes,
+                    mismatch_regions,
+                    bit_errors,
+                });
+            }
+            read_bytes += len as u64;
+            continue;
+        }
+
+        source.fill(&mut rng_buf[..len], read_bytes);
+
+        if let Some(i) = compare::first_mismatch(&device_buf[..len], &rng_buf[..len]) {
+            let mismatch = Mismatch {
+                position: read_bytes + i as u64,
+                kind: MismatchKind::Byte {
+                    found: device_buf[i],
+                    expected: rng_buf[i],
+                },
+            };
+            error_log.mismatch(&mismatch)?;
+            progress.error(mismatch.to_string());
+
+            let resume = args.continue_on_error
+                || (args.pause_on_mismatch
+                    && pause_on_mismatch(
+                        &mut d,
+                        read_bytes,
+                        &mut device_buf[..len],
+                        &rng_buf[..len],
+                        &mismatch,
+                        error_log,
+                    )?);
+
+            let last = compare::last_mismatch(&device_buf[..len], &rng_buf[..len])
+                .context("Unreachable. Unable to find mismatching bytes.")?;
+            mismatch_regions.record(mismatch.position..read_bytes + last as u64 + 1, buffer_size);
+            bit_errors += device_buf[..len]
+                .iter()
+                .zip(&rng_buf[..len])
+                .map(|(found, expected)| u64::from((found ^ expected).count_ones()))
+                .sum::<u64>();
+            let limit_reached = args
+                .abort_after_errors
+                .is_some_and(|limit| mismatch_regions.len() >= limit);
+            if resume && limit_reached {
+                emit!(
+                    "Stopping after {} regions of mismatching bytes because of --abort-after-errors",
+                    mismatch_regions.len()
+                );
+            }
+            let fake_region = match (args.fail_fast_threshold, mismatch_regions.last()) {
+                (Some(threshold), Some(last)) if last.end - last.start >= threshold => {
+                    Some(last.clone())
+                }
+                _ => None,
+            };
+            if let (true, Some(region)) = (resume, &fake_region) {
+                emit!(
+                    "Stopping after {} contiguous mismatching bytes because of --fail-fast-threshold. The device is likely fake, with an effective capacity of {} bytes",
+                    region.end - region.start,
+                    region.start
+                );
+            }
+
+            if !resume || limit_reached || fake_region.is_some() {
+                let mismatch = first_mismatch.unwrap_or(mismatch);
+                return Ok(ReadResult {
+                    read_bytes: read_bytes + len as u64,
+                    matched_bytes: mismatch.position,
+                    mismatch: Some(mismatch),
+                    read_sizes,
+                    mismatch_regions,
+                    bit_errors,
+                });
+            }
+
+            if first_mismatch.is_none() {
+                first_mismatch = Some(mismatch);
+            }
+        }
+
+        read_bytes += len as u64;
+    }
+}
+
+/// Write the device while verifying each block once `distance` more bytes have been written
+/// after it, as described for --cache-bust-distance.
+fn interleaved_write_read(
+    args: &Args,
+    spec: &KeystreamSpec,
+    buffer_size: u64,
+    distance: u64,
+    disk_size: u64,
+    error_log: &mut ErrorLog,
+) -> Result<()> {
+    // Blocks are verified a buffer at a time, so the distance is a whole number of buffers.
+    let distance = distance.div_ceil(buffer_size).max(1) * buffer_size;
+    if distance > disk_size / 2 {
+        bail!(
+            "--cache-bust-distance of {} bytes must be at most half the device size of {} bytes",
+            distance,
+            disk_size
+        );
+    }
+
+    let mut writer = open_device(&args.device, Mode::Write, args.open_flags)?;
+    let mut reader = Verifier {
+        d: open_device(&args.device, Mode::Read, args.open_flags)?,
+        rng: spec.keystream(),
+        device_buf: Buffer::new(buffer_size as usize, args.hugepages),
+        rng_buf: Buffer::new(buffer_size as usize, args.hugepages),
+        verified_bytes: 0,
+    };
+    drop_page_cache(&reader.d)?;
+    let mut rng = spec.keystream();
+    let mut buf = Buffer::new(buffer_size as usize, args.hugepages);
+
+    let title = format!(
+        "Writing to and verifying device {}, {} bytes behind",
+        args.device.display(),
+        distance
+    );
+    emit!("{}", title);
+    let mut progress = Progress::new(Verb::Written, title, disk_size, &progress_options(args));
+
+    let mut written_bytes = 0;
+    while written_bytes < disk_size {
+        progress.update(written_bytes);
+        let len = (disk_size - written_bytes).min(buffer_size) as usize;
+        rng.try_fill_bytes(&mut buf[..len])
+            .context("Error generating random bytes")?;
+        if let Err(e) = writer.write_all(&buf[..len]) {
+            error_log.io_error(written_bytes, &e)?;
+            return Err(Error::from(e).context(format!(
+                "Encountered error writing to device. Had successfully written {} bytes and verified {} bytes.",
+                written_bytes, reader.verified_bytes
+            )));
+        }
+        // Syncing each time another `distance` bytes have been written ensures the blocks
+        // verified until the next sync are on the device.
+        if (written_bytes + len as u64) / distance > written_bytes / distance {
+            writer
+                .sync_data()
+                .context("Error while trying to call fsync")?;
+        }
+        written_bytes += len as u64;
+
+        while reader.verified_bytes + distance + buffer_size <= written_bytes {
+            reader.verify(buffer_size, error_log)?;
+        }
+    }
+    writer
+        .sync_data()
+        .context("Error while trying to call fsync")?;
+    emit!(
+        "{}",
+        Message::WriteSucceeded {
+            bytes: written_bytes
+        }
+    );
+
+    emit!(
+        "Rewriting the first {} bytes to push the last {} bytes out of any cache",
+        distance,
+        disk_size - reader.verified_bytes
+    );
+    writer.seek(SeekFrom::Start(0))?;
+    let mut rng = spec.keystream();
+    let mut rewritten_bytes = 0;
+    while rewritten_bytes < distance {
+        let len = (distance - rewritten_bytes).min(buffer_size) as usize;
+        rng.try_fill_bytes(&mut buf[..len])
+            .context("Error generating random bytes")?;
+        if let Err(e) = writer.write_all(&buf[..len]) {
+            error_log.io_error(rewritten_bytes, &e)?;
+            return Err(Error::from(e).context("Encountered error rewriting start of device"));
+        }
+        rewritten_bytes += len as u64;
+    }
+    writer
+        .sync_data()
+        .context("Error while trying to call fsync")?;
+
+    while reader.verified_bytes < disk_size {
+        let len = (disk_size - reader.verified_bytes).min(buffer_size);
+        reader.verify(len, error_log)?;
+    }
+    emit!(
+        "Successfully verified {} bytes, each read back after another {} bytes were written",
+        reader.verified_bytes,
+        distance
+    );
+    Ok(())
+}
+
+/// Reads back and verifies the device in order for --cache-bust-distance.
+struct Verifier {
+    d: File,
+    rng: Keystream,
+    device_buf: Buffer,
+    rng_buf: Buffer,
+    verified_bytes: u64,
+}
+
+impl Verifier {
+    /// Verify the next `len` bytes, bypassing the page cache, and fail if they do not match.
+    fn verify(&mut self, len: u64, error_log: &mut ErrorLog) -> Result<()> {
+        let offset = self.verified_bytes;
+        let len = len as usize;
+        drop_cached_range(&self.d, offset, len as u64)?;
+        self.d.seek(SeekFrom::Start(offset))?;
+        if let Err(e) = self.d.read_exact(&mut self.device_buf[..len]) {
+            error_log.io_error(offset, &e)?;
+            return Err(Error::from(e).context(format!(
+                "Encountered error reading device. Had successfully verified {} bytes.",
+                offset
+            )));
+        }
+        self.rng.try_fill_bytes(&mut self.rng_buf[..len])?;
+        if let Some(i) = compare::first_mismatch(&self.device_buf[..len], &self.rng_buf[..len]) {
+            let mismatch = Mismatch {
+                position: offset + i as u64,
+                kind: MismatchKind::Byte {
+                    found: self.device_buf[i],
+                    expected: self.rng_buf[i],
+                },
+            };
+            error_log.mismatch(&mismatch)?;
+            emit!(
+                "{}",
+                Message::Verified {
+                    bytes: mismatch.position
+                }
+            );
+            bail!("{}", mismatch);
+        }
+        self.verified_bytes += len as u64;
+        Ok(())
+    }
+}
+
+/// The name of the subdirectory of --output-dir for a run on `device` started at `time`.
+fn run_dir_name(device: &Path, time: SystemTime) -> String {
+    // The timestamp without separators or milliseconds, e.g. 20210304T050607Z.
+    let timestamp: String = output::timestamp(time)
+        .chars()
+        .filter(|c| *c != '-' && *c != ':')
+        .take_while(|c| *c != '.')
+        .collect();
+    let device = device
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "device".to_string());
+    format!("{}Z-{}", timestamp, device)
+}
+
+/// Create a new subdirectory of `dir` called `name`, or if runs started in the same second, with
+/// a suffix like `-2` to keep their artifacts apart.
+fn create_run_dir(dir: &Path, name: &str) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Unable to create output directory '{}'", dir.display()))?;
+    let mut run_dir = dir.join(name);
+    for attempt in 2.. {
+        match std::fs::create_dir(&run_dir) {
+            Ok(()) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                run_dir = dir.join(format!("{}-{}", name, attempt));
+            }
+            Err(e) => {
+                return Err(Error::from(e).context(format!(
+                    "Unable to create output directory '{}'",
+                    run_dir.display()
+                )))
+            }
+        }
+    }
+    Ok(run_dir)
+}
+
+/// Write each --region with its own keystream, then read them all back and report the result for
+/// each.
+fn test_regions(args: &Args, error_log: &mut ErrorLog) -> Result<()> {
+    let block_size = get_block_size(&args.device)?;
+    let logical_sector_size = get_logical_sector_size(&args.device)?;
+    let disk_size = get_disk_size(&args.device)?;
+    region::validate(&args.regions, disk_size)?;
+
+    let (buffer_size, _) = io_sizes(
+        args,
+        args.buffer_size.unwrap_or(block_size),
+        block_size,
+        logical_sector_size,
+        disk_size,
+    )?;
+    let total_bytes: u64 = args.regions.iter().map(|region| region.length).sum();
+
+    if args.write {
+        check_overwrite_confirmed(args)?;
+        let mut d = open_device(&args.device, Mode::Write, args.open_flags)?;
+        let mut buf = Buffer::new(buffer_size as usize, args.hugepages);
+        let title = format!("Writing regions to device {}", args.device.display());
+        emit!("{}", title);
+        let mut progress =
+            Progress::new(Verb::Written, title, total_bytes, &progress_options(args));
+        let mut written_bytes = 0;
+        for region in &args.regions {
+            let mut rng = region.keystream_spec().keystream();
+            d.seek(SeekFrom::Start(region.offset))?;
+            let mut remaining = region.length;
+            while remaining > 0 {
+                progress.update(written_bytes);
+                let len = remaining.min(buffer_size) as usize;
+                rng.try_fill_bytes(&mut buf[..len])
+                    .context("Error generating random bytes")?;
+                let offset = region.end() - remaining;
+                if let Err(e) = d.write_all(&buf[..len]) {
+                    error_log.io_error(offset, &e)?;
+                    return Err(Error::from(e).context(format!(
+                        "Error writing region at offset {} at offset {}",
+                        region.offset, offset
+                    )));
+                }
+                remaining -= len as u64;
+                written_bytes += len as u64;
+            }
+        }
+        args.sync_mode
+            .sync(&d)
+            .context("Error while trying to flush the writes to the device")?;
+        emit!(
+            "Successfully wrote {} bytes to {} regions",
+            written_bytes,
+            args.regions.len()
+        );
+    }
+
+    if !args.read {
+        return Ok(());
+    }
+
+    let mut d = open_device(&args.device, Mode::Read, args.open_flags)?;
+    if args.cache == CacheMode::Cold {
+        drop_page_cache(&d).context("Error dropping the page cache of the device")?;
+    }
+    let mut device_buf = Buffer::new(buffer_size as usize, args.hugepages);
+    let mut rng_buf = Buffer::new(buffer_size as usize, args.hugepages);
+    let title = format!("Reading regions from device {}", args.device.display());
+    emit!("{}", title);
+    let mut progress = Progress::new(Verb::Read, title, total_bytes, &progress_options(args));
+    let mut read_bytes = 0;
+    let mut mismatches = Vec::new();
+    for region in &args.regions {
+        let mut rng = region.keystream_spec().keystream();
+        d.seek(SeekFrom::Start(region.offset))?;
+        let mut remaining = region.length;
+        let mut mismatch = None;
+        while remaining > 0 {
+            progress.update(read_bytes);
+            let len = remaining.min(buffer_size) as usize;
+            let offset = region.end() - remaining;
+            if let Err(e) = d.read_exact(&mut device_buf[..len]) {
+                error_log.io_error(offset, &e)?;
                 return Err(Error::from(e).context(format!(
-                    "Encountered error reading device. Had successfully read {} bytes.",
-                    read_bytes
+                    "Error reading region at offset {} at offset {}",
+                    region.offset, offset
                 )));
             }
+            rng.try_fill_bytes(&mut rng_buf[..len])?;
+            if let Some(i) = compare::first_mismatch(&device_buf[..len], &rng_buf[..len]) {
+                let found = Mismatch {
+                    position: offset + i as u64,
+                    kind: MismatchKind::Byte {
+                        found: device_buf[i],
+                        expected: rng_buf[i],
+                    },
+                };
+                error_log.mismatch(&found)?;
+                read_bytes += remaining;
+                mismatch = Some(found);
+                break;
+            }
+            remaining -= len as u64;
+            read_bytes += len as u64;
+        }
+        mismatches.push(mismatch);
+    }
+
+    for (region, mismatch) in args.regions.iter().zip(&mismatches) {
+        match mismatch {
+            Some(mismatch) => emit!(
+                "{} region at offset {} of {} bytes with seed {}: {}",
+                color::paint("FAIL:", Color::Red),
+                region.offset,
+                region.length,
+                region.seed,
+                mismatch
+            ),
+            None => emit!(
+                "{} region at offset {} of {} bytes with seed {}",
+                color::paint("PASS:", Color::Green),
+                region.offset,
+                region.length,
+                region.seed
+            ),
+        }
+    }
+
+    let failed = mismatches.iter().filter(|m| m.is_some()).count();
+    if failed > 0 {
+        bail!("{} of {} regions failed", failed, args.regions.len());
+    }
+    Ok(())
+}
+
+/// Running totals of a --soak run.
+#[derive(Debug, Default)]
+struct SoakTally {
+    cycles: u64,
+    failures: u64,
+    written_bytes: u64,
+    read_bytes: u64,
+}
+
+impl SoakTally {
+    fn print(&self, elapsed: Duration) {
+        emit!(
+            "Soak test: {} cycles completed, {} failed, {} bytes written, {} bytes read, {} elapsed",
+            self.cycles,
+            self.failures,
+            self.written_bytes,
+            self.read_bytes,
+            progress::format_duration(elapsed.as_secs())
+        );
+    }
+}
+
+/// Repeat write and read cycles for --soak until interrupted. Mismatches and short writes or reads
+/// are counted as failures and the test continues, but I/O errors end it.
+fn soak(
+    args: &Args,
+    spec: &KeystreamSpec,
+    buffer_size: u64,
+    alignment: u64,
+    disk_size: u64,
+    error_log: &mut ErrorLog,
+) -> Result<()> {
+    use sha2::Digest;
+    use std::sync::{Arc, Mutex};
+
+    let start = Instant::now();
+    let tally = Arc::new(Mutex::new(SoakTally::default()));
+
+    let mut signals = signal_hook::iterator::Signals::new([
+        signal_hook::consts::SIGINT,
+        signal_hook::consts::SIGTERM,
+    ])
+    .context("Error installing signal handler")?;
+    let signal_tally = tally.clone();
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            emit!("Interrupted, stopping the soak test");
+            let tally = signal_tally.lock().unwrap_or_else(|e| e.into_inner());
+            tally.print(start.elapsed());
+            std::process::exit(if tally.failures > 0 { 1 } else { 128 + signal });
+        }
+    });
+
+    for cycle in 1u64.. {
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(spec.key);
+        hasher.update(cycle.to_le_bytes());
+        let cycle_spec = KeystreamSpec {
+            key: hasher.finalize().into(),
+            ..spec.clone()
+        };
+        emit!(
+            "Starting soak cycle {} with raw seed {}",
+            cycle,
+            hex::encode(cycle_spec.key)
+        );
+
+        let written_bytes = write_device(
+            args,
+            &mut *data_source(args, &cycle_spec, buffer_size),
+            buffer_size,
+            0,
+            disk_size,
+            error_log,
+        )
+        .with_context(|| format!("Error writing to device '{}'", args.device.display()))?
+        .written_bytes;
+        let result = read_device(
+            args,
+            &mut *data_source(args, &cycle_spec, buffer_size),
+            buffer_size,
+            alignment,
+            0..written_bytes,
+            error_log,
+        )
+        .with_context(|| format!("Error reading from device '{}'", args.device.display()))?;
+
+        let failure = if let Some(mismatch) = &result.mismatch {
+            Some(mismatch.to_string())
+        } else if written_bytes != disk_size {
+            Some(format!(
+                "Wrote {} bytes, but expected disk size to be {} bytes",
+                written_bytes, disk_size
+            ))
+        } else if result.matched_bytes != disk_size {
+            Some(format!(
+                "Read {} bytes, but expected disk size to be {} bytes",
+                result.matched_bytes, disk_size
+            ))
+        } else {
+            None
         };
-        rng.try_fill_bytes(&mut rng_buf[..len])?;
-
-        if device_buf[..len] != rng_buf[..len] {
-            for i in 0..len {
-                let a = &device_buf[i];
-                let b = &rng_buf[i];
-                if a != b {
-                    bail!("Device found byte that does not match expected contents on position {}. Device had contents 0x{:02x}, but expected 0x{:02x}.", read_bytes + i, a, b);
+
+        let mut tally = tally.lock().unwrap_or_else(|e| e.into_inner());
+        metrics::cycle_finished(failure.is_none());
+        tally.cycles += 1;
+        tally.written_bytes += written_bytes;
+        tally.read_bytes += result.read_bytes;
+        if let Some(failure) = failure {
+            tally.failures += 1;
+            emit!(
+                "{} soak cycle {}: {}",
+                color::paint("FAIL:", Color::Red),
+                cycle,
+                failure
+            );
+        }
+        tally.print(start.elapsed());
+    }
+
+    Ok(())
+}
+
+/// Verify the blocks containing the offsets listed in the given file for --offsets-in.
+fn verify_offsets(
+    args: &Args,
+    spec: &KeystreamSpec,
+    path: &Path,
+    buffer_size: u64,
+    disk_size: u64,
+    error_log: &mut ErrorLog,
+) -> Result<()> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read offsets file '{}'", path.display()))?;
+    let mut offsets = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let offset = match line.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => line.parse(),
+        }
+        .with_context(|| format!("Invalid offset on line {} of '{}'", i + 1, path.display()))?;
+        offsets.push(offset);
+    }
+
+    let mut d = open_device(&args.device, Mode::Read, args.open_flags)?;
+    if args.cache == CacheMode::Cold {
+        drop_page_cache(&d).context("Error dropping the page cache of the device")?;
+    }
+
+    let mut failed = 0;
+    for offset in offsets.iter().copied() {
+        if offset >= disk_size {
+            emit!(
+                "{} offset {} is beyond the end of the device",
+                color::paint("FAIL:", Color::Red),
+                offset
+            );
+            failed += 1;
+            continue;
+        }
+
+        let block_offset = offset - offset % buffer_size;
+        let len = buffer_size.min(disk_size - block_offset) as usize;
+        let mut device_buf = vec![0; len];
+        d.seek(SeekFrom::Start(block_offset))?;
+        if let Err(e) = d.read_exact(&mut device_buf) {
+            error_log.io_error(block_offset, &e)?;
+            emit!(
+                "{} offset {}: error reading block at {}: {}",
+                color::paint("FAIL:", Color::Red),
+                offset,
+                block_offset,
+                e
+            );
+            failed += 1;
+            continue;
+        }
+
+        let expected = spec.bytes_at(block_offset, len);
+        match device_buf.iter().zip(&expected).position(|(a, b)| a != b) {
+            Some(i) => {
+                let mismatch = Mismatch {
+                    position: block_offset + i as u64,
+                    kind: MismatchKind::Byte {
+                        found: device_buf[i],
+                        expected: expected[i],
+                    },
+                };
+                error_log.mismatch(&mismatch)?;
+                emit!(
+                    "{} offset {}: block at {} first mismatches at position {}",
+                    color::paint("FAIL:", Color::Red),
+                    offset,
+                    block_offset,
+                    mismatch.position
+                );
+                failed += 1;
+            }
+            None => emit!(
+                "{} offset {}: block at {} matches",
+                color::paint("PASS:", Color::Green),
+                offset,
+                block_offset
+            ),
+        }
+    }
+
+    if failed > 0 {
+        bail!(
+            "{} of {} offsets failed verification",
+            failed,
+            offsets.len()
+        );
+    }
+    emit!("All {} offsets verified successfully", offsets.len());
+    Ok(())
+}
+
+/// The outcome of one thread of a parallel read.
+struct RangeResult {
+    /// The number of bytes that were read from the start of the range.
+    read_bytes: u64,
+    mismatch: Option<Mismatch>,
+    read_sizes: SizeDistribution,
+}
+
+/// Read back the first `disk_size` bytes of the device with `readers` threads, each verifying a
+/// disjoint range against the keystream at that offset.
+fn read_device_parallel(
+    args: &Args,
+    spec: &KeystreamSpec,
+    buffer_size: u64,
+    disk_size: u64,
+    readers: usize,
+    error_log: &mut ErrorLog,
+) -> Result<ReadResult> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    if args.cache == CacheMode::Cold {
+        let d = open_device(&args.device, Mode::Read, args.open_flags)?;
+        drop_page_cache(&d).context("Error dropping the page cache of the device")?;
+    }
+
+    // Ranges start on buffer boundaries, which are also word boundaries of the keystream if the
+    // buffer size is a multiple of 4, so each thread can generate its part of the keystream.
+    let buffers = disk_size.div_ceil(buffer_size);
+    let buffers_per_reader = buffers.div_ceil(readers as u64).max(1);
+    let ranges: Vec<std::ops::Range<u64>> = (0..readers as u64)
+        .map(|i| {
+            let start = (i * buffers_per_reader * buffer_size).min(disk_size);
+            let end = ((i + 1) * buffers_per_reader * buffer_size).min(disk_size);
+            start..end
+        })
+        .filter(|range| !range.is_empty())
+        .collect();
+
+    let title = format!(
+        "Reading from device {} with {} threads",
+        args.device.display(),
+        ranges.len()
+    );
+    emit!("{}", title);
+    let mut progress = Progress::new(Verb::Read, title, disk_size, &progress_options(args));
+    let total_read = AtomicU64::new(0);
+
+    let results: Vec<Result<RangeResult>> = std::thread::scope(|scope| -> Result<_> {
+        let handles: Vec<_> = ranges
+            .iter()
+            .map(|range| {
+                let range = range.clone();
+                let total_read = &total_read;
+                scope.spawn(move || -> Result<RangeResult> {
+                    let mut d = open_device(&args.device, Mode::Read, args.open_flags)?;
+                    d.seek(SeekFrom::Start(range.start))?;
+                    let mut rng = spec.keystream_at(range.start);
+                    let mut device_buf = Buffer::new(buffer_size as usize, args.hugepages);
+                    let mut rng_buf = Buffer::new(buffer_size as usize, args.hugepages);
+                    let mut read_sizes = SizeDistribution::default();
+                    let mut read_bytes: u64 = 0;
+                    let range_len = range.end - range.start;
+
+                    while read_bytes < range_len {
+                        if let Some(interrupted) = interrupt::received() {
+                            return Err(interrupted.into());
+                        }
+                        let max_len = (range_len - read_bytes).min(buffer_size) as usize;
+                        let len = d.read(&mut device_buf[..max_len]).with_context(|| {
+                            format!(
+                                "Encountered error reading device at offset {}",
+                                range.start + read_bytes
+                            )
+                        })?;
+                        if len == 0 {
+                            break;
+                        }
+                        read_sizes.record(len);
+                        rng.try_fill_bytes(&mut rng_buf[..len])?;
+                        if let Some(i) =
+                            compare::first_mismatch(&device_buf[..len], &rng_buf[..len])
+                        {
+                            return Ok(RangeResult {
+                                read_bytes: read_bytes + len as u64,
+                                mismatch: Some(Mismatch {
+                                    position: range.start + read_bytes + i as u64,
+                                    kind: MismatchKind::Byte {
+                                        found: device_buf[i],
+                                        expected: rng_buf[i],
+                                    },
+                                }),
+                                read_sizes,
+                            });
+                        }
+                        read_bytes += len as u64;
+                        total_read.fetch_add(len as u64, Ordering::Relaxed);
+                    }
+
+                    Ok(RangeResult {
+                        read_bytes,
+                        mismatch: None,
+                        read_sizes,
+                    })
+                })
+            })
+            .collect();
+
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            progress.update(total_read.load(Ordering::Relaxed));
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("Reader thread panicked")))
+            })
+            .collect())
+    })?;
+    if let Some(interrupted) = interrupt::received() {
+        emit!(
+            "{}, stopped after reading {} bytes",
+            interrupted,
+            total_read.load(Ordering::Relaxed)
+        );
+        return Err(interrupted.into());
+    }
+
+    let mut read_bytes = 0;
+    let mut matched_bytes = disk_size;
+    let mut mismatch: Option<Mismatch> = None;
+    let mut read_sizes = SizeDistribution::default();
+    for (range, result) in ranges.iter().zip(results) {
+        let result = result?;
+        read_bytes += result.read_bytes;
+        read_sizes.merge(&result.read_sizes);
+        // Ranges are in order, so the first one that did not fully match determines the result.
+        if matched_bytes != disk_size {
+            continue;
+        }
+        if let Some(range_mismatch) = result.mismatch {
+            matched_bytes = range_mismatch.position;
+            mismatch = Some(range_mismatch);
+        } else if result.read_bytes < range.end - range.start {
+            matched_bytes = range.start + result.read_bytes;
+        }
+    }
+
+    match &mismatch {
+        Some(mismatch) => {
+            error_log.mismatch(mismatch)?;
+            emit!("Finished reading {} bytes", read_bytes);
+        }
+        None if matched_bytes == disk_size => {
+            emit!("{}", Message::ReadSucceeded { bytes: read_bytes })
+        }
+        None => emit!("Finished reading {} bytes", read_bytes),
+    }
+
+    Ok(ReadResult {
+        read_bytes,
+        matched_bytes,
+        mismatch,
+        read_sizes,
+        mismatch_regions: MismatchRegions::default(),
+        bit_errors: 0,
+    })
+}
+
+/// Interactive prompt shown when a mismatch is found with --pause-on-mismatch. `device_buf` and
+/// `expected` are the contents of the block starting at `block_offset`, and the device is
+/// positioned right after that block.
+///
+/// Returns whether reading should continue.
+fn pause_on_mismatch<D: Read + Seek>(
+    d: &mut D,
+    block_offset: u64,
+    device_buf: &mut [u8],
+    expected: &[u8],
+    mismatch: &Mismatch,
+    error_log: &mut ErrorLog,
+) -> Result<bool> {
+    emit!("{}", mismatch);
+    loop {
+        emit!("(i)nspect nearby bytes, (r)e-read the block, (c)ontinue reading, or (q)uit?");
+        let mut response = String::new();
+        if std::io::stdin()
+            .read_line(&mut response)
+            .context("Error reading from stdin")?
+            == 0
+        {
+            return Ok(false);
+        }
+
+        match response.trim() {
+            "i" => {
+                let index = (mismatch.position - block_offset) as usize;
+                let start = (index - index % 16).saturating_sub(16);
+                let end = (start + 48).min(device_buf.len());
+                for row in (start..end).step_by(16) {
+                    let row_end = (row + 16).min(end);
+                    emit!(
+                        "{:>16x}  device:   {}",
+                        block_offset + row as u64,
+                        hex::encode(&device_buf[row..row_end])
+                    );
+                    emit!(
+                        "{:>16}  expected: {}",
+                        "",
+                        hex::encode(&expected[row..row_end])
+                    );
                 }
             }
-            bail!("Unreachable. Unable to find mismatching bytes.");
+            "r" => {
+                d.seek(SeekFrom::Start(block_offset))?;
+                if let Err(e) = d.read_exact(device_buf) {
+                    error_log.io_error(block_offset, &e)?;
+                    return Err(Error::from(e).context("Error re-reading block from device"));
+                }
+                match device_buf.iter().zip(expected).position(|(a, b)| a != b) {
+                    Some(i) => emit!(
+                        "Block still mismatches, first at position {}",
+                        block_offset + i as u64
+                    ),
+                    None => emit!("Block now matches the expected contents"),
+                }
+            }
+            "c" => return Ok(true),
+            "q" => return Ok(false),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts at most the next of `sizes` bytes per call, cycling through them.
+    struct ShortWriter {
+        written: Vec<u8>,
+        sizes: Vec<usize>,
+        calls: usize,
+    }
+
+    impl ShortWriter {
+        fn new(sizes: &[usize]) -> ShortWriter {
+            ShortWriter {
+                written: Vec::new(),
+                sizes: sizes.to_vec(),
+                calls: 0,
+            }
+        }
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.sizes[self.calls % self.sizes.len()]);
+            self.calls += 1;
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_buffer_drains_short_writes() {
+        let buf: Vec<u8> = (0..12_000u32).map(|i| i as u8).collect();
+        let mut d = ShortWriter::new(&[4096, 1000, 3]);
+        let mut written_bytes = 500;
+        let mut write_sizes = SizeDistribution::default();
+        let mut last_len = 0;
+
+        write_buffer(
+            &mut d,
+            &buf,
+            &mut written_bytes,
+            &mut write_sizes,
+            &mut last_len,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(d.written, buf);
+        assert_eq!(written_bytes, 500 + buf.len() as u64);
+        // 4096 + 1000 + 3 twice, then the last 1802 bytes.
+        assert_eq!(
+            write_sizes.counts.into_iter().collect::<Vec<_>>(),
+            [(3, 2), (1000, 2), (1802, 1), (4096, 2)]
+        );
+        assert_eq!(last_len, 1802);
+    }
+
+    #[test]
+    fn write_buffer_fails_when_nothing_is_written() {
+        let mut d = ShortWriter::new(&[100, 0]);
+        let mut written_bytes = 0;
+
+        let e = write_buffer(
+            &mut d,
+            &[0; 1000],
+            &mut written_bytes,
+            &mut SizeDistribution::default(),
+            &mut 0,
+            false,
+        )
+        .unwrap_err();
+
+        assert_eq!(e.kind(), std::io::ErrorKind::WriteZero);
+        assert_eq!(written_bytes, 100);
+    }
+
+    /// Returns at most the next of `sizes` bytes per read, cycling through them.
+    struct ShortReader {
+        data: std::io::Cursor<Vec<u8>>,
+        sizes: Vec<usize>,
+        calls: usize,
+    }
+
+    impl Read for ShortReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(self.sizes[self.calls % self.sizes.len()]);
+            self.calls += 1;
+            self.data.read(&mut buf[..n])
+        }
+    }
+
+    impl Seek for ShortReader {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+
+    #[test]
+    fn read_source_keeps_the_keystream_in_step_with_short_reads() {
+        let args = Args::from_iter(["verify-device-capacity", "--read", "device"]);
+        let spec = KeystreamSpec {
+            algorithm: DEFAULT_ALGORITHM,
+            key: [7; 32],
+            nonce: 0,
+            counter: 0,
+        };
+        let mut data = vec![0; 200_000];
+        KeystreamSource::new(&spec).fill(&mut data, 0);
+
+        let result = read_source(
+            &args,
+            || {
+                Ok(ShortReader {
+                    data: std::io::Cursor::new(data.clone()),
+                    sizes: vec![4096, 1000, 3, 517],
+                    calls: 0,
+                })
+            },
+            &mut KeystreamSource::new(&spec),
+            1 << 16,
+            512,
+            0..data.len() as u64,
+            &mut ErrorLog::open(None).unwrap(),
+        )
+        .unwrap();
+
+        assert!(result.mismatch.is_none());
+        assert_eq!(result.matched_bytes, data.len() as u64);
+        let counts = &result.read_sizes.counts;
+        for size in [4096, 1000, 3, 517] {
+            assert!(counts.contains_key(&size), "no reads of {} bytes", size);
+        }
+        let total: u64 = counts
+            .iter()
+            .map(|(&size, &count)| size as u64 * count)
+            .sum();
+        assert_eq!(total, data.len() as u64);
+        assert!(!result.read_sizes.is_uniform());
+    }
+
+    /// A temporary file, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> TempFile {
+            TempFile(std::env::temp_dir().join(format!("vdc-unit-{}-{}", name, std::process::id())))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
         }
+    }
+
+    #[test]
+    fn write_verify_reports_corrupted_buffer() {
+        let device = TempFile::new("write-verify.img");
+        let log = TempFile::new("write-verify.log");
+        let expected: Vec<u8> = (0..4096u32).map(|i| (i * 7) as u8).collect();
+        // The device dropped a bit of the second buffer written to it.
+        let mut contents = vec![0; 4096];
+        contents.extend_from_slice(&expected);
+        contents[4096 + 1234] ^= 0x10;
+        std::fs::write(&device.0, &contents).unwrap();
+        let file = File::open(&device.0).unwrap();
+        let mut verifier = WriteVerifier {
+            d: File::open(&device.0).unwrap(),
+            buf: Buffer::new(expected.len(), false),
+            duration: Duration::ZERO,
+        };
+        let mut error_log = ErrorLog::open(Some(&log.0)).unwrap();
+
+        let e = verifier
+            .verify(&file, 4096, &expected, &mut error_log)
+            .unwrap_err();
 
-        read_bytes += len;
+        assert!(format!("{:#}", e).contains(&format!(
+            "position {}. Device had contents 0x{:02x}, but expected 0x{:02x}.",
+            4096 + 1234,
+            expected[1234] ^ 0x10,
+            expected[1234]
+        )));
+        drop(error_log);
+        assert_eq!(
+            std::fs::read_to_string(&log.0).unwrap(),
+            format!(
+                "event=mismatch offset={} found=0x{:02x} expected=0x{:02x}\n",
+                4096 + 1234,
+                expected[1234] ^ 0x10,
+                expected[1234]
+            )
+        );
     }
 }
@@ -0,0 +1,88 @@
+//! The marker written to the start of the device with --mark-tested after a successful run, and
+//! read back with --check-tested.
+//!
+//! The marker occupies the first `MARKER_SIZE` bytes of the device. It starts with the `MAGIC`
+//! line followed by `name = value` lines, and is padded with zero bytes.
+
+use anyhow::{bail, Context, Result};
+
+use sha2::Digest;
+
+use crate::device::{open_device, Mode, OpenFlags};
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+const MAGIC: &str = "verify-device-capacity tested marker v1";
+
+/// Size of the marker on the device. A multiple of every common sector size.
+pub const MARKER_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub struct Marker {
+    /// Seconds since the Unix epoch.
+    pub tested_at: u64,
+    /// The sha256 hash of the seed, which identifies the run without revealing the seed.
+    pub seed_hash: String,
+    pub verified_bytes: u64,
+    pub tool_version: String,
+}
+
+impl Marker {
+    pub fn new(key: &[u8; 32], verified_bytes: u64) -> Marker {
+        Marker {
+            tested_at: crate::report::unix_time(),
+            seed_hash: hex::encode(sha2::Sha256::digest(key)),
+            verified_bytes,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = format!(
+            "{}\ntested_at = {}\nseed_hash = {}\nverified_bytes = {}\ntool_version = {}\n",
+            MAGIC, self.tested_at, self.seed_hash, self.verified_bytes, self.tool_version
+        );
+        let mut buf = vec![0u8; MARKER_SIZE];
+        buf[..contents.len()].copy_from_slice(contents.as_bytes());
+
+        let mut d = open_device(path, Mode::Write, OpenFlags::default())?;
+        d.write_all(&buf)?;
+        d.sync_all().context("Error while trying to call fsync")?;
+        Ok(())
+    }
+
+    /// Read the marker from the device, returning None if there is none.
+    pub fn read(path: &Path) -> Result<Option<Marker>> {
+        let mut buf = vec![0u8; MARKER_SIZE];
+        open_device(path, Mode::Read, OpenFlags::default())?.read_exact(&mut buf)?;
+
+        let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        let contents = match std::str::from_utf8(&buf[..end]) {
+            Ok(contents) => contents,
+            Err(_) => return Ok(None),
+        };
+        let rest = match contents.strip_prefix(MAGIC) {
+            Some(rest) => rest,
+            None => return Ok(None),
+        };
+
+        let pairs = crate::keystream::parse_pairs(rest).context("Invalid marker")?;
+        let value = |name: &str| -> Result<&str> {
+            match pairs.iter().find(|(n, _)| *n == name) {
+                Some((_, value)) => Ok(value),
+                None => bail!("Marker is missing {}", name),
+            }
+        };
+        Ok(Some(Marker {
+            tested_at: value("tested_at")?
+                .parse()
+                .context("Invalid tested_at in marker")?,
+            seed_hash: value("seed_hash")?.to_string(),
+            verified_bytes: value("verified_bytes")?
+                .parse()
+                .context("Invalid verified_bytes in marker")?,
+            tool_version: value("tool_version")?.to_string(),
+        }))
+    }
+}
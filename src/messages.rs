@@ -0,0 +1,232 @@
+//! Translations of the messages users see in the main flow of a run: the overwrite prompt,
+//! progress lines, the outcome of each phase, mismatches and the final verdict.
+//!
+//! The locale is chosen once at startup with `init`, from --lang or the environment, like the
+//! other output settings. Messages are values whose `Display` renders them in that locale, so
+//! they can be passed to `emit!` and friends like any other argument. Anything not listed here
+//! is still only in English.
+
+use anyhow::{bail, Error, Result};
+
+use crossterm::style::Color;
+
+use crate::color;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// A language messages can be shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    English,
+    German,
+}
+
+impl std::str::FromStr for Locale {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Locale> {
+        match s {
+            "en" => Ok(Locale::English),
+            "de" => Ok(Locale::German),
+            _ => bail!("Unknown language '{}', expected en or de", s),
+        }
+    }
+}
+
+impl Locale {
+    /// The locale of the environment, from the first of LC_ALL, LC_MESSAGES and LANG that is
+    /// set, e.g. `de_DE.UTF-8`. English if none is set or the language is not translated.
+    pub fn from_env() -> Locale {
+        let value = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        let language = value.split(['_', '.', '@']).next().unwrap_or_default();
+        language.parse().unwrap_or(Locale::English)
+    }
+}
+
+static LOCALE: AtomicU8 = AtomicU8::new(Locale::English as u8);
+
+pub fn init(locale: Locale) {
+    LOCALE.store(locale as u8, Ordering::Relaxed);
+}
+
+fn locale() -> Locale {
+    match LOCALE.load(Ordering::Relaxed) {
+        x if x == Locale::German as u8 => Locale::German,
+        _ => Locale::English,
+    }
+}
+
+/// Whether a response to a yes/no question means yes. English answers are always understood.
+pub fn is_yes(response: &str) -> bool {
+    match response.trim() {
+        "y" | "Y" => true,
+        "j" | "J" => locale() == Locale::German,
+        _ => false,
+    }
+}
+
+/// What a phase reports having done with the bytes it processed, in its progress lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verb {
+    Written,
+    Read,
+}
+
+impl std::fmt::Display for Verb {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match (locale(), self) {
+            (Locale::English, Verb::Written) => "Written",
+            (Locale::English, Verb::Read) => "Read",
+            (Locale::German, Verb::Written) => "Geschrieben",
+            (Locale::German, Verb::Read) => "Gelesen",
+        })
+    }
+}
+
+pub enum Message<'a> {
+    ConfirmOverwrite {
+        device: &'a Path,
+    },
+    ConfirmOverwriteDevices {
+        devices: &'a [PathBuf],
+    },
+    OverwritingBecauseOfYes {
+        device: &'a Path,
+    },
+    Writing {
+        device: &'a Path,
+    },
+    Reading {
+        device: &'a Path,
+    },
+    Progress {
+        verb: Verb,
+        bytes: u64,
+        rate: f64,
+        precision: usize,
+        completion: f64,
+    },
+    WriteSucceeded {
+        bytes: u64,
+    },
+    ReadSucceeded {
+        bytes: u64,
+    },
+    ByteMismatch {
+        position: u64,
+        found: u8,
+        expected: u8,
+    },
+    ChecksumMismatch {
+        position: u64,
+    },
+    Claimed {
+        bytes: u64,
+    },
+    Verified {
+        bytes: u64,
+    },
+    /// Whether the verified size matches the claimed size.
+    Verdict {
+        pass: bool,
+    },
+    /// The capacity a thinly provisioned device could hold, out of the size it claims.
+    Usable {
+        bytes: u64,
+        claimed: u64,
+    },
+    /// Whether the verified size matches the usable size of a thinly provisioned device.
+    UsableVerdict {
+        pass: bool,
+    },
+    WriteRate {
+        rate: f64,
+    },
+    ReadRate {
+        rate: f64,
+    },
+    UnusualRateRatio {
+        ratio: f64,
+    },
+}
+
+impl std::fmt::Display for Message<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match locale() {
+            Locale::English => self.english(f),
+            Locale::German => self.german(f),
+        }
+    }
+}
+
+impl Message<'_> {
+    fn english(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Message::ConfirmOverwrite { device } => write!(f, "Will write pseudo-random stream of data to device '{}'. This will overwrite all data on the device. Are you sure you want to continue? (y/N)", device.display()),
+            Message::ConfirmOverwriteDevices { devices } => write!(f, "Will write pseudo-random stream of data to devices {}. This will overwrite all data on these devices. Are you sure you want to continue? (y/N)", DeviceList(devices)),
+            Message::OverwritingBecauseOfYes { device } => write!(f, "Overwriting data on device '{}' because of --yes", device.display()),
+            Message::Writing { device } => write!(f, "Writing to device {}", device.display()),
+            Message::Reading { device } => write!(f, "Reading from device {}", device.display()),
+            Message::Progress { verb, bytes, rate, precision, completion } => write!(f, "{} {} bytes total. {:.0} bytes/second. {:.*}% complete.", verb, bytes, rate, precision, completion),
+            Message::WriteSucceeded { bytes } => write!(f, "Successfully wrote {} bytes", bytes),
+            Message::ReadSucceeded { bytes } => write!(f, "Successfully read and matched {} bytes", bytes),
+            Message::ByteMismatch { position, found, expected } => write!(f, "Device found byte that does not match expected contents on position {}. Device had contents 0x{:02x}, but expected 0x{:02x}.", position, found, expected),
+            Message::ChecksumMismatch { position } => write!(f, "Device block starting on position {} does not match its checksum.", position),
+            Message::Claimed { bytes } => write!(f, "Claimed: {} bytes", bytes),
+            Message::Verified { bytes } => write!(f, "Verified: {} bytes", bytes),
+            Message::Verdict { pass: true } => write!(f, "{} device capacity matches the claimed size", color::paint("PASS:", Color::Green)),
+            Message::Verdict { pass: false } => write!(f, "{} device capacity does not match the claimed size", color::paint("FAIL:", Color::Red)),
+            Message::Usable { bytes, claimed } => write!(f, "Usable: {} of claimed {} bytes", bytes, claimed),
+            Message::UsableVerdict { pass: true } => write!(f, "{} the usable capacity of the thinly provisioned device holds its data", color::paint("PASS:", Color::Green)),
+            Message::UsableVerdict { pass: false } => write!(f, "{} the usable capacity of the thinly provisioned device does not hold its data", color::paint("FAIL:", Color::Red)),
+            Message::WriteRate { rate } => write!(f, "Average write rate: {:.0} bytes/second", rate),
+            Message::ReadRate { rate } => write!(f, "Average read rate: {:.0} bytes/second", rate),
+            Message::UnusualRateRatio { ratio } => write!(f, "NOTE: Read rate is {:.2} times the write rate, which is unusual for genuine media and can indicate caching tricks by the device.", ratio),
+        }
+    }
+
+    fn german(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Message::ConfirmOverwrite { device } => write!(f, "Es wird ein pseudozufälliger Datenstrom auf das Gerät '{}' geschrieben. Dabei werden alle Daten auf dem Gerät überschrieben. Wirklich fortfahren? (j/N)", device.display()),
+            Message::ConfirmOverwriteDevices { devices } => write!(f, "Es wird ein pseudozufälliger Datenstrom auf die Geräte {} geschrieben. Dabei werden alle Daten auf diesen Geräten überschrieben. Wirklich fortfahren? (j/N)", DeviceList(devices)),
+            Message::OverwritingBecauseOfYes { device } => write!(f, "Daten auf dem Gerät '{}' werden wegen --yes überschrieben", device.display()),
+            Message::Writing { device } => write!(f, "Schreibe auf Gerät {}", device.display()),
+            Message::Reading { device } => write!(f, "Lese von Gerät {}", device.display()),
+            Message::Progress { verb, bytes, rate, precision, completion } => write!(f, "{}: {} Bytes insgesamt. {:.0} Bytes/Sekunde. {:.*} % abgeschlossen.", verb, bytes, rate, precision, completion),
+            Message::WriteSucceeded { bytes } => write!(f, "{} Bytes erfolgreich geschrieben", bytes),
+            Message::ReadSucceeded { bytes } => write!(f, "{} Bytes erfolgreich gelesen und abgeglichen", bytes),
+            Message::ByteMismatch { position, found, expected } => write!(f, "Das Gerät enthält an Position {} ein Byte, das nicht dem erwarteten Inhalt entspricht. Gelesen wurde 0x{:02x}, erwartet war 0x{:02x}.", position, found, expected),
+            Message::ChecksumMismatch { position } => write!(f, "Der Block des Geräts ab Position {} stimmt nicht mit seiner Prüfsumme überein.", position),
+            Message::Claimed { bytes } => write!(f, "Angegeben: {} Bytes", bytes),
+            Message::Verified { bytes } => write!(f, "Überprüft: {} Bytes", bytes),
+            Message::Verdict { pass: true } => write!(f, "{} die Kapazität des Geräts entspricht der angegebenen Größe", color::paint("BESTANDEN:", Color::Green)),
+            Message::Verdict { pass: false } => write!(f, "{} die Kapazität des Geräts entspricht nicht der angegebenen Größe", color::paint("NICHT BESTANDEN:", Color::Red)),
+            Message::Usable { bytes, claimed } => write!(f, "Nutzbar: {} von angegebenen {} Bytes", bytes, claimed),
+            Message::UsableVerdict { pass: true } => write!(f, "{} die nutzbare Kapazität des Geräts mit Thin Provisioning hält ihre Daten", color::paint("BESTANDEN:", Color::Green)),
+            Message::UsableVerdict { pass: false } => write!(f, "{} die nutzbare Kapazität des Geräts mit Thin Provisioning hält ihre Daten nicht", color::paint("NICHT BESTANDEN:", Color::Red)),
+            Message::WriteRate { rate } => write!(f, "Durchschnittliche Schreibrate: {:.0} Bytes/Sekunde", rate),
+            Message::ReadRate { rate } => write!(f, "Durchschnittliche Leserate: {:.0} Bytes/Sekunde", rate),
+            Message::UnusualRateRatio { ratio } => write!(f, "HINWEIS: Die Leserate ist {:.2}-mal so hoch wie die Schreibrate. Das ist für echte Speichermedien ungewöhnlich und kann auf Tricks mit einem Zwischenspeicher des Geräts hindeuten.", ratio),
+        }
+    }
+}
+
+/// Quoted, comma-separated device paths.
+struct DeviceList<'a>(&'a [PathBuf]);
+
+impl std::fmt::Display for DeviceList<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, device) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "'{}'", device.display())?;
+        }
+        Ok(())
+    }
+}
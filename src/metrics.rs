@@ -0,0 +1,179 @@
+//! Prometheus metrics of the run, served over HTTP with --metrics-port for monitoring a test
+//! bench.
+//!
+//! A single thread answers each request to `/metrics` in turn with the text exposition format.
+//! The counters are updated by the phases as they go, and the thread is simply left behind when
+//! the process exits.
+
+use anyhow::{Context, Result};
+
+use crate::messages::Verb;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How often the rate is measured.
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client gets to send its request, so a stuck client cannot block the others.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The device label of every metric, escaped. Set once the server is started.
+static DEVICE: OnceLock<String> = OnceLock::new();
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+/// The rate over the last `RATE_INTERVAL` in bytes per second, as the bits of an `f64`.
+static RATE: AtomicU64 = AtomicU64::new(0);
+/// When the rate was last measured, and the bytes processed by then.
+static RATE_SAMPLE: Mutex<Option<(Instant, u64)>> = Mutex::new(None);
+/// The phase running, as one of the `PHASE_*` constants.
+static PHASE: AtomicU8 = AtomicU8::new(PHASE_STARTING);
+static CYCLES_PASSED: AtomicU64 = AtomicU64::new(0);
+static CYCLES_FAILED: AtomicU64 = AtomicU64::new(0);
+
+const PHASE_STARTING: u8 = 0;
+const PHASE_WRITE: u8 = 1;
+const PHASE_READ: u8 = 2;
+const PHASE_NAMES: [&str; 3] = ["starting", "write", "read"];
+
+/// Start serving metrics for the given device on all interfaces on `port`.
+pub fn init(port: u16, device: &std::path::Path) -> Result<()> {
+    let listener = TcpListener::bind(("::", port))
+        .or_else(|_| TcpListener::bind(("0.0.0.0", port)))
+        .with_context(|| format!("Unable to listen for metrics requests on port {}", port))?;
+    let _ = DEVICE.set(escape(&device.display().to_string()));
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A client going away mid-request is its own problem.
+            let _ = respond(stream);
+        }
+    });
+    Ok(())
+}
+
+/// Record the progress of a phase, called on every buffer.
+pub fn progress(verb: Verb, bytes: u64) {
+    if DEVICE.get().is_none() {
+        return;
+    }
+    let (counter, phase) = match verb {
+        Verb::Written => (&BYTES_WRITTEN, PHASE_WRITE),
+        Verb::Read => (&BYTES_READ, PHASE_READ),
+    };
+    counter.store(bytes, Ordering::Relaxed);
+    let previous_phase = PHASE.swap(phase, Ordering::Relaxed);
+
+    let mut sample = RATE_SAMPLE.lock().unwrap_or_else(|e| e.into_inner());
+    match *sample {
+        // A new phase starts counting from 0 again.
+        Some((time, sampled_bytes)) if previous_phase == phase && bytes >= sampled_bytes => {
+            let elapsed = time.elapsed();
+            if elapsed >= RATE_INTERVAL {
+                let rate = (bytes - sampled_bytes) as f64 / elapsed.as_secs_f64();
+                RATE.store(rate.to_bits(), Ordering::Relaxed);
+                *sample = Some((Instant::now(), bytes));
+            }
+        }
+        _ => *sample = Some((Instant::now(), bytes)),
+    }
+}
+
+/// Record the outcome of a --soak cycle.
+pub fn cycle_finished(passed: bool) {
+    if passed {
+        CYCLES_PASSED.fetch_add(1, Ordering::Relaxed);
+    } else {
+        CYCLES_FAILED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers are read and ignored, since nothing in them changes the response.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            render(DEVICE.get().map_or("", |device| device)),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn render(device: &str) -> String {
+    let label = format!("device=\"{}\"", device);
+    let phase = PHASE.load(Ordering::Relaxed) as usize;
+    let mut out = String::new();
+    out.push_str("# HELP vdc_written_bytes_total Bytes written to the device so far.\n");
+    out.push_str("# TYPE vdc_written_bytes_total counter\n");
+    out.push_str(&format!(
+        "vdc_written_bytes_total{{{}}} {}\n",
+        label,
+        BYTES_WRITTEN.load(Ordering::Relaxed)
+    ));
+    out.push_str("# HELP vdc_read_bytes_total Bytes read back from the device so far.\n");
+    out.push_str("# TYPE vdc_read_bytes_total counter\n");
+    out.push_str(&format!(
+        "vdc_read_bytes_total{{{}}} {}\n",
+        label,
+        BYTES_READ.load(Ordering::Relaxed)
+    ));
+    out.push_str(
+        "# HELP vdc_rate_bytes_per_second Rate of the phase running over the last second.\n",
+    );
+    out.push_str("# TYPE vdc_rate_bytes_per_second gauge\n");
+    out.push_str(&format!(
+        "vdc_rate_bytes_per_second{{{}}} {}\n",
+        label,
+        f64::from_bits(RATE.load(Ordering::Relaxed))
+    ));
+    out.push_str("# HELP vdc_phase Whether each phase is the one running.\n");
+    out.push_str("# TYPE vdc_phase gauge\n");
+    for (i, name) in PHASE_NAMES.iter().enumerate() {
+        out.push_str(&format!(
+            "vdc_phase{{{},phase=\"{}\"}} {}\n",
+            label,
+            name,
+            u8::from(i == phase)
+        ));
+    }
+    out.push_str("# HELP vdc_soak_cycles_total Finished --soak cycles by result.\n");
+    out.push_str("# TYPE vdc_soak_cycles_total counter\n");
+    for (result, counter) in [("pass", &CYCLES_PASSED), ("fail", &CYCLES_FAILED)] {
+        out.push_str(&format!(
+            "vdc_soak_cycles_total{{{},result=\"{}\"}} {}\n",
+            label,
+            result,
+            counter.load(Ordering::Relaxed)
+        ));
+    }
+    out
+}
+
+/// Escape a label value for the text exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
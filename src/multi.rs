@@ -0,0 +1,168 @@
+//! Verification of several devices at once from a single invocation, for test stations with many
+//! ports.
+//!
+//! Each device is verified by a child process running this program on just that device, so each
+//! gets its own seed, progress, checkpoints and signal handling without any of the state of a run
+//! having to be shared. The output of the children is passed through prefixed with their device,
+//! or with --tui shown as a table of the latest line of each, and the outcome of every device is
+//! summarized at the end.
+
+use anyhow::{bail, Context, Result};
+
+use crossterm::style::Color;
+
+use crate::color;
+use crate::messages::Message;
+use crate::output::{self, emit};
+use crate::tui::DeviceTable;
+
+use std::ffi::OsString;
+use std::io::{BufRead, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Verify all `devices` concurrently. `assume_yes` passes the confirmation of the overwrite on to
+/// the children, once it has been confirmed for all of them. With `table`, the output of the
+/// children is replaced by a table of their latest line.
+pub fn run(devices: &[PathBuf], assume_yes: bool, table: bool) -> Result<()> {
+    let names: Vec<String> = devices.iter().map(|device| device_name(device)).collect();
+
+    // The arguments of the children are our own, with the devices and --tui left out. A token is
+    // taken to be a device if it is equal to one, which only an option value could also be.
+    let mut common_args: Vec<OsString> = std::env::args_os()
+        .skip(1)
+        .filter(|arg| arg != "--tui" && !devices.iter().any(|device| device == arg))
+        .collect();
+    // The confirmation is passed on as the confirmation file of each child on its stdin rather
+    // than as --yes, which the site policy may forbid even though we did ask.
+    if assume_yes {
+        common_args.push("--overwrite-confirmation-from".into());
+        common_args.push("/dev/stdin".into());
+    }
+    if !common_args.iter().any(|arg| arg == "--") {
+        common_args.push("--".into());
+    }
+
+    let program = std::env::current_exe().context("Unable to find own executable")?;
+    let mut children = Vec::new();
+    for device in devices {
+        let mut child = Command::new(&program)
+            .args(&common_args)
+            .arg(device)
+            .stdin(if assume_yes {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Unable to start verifying device {}", device.display()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // Closed when dropped, ending the confirmation file.
+            stdin
+                .write_all(device.as_os_str().as_bytes())
+                .with_context(|| format!("Unable to confirm overwriting {}", device.display()))?;
+        }
+        children.push(child);
+    }
+    emit!("Verifying {} devices concurrently", devices.len());
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    for (i, child) in children.iter_mut().enumerate() {
+        let stderr = child.stderr.take().expect("Child stderr is piped");
+        let tx = tx.clone();
+        std::thread::spawn(move || {
+            for line in std::io::BufReader::new(stderr)
+                .lines()
+                .map_while(Result::ok)
+            {
+                if tx.send((i, line)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(tx);
+
+    // The summary shows how much of each device was verified, or why it failed. The children
+    // put timestamps before their lines with --timestamps, which are left out of it.
+    let verified_label = Message::Verified { bytes: 0 }.to_string();
+    let verified_label = verified_label.split(':').next().unwrap_or_default();
+    let is_outcome = |line: &str| {
+        line.strip_prefix(verified_label)
+            .or_else(|| line.strip_prefix("Error"))
+            .is_some_and(|rest| rest.starts_with(':'))
+    };
+
+    let mut last_lines = vec![String::new(); devices.len()];
+    let mut outcomes = vec![None; devices.len()];
+    let mut table = table.then(DeviceTable::new);
+    for (i, line) in rx {
+        let untimed = output::strip_timestamp(&line);
+        if is_outcome(untimed) {
+            outcomes[i] = Some(untimed.to_string());
+        }
+        match &mut table {
+            Some(table) => {
+                last_lines[i] = line;
+                table.render(&names, &last_lines)?;
+            }
+            None => {
+                eprintln!("[{}] {}", names[i], line);
+                last_lines[i] = line;
+            }
+        }
+    }
+
+    emit!("Summary:");
+    let mut failed = 0;
+    let outcomes = outcomes.into_iter().zip(last_lines);
+    for ((child, name), (outcome, last_line)) in children.iter_mut().zip(&names).zip(outcomes) {
+        let outcome = outcome.unwrap_or(last_line);
+        let status = child.wait().context("Error waiting for verification")?;
+        if status.success() {
+            emit!(
+                "{} {}: {}",
+                color::paint("PASS:", Color::Green),
+                name,
+                outcome
+            );
+        } else {
+            failed += 1;
+            emit!(
+                "{} {}: {}: {}",
+                color::paint("FAIL:", Color::Red),
+                name,
+                describe_status(status),
+                outcome
+            );
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "{} of {} devices failed verification",
+            failed,
+            devices.len()
+        );
+    }
+    Ok(())
+}
+
+/// Short name for a device in the output, e.g. `sdb` for `/dev/sdb`.
+fn device_name(device: &std::path::Path) -> String {
+    device
+        .file_name()
+        .unwrap_or(device.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn describe_status(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exited with code {}", code),
+        (None, Some(signal)) => format!("killed by signal {}", signal),
+        (None, None) => "exited".to_string(),
+    }
+}
@@ -0,0 +1,136 @@
+//! Status output to stderr, optionally prefixed with the time with --timestamps, mirrored to
+//! syslog with --syslog, and to a transcript in the run directory with --output-dir.
+
+use anyhow::{Context, Result};
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+static SYSLOG: AtomicBool = AtomicBool::new(false);
+/// Transcript of the status output with --output-dir.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+/// Print a status line to stderr, prefixed with the time if --timestamps is given. Takes the same
+/// arguments as `eprintln!`.
+macro_rules! emit {
+    ($($arg:tt)*) => {
+        $crate::output::emit_line(format_args!($($arg)*))
+    };
+}
+pub(crate) use emit;
+
+pub fn init(timestamps: bool, syslog: bool) {
+    TIMESTAMPS.store(timestamps, Ordering::Relaxed);
+    if syslog {
+        crate::syslog::open();
+        SYSLOG.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn emit_line(args: std::fmt::Arguments) {
+    if TIMESTAMPS.load(Ordering::Relaxed) {
+        eprintln!("{} {}", timestamp(SystemTime::now()), args);
+    } else {
+        eprintln!("{}", args);
+    }
+    if SYSLOG.load(Ordering::Relaxed) {
+        crate::syslog::log(&args.to_string());
+    }
+    if let Some(file) = LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        // The transcript is a convenience, so failing to write it must not fail the run.
+        let _ = writeln!(
+            file,
+            "{} {}",
+            timestamp(SystemTime::now()),
+            crate::syslog::strip_escapes(&args.to_string())
+        );
+    }
+}
+
+/// Also write every status line from now on to the given file, always with a timestamp.
+pub fn log_to_file(path: &Path) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Unable to create output log '{}'", path.display()))?;
+    *LOG_FILE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
+
+/// The line without the timestamp put before it with --timestamps, if it has one.
+pub fn strip_timestamp(line: &str) -> &str {
+    match line.split_once(' ') {
+        Some((time, rest))
+            if time.len() == 24
+                && time.as_bytes()[10] == b'T'
+                && time.ends_with('Z')
+                && time[..4].bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            rest
+        }
+        _ => line,
+    }
+}
+
+/// Format the time as an RFC 3339 timestamp in UTC with millisecond precision, e.g.
+/// `2021-03-04T05:06:07.089Z`.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Format the time as an ISO 8601 timestamp in the local time zone with its offset from UTC and
+/// second precision, e.g. `2021-03-04T06:06:07+01:00`. Falls back to UTC if the local time cannot
+/// be determined.
+pub fn local_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&seconds, &mut tm) }.is_null() {
+        return timestamp(time);
+    }
+    let offset_minutes = tm.tm_gmtoff / 60;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        if offset_minutes < 0 { '-' } else { '+' },
+        offset_minutes.abs() / 60,
+        offset_minutes.abs() % 60
+    )
+}
+
+/// Convert a number of days since 1970-01-01 to a year, month and day in the proleptic Gregorian
+/// calendar, using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
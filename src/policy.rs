@@ -0,0 +1,137 @@
+//! Site policy for the overwrite confirmation, letting administrators decide centrally whether
+//! overwriting a device may skip the question.
+//!
+//! The policy is read from `SITE_POLICY_PATH` as `name = value` lines, like the keystream
+//! descriptor. Only the confirmation is covered so far:
+//!
+//! ```text
+//! confirmation = interactive
+//! ```
+//!
+//! Whether to skip the question is decided in this order, the first that applies winning:
+//!
+//! 1. `confirmation = interactive` in the site policy always asks, and refuses to run with --yes,
+//!    --overwrite-confirmation-from or VDC_ASSUME_YES set to yes.
+//! 2. --yes on the command line.
+//! 3. The VDC_ASSUME_YES environment variable, `1`, `true` or `yes` to skip the question, and `0`,
+//!    `false`, `no` or empty to ask.
+//! 4. `confirmation = assume-yes` in the site policy.
+//! 5. Asking, which is also what `confirmation = ask` or no policy file at all means.
+
+use anyhow::{bail, Context, Error, Result};
+
+use crate::keystream::parse_pairs;
+
+use std::path::Path;
+
+/// Where the site policy is read from.
+pub const SITE_POLICY_PATH: &str = "/etc/verify-device-capacity/policy";
+
+/// The environment variable for skipping the question per user or per script.
+const ASSUME_YES_VAR: &str = "VDC_ASSUME_YES";
+
+/// How the site policy wants overwriting a device to be confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Confirmation {
+    /// Ask unless told otherwise by --yes or VDC_ASSUME_YES.
+    #[default]
+    Ask,
+    /// Skip the question unless VDC_ASSUME_YES says to ask.
+    AssumeYes,
+    /// Always ask, even in scripts.
+    Interactive,
+}
+
+impl std::str::FromStr for Confirmation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Confirmation> {
+        match s {
+            "ask" => Ok(Confirmation::Ask),
+            "assume-yes" => Ok(Confirmation::AssumeYes),
+            "interactive" => Ok(Confirmation::Interactive),
+            _ => bail!(
+                "Unknown confirmation '{}', expected ask, assume-yes or interactive",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Policy {
+    pub confirmation: Confirmation,
+}
+
+impl Policy {
+    /// The policy in the given file, or the default policy if there is no such file.
+    pub fn load(path: &Path) -> Result<Policy> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Policy::default()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Unable to read site policy '{}'", path.display()))
+            }
+        };
+        Self::parse(&contents).with_context(|| format!("Invalid site policy '{}'", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Policy> {
+        let mut policy = Policy::default();
+        for (name, value) in parse_pairs(contents)? {
+            match name {
+                "confirmation" => policy.confirmation = value.parse()?,
+                _ => bail!("Unknown name '{}'", name),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Whether to overwrite the device without asking, given whether --yes and
+    /// --overwrite-confirmation-from were given. Fails if the policy forbids skipping the question
+    /// but it was asked to be skipped.
+    pub fn assume_yes(&self, yes_flag: bool, confirmation_file: bool) -> Result<bool> {
+        let env = assume_yes_from_env()?;
+        if self.confirmation == Confirmation::Interactive {
+            if yes_flag {
+                bail!(
+                    "The site policy in '{}' requires confirming the overwrite interactively, so --yes cannot be used",
+                    SITE_POLICY_PATH
+                );
+            }
+            if confirmation_file {
+                bail!(
+                    "The site policy in '{}' requires confirming the overwrite interactively, so --overwrite-confirmation-from cannot be used",
+                    SITE_POLICY_PATH
+                );
+            }
+            if env == Some(true) {
+                bail!(
+                    "The site policy in '{}' requires confirming the overwrite interactively, so {} cannot be set to yes",
+                    SITE_POLICY_PATH,
+                    ASSUME_YES_VAR
+                );
+            }
+            return Ok(false);
+        }
+        Ok(yes_flag || env.unwrap_or(self.confirmation == Confirmation::AssumeYes))
+    }
+}
+
+/// The value of VDC_ASSUME_YES, if it is set.
+fn assume_yes_from_env() -> Result<Option<bool>> {
+    let value = match std::env::var_os(ASSUME_YES_VAR) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    match value.to_str().map(str::trim) {
+        Some("1" | "true" | "yes") => Ok(Some(true)),
+        Some("0" | "false" | "no" | "") => Ok(Some(false)),
+        _ => bail!(
+            "Invalid {} '{}', expected 1, true, yes, 0, false or no",
+            ASSUME_YES_VAR,
+            value.to_string_lossy()
+        ),
+    }
+}
@@ -0,0 +1,199 @@
+//! Periodic progress reporting for the write and read phases.
+
+use crossterm::style::Color;
+use crossterm::tty::IsTty;
+use crossterm::ExecutableCommand;
+
+use crate::color;
+use crate::messages::{Message, Verb};
+use crate::output::emit;
+use crate::tui::Dashboard;
+use crate::usage::UsageReporter;
+
+use std::time::{Duration, Instant};
+
+const UPDATE_FREQUENCY: Duration = Duration::from_secs(60);
+
+/// Number of times in a row moving the cursor may fail before progress lines are appended rather
+/// than overwritten.
+const MAX_CURSOR_FAILURES: u32 = 3;
+
+/// How often the dashboard is redrawn with --tui.
+const DASHBOARD_UPDATE_FREQUENCY: Duration = Duration::from_secs(1);
+
+/// Options for how progress is reported, shared by all phases.
+#[derive(Debug, Clone)]
+pub struct ProgressOptions {
+    /// Number of decimal places shown for the completion percentage.
+    pub precision: usize,
+    /// Whether to draw the full-screen dashboard instead of progress lines.
+    pub dashboard: bool,
+    /// Print progress lines each time this many more bytes are processed rather than periodically.
+    /// Does not affect the dashboard.
+    pub every_bytes: Option<u64>,
+    /// Whether to add the CPU and memory usage of the process to progress lines, to tell whether
+    /// the run is CPU or I/O bound.
+    pub resource_usage: bool,
+}
+
+/// Reports progress of a phase to stderr.
+///
+/// When stderr is a TTY the progress line is overwritten in place on every update. Otherwise
+/// each update is appended as a plain line, so that logs captured to a file contain no escape
+/// sequences. With --tui the full-screen dashboard is redrawn instead.
+pub struct Progress {
+    /// Describes the bytes processed in the progress line.
+    verb: Verb,
+    /// Shown as the title of the dashboard, e.g. "Writing to device /dev/sdb".
+    title: String,
+    total_bytes: u64,
+    options: ProgressOptions,
+    tty: bool,
+    /// Number of times in a row moving the cursor to overwrite the progress line failed.
+    cursor_failures: u32,
+    dashboard: Option<Dashboard>,
+    usage: Option<UsageReporter>,
+    last_update: Instant,
+    last_update_bytes: u64,
+}
+
+impl Progress {
+    pub fn new(verb: Verb, title: String, total_bytes: u64, options: &ProgressOptions) -> Progress {
+        let tty = std::io::stderr().is_tty();
+        let dashboard = if options.dashboard {
+            Some(Dashboard::new())
+        } else {
+            if tty {
+                // Reserve the line that is overwritten by every update.
+                eprintln!();
+            }
+            None
+        };
+
+        Progress {
+            verb,
+            title,
+            total_bytes,
+            options: options.clone(),
+            tty,
+            cursor_failures: 0,
+            dashboard,
+            usage: if options.resource_usage {
+                Some(UsageReporter::new())
+            } else {
+                None
+            },
+            last_update: Instant::now(),
+            last_update_bytes: 0,
+        }
+    }
+
+    /// Start counting from `bytes` bytes already processed, when continuing an interrupted phase.
+    pub fn resume_from(&mut self, bytes: u64) {
+        self.last_update_bytes = bytes;
+    }
+
+    /// Report that `bytes` bytes have been processed in total so far. Only prints an update if
+    /// enough time has passed since the last one, or with `every_bytes`, if another multiple of it
+    /// has been reached.
+    ///
+    /// Failing to draw progress is only reported, since it must never end a run that may have
+    /// been going for hours.
+    pub fn update(&mut self, bytes: u64) {
+        crate::sd_notify::progress(&self.verb.to_string(), bytes, self.total_bytes);
+        crate::metrics::progress(self.verb, bytes);
+        let duration = self.last_update.elapsed();
+        let due = match self.options.every_bytes {
+            Some(every) if self.dashboard.is_none() => {
+                bytes / every > self.last_update_bytes / every
+            }
+            _ if self.dashboard.is_some() => duration > DASHBOARD_UPDATE_FREQUENCY,
+            _ => duration > UPDATE_FREQUENCY,
+        };
+        if !due {
+            return;
+        }
+
+        let newly_processed_bytes = bytes - self.last_update_bytes;
+        let rate = (newly_processed_bytes as f64) / duration.as_secs_f64();
+        self.last_update = Instant::now();
+        self.last_update_bytes = bytes;
+
+        if let Some(dashboard) = &mut self.dashboard {
+            match dashboard.render(
+                &self.title,
+                bytes,
+                self.total_bytes,
+                rate,
+                self.options.precision,
+            ) {
+                Ok(()) => return,
+                Err(e) => {
+                    self.dashboard = None;
+                    self.tty = false;
+                    emit!(
+                        "{} {:#}, showing progress lines instead of the dashboard",
+                        color::paint("WARNING:", Color::Yellow),
+                        e
+                    );
+                }
+            }
+        }
+
+        // The final buffer may take us slightly past the expected total, but we never want to show
+        // more than 100%.
+        let completion = ((bytes as f64) / (self.total_bytes as f64) * 100.0).min(100.0);
+        if self.tty {
+            match std::io::stderr().execute(crossterm::cursor::MoveToPreviousLine(1)) {
+                Ok(_) => self.cursor_failures = 0,
+                Err(e) => {
+                    // The line is appended instead, which is untidy but still readable.
+                    self.cursor_failures += 1;
+                    if self.cursor_failures == MAX_CURSOR_FAILURES {
+                        self.tty = false;
+                        emit!(
+                            "{} Unable to move the cursor ({}), appending progress lines instead",
+                            color::paint("WARNING:", Color::Yellow),
+                            e
+                        );
+                    }
+                }
+            }
+        }
+        let usage = self
+            .usage
+            .as_mut()
+            .and_then(|usage| usage.report())
+            .map(|usage| format!(" {}", usage))
+            .unwrap_or_default();
+        emit!(
+            "{}{}",
+            Message::Progress {
+                verb: self.verb,
+                bytes,
+                rate,
+                precision: self.options.precision,
+                completion,
+            },
+            usage
+        );
+    }
+
+    /// Report an error that does not stop the phase. Only shown on the dashboard, since otherwise
+    /// it is already printed where it occurs.
+    pub fn error(&mut self, error: String) {
+        if let Some(dashboard) = &mut self.dashboard {
+            dashboard.add_error(error);
+        }
+    }
+}
+
+/// Format a number of seconds as hours, minutes and seconds.
+pub fn format_duration(seconds: u64) -> String {
+    format!(
+        "{}h {:02}m {:02}s",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,86 @@
+//! Regions of the device tested with their own seeds, given with --region.
+
+use anyhow::{bail, Context, Error, Result};
+
+use sha2::Digest;
+
+use crate::keystream::{KeystreamSpec, DEFAULT_ALGORITHM};
+
+/// A range of the device filled with the keystream of its own seed, starting at the start of the
+/// region.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub offset: u64,
+    pub length: u64,
+    pub seed: String,
+}
+
+impl std::str::FromStr for Region {
+    type Err = Error;
+
+    /// Parse `offset:length:seed`, where the offset and length are sizes like 1G. The seed may
+    /// itself contain colons.
+    fn from_str(s: &str) -> Result<Region> {
+        let mut parts = s.splitn(3, ':');
+        let (offset, length, seed) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(offset), Some(length), Some(seed)) if !seed.is_empty() => (offset, length, seed),
+            _ => bail!("Invalid region '{}', expected offset:length:seed", s),
+        };
+        let offset = crate::size::parse_size(offset).context("Invalid region offset")?;
+        let length = crate::size::parse_size(length).context("Invalid region length")?;
+        if length == 0 {
+            bail!("Region '{}' is empty", s);
+        }
+        Ok(Region {
+            offset,
+            length,
+            seed: seed.to_string(),
+        })
+    }
+}
+
+impl Region {
+    pub fn end(&self) -> u64 {
+        self.offset + self.length
+    }
+
+    /// The keystream for the region, derived from its seed like --seed.
+    pub fn keystream_spec(&self) -> KeystreamSpec {
+        KeystreamSpec {
+            algorithm: DEFAULT_ALGORITHM,
+            key: sha2::Sha256::digest(self.seed.as_bytes()).into(),
+            nonce: 0,
+            counter: 0,
+        }
+    }
+}
+
+/// Check that the regions fit on a device of `disk_size` bytes and do not overlap.
+pub fn validate(regions: &[Region], disk_size: u64) -> Result<()> {
+    let mut sorted: Vec<&Region> = regions.iter().collect();
+    sorted.sort_by_key(|region| region.offset);
+    for region in sorted.iter() {
+        if region
+            .offset
+            .checked_add(region.length)
+            .is_none_or(|end| end > disk_size)
+        {
+            bail!(
+                "Region at offset {} of {} bytes does not fit on the device of {} bytes",
+                region.offset,
+                region.length,
+                disk_size
+            );
+        }
+    }
+    for pair in sorted.windows(2) {
+        if pair[1].offset < pair[0].end() {
+            bail!(
+                "Regions at offsets {} and {} overlap",
+                pair[0].offset,
+                pair[1].offset
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! The post-run verification report written with --report-out, and its signature with
+//! --sign-key.
+//!
+//! The signature is an Ed25519 signature of the exact bytes of the report, written next to it
+//! with `.sig` appended to the name, together with the public key to check it with:
+//!
+//! ```text
+//! public_key = <public key as 64 hexadecimal characters>
+//! signature = <signature as 128 hexadecimal characters>
+//! ```
+
+use anyhow::{Context, Result};
+
+use crate::ed25519::SigningKey;
+use crate::keystream::KeystreamSpec;
+use crate::output::local_timestamp;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Everything needed to certify what was verified about a device, written as `name = value`
+/// lines in the same format as the state file.
+#[derive(Debug)]
+pub struct Report {
+    pub device: PathBuf,
+    pub device_serial: Option<String>,
+    pub keystream: KeystreamSpec,
+    pub claimed_bytes: u64,
+    /// The usable size of a thinly provisioned device with --compare-claimed-to-usable, which is
+    /// then what must be verified to pass instead of the claimed size.
+    pub usable_bytes: Option<u64>,
+    /// Not known if only the read phase ran.
+    pub written_bytes: Option<u64>,
+    pub verified_bytes: u64,
+    /// Position of the first byte that did not match, if any.
+    pub first_mismatch: Option<u64>,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    /// Seconds since the Unix epoch.
+    pub finished_at: u64,
+    /// When the write phase started and finished, if it ran.
+    pub write_phase: Option<(SystemTime, SystemTime)>,
+    /// When the read phase started and finished.
+    pub read_phase: Option<(SystemTime, SystemTime)>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.verified_bytes == self.usable_bytes.unwrap_or(self.claimed_bytes)
+    }
+
+    /// Write the report to `path`, and if a key is given its signature to `path` with `.sig`
+    /// appended.
+    pub fn write(&self, path: &Path, signing_key: Option<&SigningKey>) -> Result<()> {
+        let mut f = Vec::new();
+        writeln!(f, "tool_version = {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "device = {}", self.device.display())?;
+        writeln!(
+            f,
+            "device_serial = {}",
+            self.device_serial.as_deref().unwrap_or("unknown")
+        )?;
+        writeln!(f, "algorithm = {}", self.keystream.algorithm)?;
+        writeln!(f, "key = {}", hex::encode(self.keystream.key))?;
+        writeln!(f, "nonce = {}", self.keystream.nonce)?;
+        writeln!(f, "counter = {}", self.keystream.counter)?;
+        writeln!(f, "claimed_bytes = {}", self.claimed_bytes)?;
+        if let Some(usable_bytes) = self.usable_bytes {
+            writeln!(f, "usable_bytes = {}", usable_bytes)?;
+        }
+        if let Some(written_bytes) = self.written_bytes {
+            writeln!(f, "written_bytes = {}", written_bytes)?;
+        }
+        writeln!(f, "verified_bytes = {}", self.verified_bytes)?;
+        if let Some(first_mismatch) = self.first_mismatch {
+            writeln!(f, "first_mismatch = {}", first_mismatch)?;
+        }
+        writeln!(f, "started_at = {}", self.started_at)?;
+        writeln!(f, "finished_at = {}", self.finished_at)?;
+        if let Some((started, finished)) = self.write_phase {
+            writeln!(f, "write_started = {}", local_timestamp(started))?;
+            writeln!(f, "write_finished = {}", local_timestamp(finished))?;
+        }
+        if let Some((started, finished)) = self.read_phase {
+            writeln!(f, "read_started = {}", local_timestamp(started))?;
+            writeln!(f, "read_finished = {}", local_timestamp(finished))?;
+        }
+        writeln!(
+            f,
+            "result = {}",
+            if self.passed() { "PASS" } else { "FAIL" }
+        )?;
+        write_synced(path, &f)
+            .with_context(|| format!("Unable to write report '{}'", path.display()))?;
+
+        if let Some(key) = signing_key {
+            let mut sig_path = path.as_os_str().to_owned();
+            sig_path.push(".sig");
+            let sig_path = PathBuf::from(sig_path);
+            let contents = format!(
+                "public_key = {}\nsignature = {}\n",
+                hex::encode(key.public_key()),
+                hex::encode(key.sign(&f))
+            );
+            write_synced(&sig_path, contents.as_bytes()).with_context(|| {
+                format!("Unable to write report signature '{}'", sig_path.display())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The current time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut f = std::fs::File::create(path)?;
+    f.write_all(contents)?;
+    f.sync_all()
+}
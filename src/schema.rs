@@ -0,0 +1,102 @@
+//! The JSON Schema of the JSON documents the tool reads and writes, printed with --json-schema.
+//!
+//! Must be kept in sync with `RunState::json_fields` and `Checkpoint::write`.
+
+/// Describes the run state printed by --emit-seed-json and read by --verify-json-in, and the
+/// checkpoints written by --checkpoint and read by --resume-from-json, which extend it.
+pub const JSON_SCHEMA: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "verify-device-capacity JSON documents",
+  "oneOf": [
+    { "$ref": "#/$defs/state" },
+    { "$ref": "#/$defs/checkpoint" }
+  ],
+  "$defs": {
+    "stateFields": {
+      "type": "object",
+      "properties": {
+        "algorithm": {
+          "description": "Algorithm of the keystream written to the device.",
+          "enum": ["chacha8", "chacha12", "chacha20"]
+        },
+        "key": {
+          "description": "The 32-byte keystream key, hex-encoded.",
+          "type": "string",
+          "pattern": "^[0-9a-f]{64}$"
+        },
+        "nonce": {
+          "description": "The keystream nonce, or stream number.",
+          "type": "integer",
+          "minimum": 0
+        },
+        "counter": {
+          "description": "The keystream block counter at the start of the device.",
+          "type": "integer",
+          "minimum": 0
+        },
+        "device": {
+          "description": "Path of the device that was written.",
+          "type": "string"
+        },
+        "device_serial": {
+          "description": "Serial number of the device, or null if it has none.",
+          "type": ["string", "null"]
+        },
+        "disk_size": {
+          "description": "Size of the device in bytes when it was written.",
+          "type": "integer",
+          "minimum": 0
+        },
+        "block_size": {
+          "description": "Block size of the device in bytes.",
+          "type": "integer",
+          "minimum": 1
+        },
+        "buffer_size": {
+          "description": "Size in bytes of each write.",
+          "type": "integer",
+          "minimum": 1
+        },
+        "written_bytes": {
+          "description": "Number of bytes written to the device.",
+          "type": "integer",
+          "minimum": 0
+        }
+      },
+      "required": [
+        "algorithm",
+        "key",
+        "nonce",
+        "counter",
+        "device",
+        "device_serial",
+        "disk_size",
+        "block_size",
+        "buffer_size",
+        "written_bytes"
+      ]
+    },
+    "state": {
+      "description": "Printed by --emit-seed-json and read by --verify-json-in.",
+      "$ref": "#/$defs/stateFields",
+      "unevaluatedProperties": false
+    },
+    "checkpoint": {
+      "description": "Written by --checkpoint and read by --resume-from-json.",
+      "$ref": "#/$defs/stateFields",
+      "properties": {
+        "phase": {
+          "description": "The phase that was interrupted.",
+          "enum": ["write", "read"]
+        },
+        "offset": {
+          "description": "How far into the device the phase got. Everything before it is done.",
+          "type": "integer",
+          "minimum": 0
+        }
+      },
+      "required": ["phase", "offset"],
+      "unevaluatedProperties": false
+    }
+  }
+}"##;
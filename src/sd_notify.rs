@@ -0,0 +1,106 @@
+//! Notifications to systemd with --sd-notify, so `systemctl status` shows the progress of a run
+//! as a service, and the service watchdog is kept happy during long phases.
+//!
+//! Implements the protocol of sd_notify(3) directly: newline-separated assignments sent as
+//! datagrams to the socket named by $NOTIFY_SOCKET.
+
+use std::os::unix::net::UnixDatagram;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often the status is updated when there is no watchdog, or it is slow.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Notifier {
+    socket: UnixDatagram,
+    /// Send a status update at least this often. Half the watchdog timeout if there is one.
+    interval: Duration,
+    watchdog: bool,
+    last_sent: Instant,
+}
+
+static NOTIFIER: Mutex<Option<Notifier>> = Mutex::new(None);
+
+/// Connect to systemd and report that the service is ready. Does nothing when not running under
+/// systemd, or if the socket cannot be connected to.
+pub fn init() {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    let socket = match connect(&path) {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+
+    // The watchdog only applies to the process it names, if it names one.
+    let watchdog_pid = std::env::var("WATCHDOG_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    let watchdog = std::env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| usec.parse::<u64>().ok())
+        .filter(|_| watchdog_pid.is_none_or(|pid| pid == std::process::id()))
+        .map(|usec| Duration::from_micros(usec) / 2);
+
+    let notifier = Notifier {
+        socket,
+        interval: watchdog.map_or(STATUS_INTERVAL, |w| w.min(STATUS_INTERVAL)),
+        watchdog: watchdog.is_some(),
+        last_sent: Instant::now(),
+    };
+    notifier.send("READY=1\nSTATUS=Starting");
+    *NOTIFIER.lock().unwrap_or_else(|e| e.into_inner()) = Some(notifier);
+}
+
+fn connect(path: &std::ffi::OsStr) -> std::io::Result<UnixDatagram> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_bytes();
+    // A leading @ names a socket in the abstract namespace.
+    #[cfg(target_os = "linux")]
+    if let Some(name) = bytes.strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.connect_addr(&addr)?;
+        return Ok(socket);
+    }
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+/// Report the progress of a phase, e.g. "Written", as the service status, and pet the watchdog.
+/// Called on every buffer, but only sends a notification every few seconds.
+pub fn progress(verb: &str, bytes: u64, total_bytes: u64) {
+    let mut notifier = NOTIFIER.lock().unwrap_or_else(|e| e.into_inner());
+    let notifier = match notifier.as_mut() {
+        Some(notifier) if notifier.last_sent.elapsed() >= notifier.interval => notifier,
+        _ => return,
+    };
+    let completion = ((bytes as f64) / (total_bytes as f64) * 100.0).min(100.0);
+    let mut message = format!(
+        "STATUS={} {} of {} bytes ({:.1}%)",
+        verb, bytes, total_bytes, completion
+    );
+    if notifier.watchdog {
+        message.push_str("\nWATCHDOG=1");
+    }
+    notifier.send(&message);
+    notifier.last_sent = Instant::now();
+}
+
+/// Report that the run is over, with the final status line.
+pub fn stopping(status: &str) {
+    if let Some(notifier) = NOTIFIER.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        // Assignments are separated by newlines, so the status must fit on one line.
+        notifier.send(&format!("STOPPING=1\nSTATUS={}", status.replace('\n', " ")));
+    }
+}
+
+impl Notifier {
+    fn send(&self, message: &str) {
+        // Notifications are best effort: systemd going away must not fail the run.
+        let _ = self.socket.send(message.as_bytes());
+    }
+}
@@ -0,0 +1,106 @@
+//! Fetching the seed of a device from a central seed service with --seed-url, so the seeds of a
+//! fleet are held by one server and never stored on the test stations.
+//!
+//! The identity of the device is POSTed to the service as a JSON object:
+//!
+//! ```text
+//! {"serial": "4C530001", "vendor": "SanDisk", "model": "Ultra", "size": 1048576}
+//! ```
+//!
+//! with `vendor` and `model` null if unknown, and the service answers with the raw seed of the
+//! device as 64 hexadecimal characters, like --raw-seed. The request is made by curl, which
+//! brings the TLS implementation and the system's certificate store with it, and is restricted to
+//! HTTPS for the request and any redirects, as the seed decides what the device is checked
+//! against.
+
+use anyhow::{bail, Context, Result};
+
+use crate::device;
+use crate::json;
+use crate::output::emit;
+use crate::sysfs;
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// How long a request may take in seconds, including retries.
+const MAX_TIME: u64 = 60;
+
+/// How often a request that failed with a transient error, like a timeout or a 5xx response, is
+/// retried.
+const RETRIES: u32 = 3;
+
+/// Fetch the seed of the device from the service at `url`, as the hexadecimal string returned
+/// by the service.
+pub fn fetch_seed(url: &str, device: &Path) -> Result<String> {
+    if !url.to_ascii_lowercase().starts_with("https://") {
+        bail!(
+            "--seed-url must be an https:// URL, so the seed cannot be read or changed on its way"
+        );
+    }
+    let serial = sysfs::device_serial(device)
+        .context("Unable to look up device serial number")?
+        .context("Device has no serial number, which is required for --seed-url")?;
+    let attribute = |name| {
+        sysfs::disk_dir(device)
+            .ok()
+            .and_then(|disk| sysfs::read_attribute(&disk.join("device").join(name)))
+    };
+    let request = json::object(&[
+        ("serial", json::string(&serial)),
+        (
+            "vendor",
+            json::optional_string(attribute("vendor").as_deref()),
+        ),
+        (
+            "model",
+            json::optional_string(attribute("model").as_deref()),
+        ),
+        ("size", device::disk_size(device)?.to_string()),
+    ]);
+
+    let mut child = Command::new("curl")
+        .args(["--proto", "=https", "--proto-redir", "=https", "--location"])
+        .args(["--silent", "--show-error", "--fail"])
+        .args(["--max-time", &MAX_TIME.to_string()])
+        .args(["--retry", &RETRIES.to_string()])
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-"])
+        .arg("--")
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Unable to run curl, which is needed for --seed-url")?;
+    // Closed when dropped, ending the request body.
+    child
+        .stdin
+        .take()
+        .expect("Child stdin is piped")
+        .write_all(request.as_bytes())
+        .context("Unable to send the device identity to curl")?;
+    let output = child.wait_with_output().context("Error waiting for curl")?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        let message = message.trim().trim_start_matches("curl: ");
+        bail!(
+            "Unable to fetch the seed for device serial '{}' from the seed service: {}",
+            serial,
+            if message.is_empty() {
+                "curl failed without saying why"
+            } else {
+                message
+            }
+        );
+    }
+    let response = String::from_utf8(output.stdout)
+        .context("The seed service returned a seed that is not text")?;
+    emit!(
+        "Fetched seed for device serial '{}' from the seed service",
+        serial
+    );
+    Ok(response.trim().to_string())
+}
@@ -0,0 +1,43 @@
+//! Parsing of byte counts given on the command line.
+
+use anyhow::{bail, Context, Result};
+
+/// Parse a byte count with an optional unit suffix, e.g. `512`, `4K`, `4KiB`, `1MB` or `2G`.
+///
+/// A bare `K`, `M`, `G` or `T`, optionally followed by `iB`, is a binary unit (powers of 1024),
+/// while `KB`, `MB`, `GB` and `TB` are SI units (powers of 1000). Units are case-insensitive.
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    if number.is_empty() {
+        bail!(
+            "Invalid size '{}', expected a number of bytes like 4096 or 4K",
+            s
+        );
+    }
+    let number: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid size '{}'", s))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => bail!(
+            "Invalid unit '{}' in size '{}', expected one of K, M, G or T, optionally followed by iB or B",
+            unit,
+            s
+        ),
+    };
+
+    number
+        .checked_mul(multiplier)
+        .with_context(|| format!("Size '{}' is too large", s))
+}
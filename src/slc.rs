@@ -0,0 +1,101 @@
+//! Detecting the SLC cache of cheap flash drives from the write rate: writes are fast until the
+//! cache fills up, and then drop to the much slower native speed of the flash.
+
+use std::time::Instant;
+
+/// The write rate is sampled this many times over the whole device.
+const SAMPLES_PER_DEVICE: u64 = 100;
+
+/// The smallest interval the write rate is sampled over.
+const MIN_SAMPLE_BYTES: u64 = 1 << 20;
+
+/// How much slower than before writes must get to count as a cliff, as a fraction of the earlier
+/// rate.
+const MIN_DROP: f64 = 0.4;
+
+/// How many samples the drop must last, so a single stall is not mistaken for a cliff. Also the
+/// number of samples needed before the cliff to know the fast rate.
+const MIN_SUSTAINED_SAMPLES: usize = 3;
+
+/// Where the write rate dropped and by how much.
+#[derive(Debug)]
+pub struct SpeedCliff {
+    /// The number of bytes written at full speed, i.e. the size of the cache.
+    pub offset: u64,
+    /// The median rate before and after the cliff, in bytes per second.
+    pub fast_rate: f64,
+    pub slow_rate: f64,
+}
+
+#[derive(Debug)]
+struct Sample {
+    /// Where the sampled interval starts.
+    offset: u64,
+    /// Bytes per second over the interval.
+    rate: f64,
+}
+
+/// Samples the write rate at regular intervals of the bytes written.
+pub struct RateSampler {
+    interval: u64,
+    last_bytes: u64,
+    last_time: Instant,
+    samples: Vec<Sample>,
+}
+
+impl RateSampler {
+    pub fn new(disk_size: u64) -> RateSampler {
+        RateSampler {
+            interval: (disk_size / SAMPLES_PER_DEVICE).max(MIN_SAMPLE_BYTES),
+            last_bytes: 0,
+            last_time: Instant::now(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Record that `bytes` bytes have been written in total so far.
+    pub fn record(&mut self, bytes: u64) {
+        if bytes - self.last_bytes < self.interval {
+            return;
+        }
+        let elapsed = self.last_time.elapsed().as_secs_f64();
+        self.samples.push(Sample {
+            offset: self.last_bytes,
+            rate: (bytes - self.last_bytes) as f64 / elapsed,
+        });
+        self.last_bytes = bytes;
+        self.last_time = Instant::now();
+    }
+
+    /// Find the first point where the write rate drops by at least `MIN_DROP` compared to the
+    /// rate before it, and stays down.
+    pub fn find_cliff(&self) -> Option<SpeedCliff> {
+        let rates: Vec<f64> = self.samples.iter().map(|sample| sample.rate).collect();
+        if rates.len() < 2 * MIN_SUSTAINED_SAMPLES {
+            return None;
+        }
+        for i in MIN_SUSTAINED_SAMPLES..=rates.len() - MIN_SUSTAINED_SAMPLES {
+            let fast_rate = median(&rates[..i]);
+            let threshold = fast_rate * (1.0 - MIN_DROP);
+            let slow_rate = median(&rates[i..]);
+            if rates[i..i + MIN_SUSTAINED_SAMPLES]
+                .iter()
+                .all(|&rate| rate < threshold)
+                && slow_rate < threshold
+            {
+                return Some(SpeedCliff {
+                    offset: self.samples[i].offset,
+                    fast_rate,
+                    slow_rate,
+                });
+            }
+        }
+        None
+    }
+}
+
+fn median(rates: &[f64]) -> f64 {
+    let mut sorted = rates.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted[sorted.len() / 2]
+}
@@ -0,0 +1,149 @@
+//! Sources of the data written to the device and expected back from it.
+//!
+//! The write and read phases only ask a source for the contents of the device at some offset, so
+//! a different kind of data, like a fixed pattern or the contents of a file, only needs another
+//! implementation of `DataSource` rather than changes to the phases.
+
+use rand_chacha::rand_core::RngCore;
+
+use crate::keystream::{Keystream, KeystreamSpec};
+
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+
+/// Generates the expected contents of the device.
+pub trait DataSource {
+    /// Fill `buf` with the contents of the device starting at byte `offset`. Offsets are usually
+    /// asked for in ascending order, one buffer after the other, but may be asked for in any order.
+    fn fill(&mut self, buf: &mut [u8], offset: u64);
+}
+
+/// The keystream, the default source.
+pub struct KeystreamSource {
+    spec: KeystreamSpec,
+    rng: Keystream,
+    /// The offset `rng` is positioned at.
+    position: u64,
+}
+
+impl KeystreamSource {
+    pub fn new(spec: &KeystreamSpec) -> KeystreamSource {
+        KeystreamSource {
+            spec: spec.clone(),
+            rng: spec.keystream(),
+            position: 0,
+        }
+    }
+}
+
+impl DataSource for KeystreamSource {
+    fn fill(&mut self, buf: &mut [u8], offset: u64) {
+        if offset != self.position {
+            // The keystream can only be positioned at whole 32-bit words. Generating from the start
+            // of the word is slower, but only needed when a phase starts at an odd offset.
+            if !offset.is_multiple_of(4) {
+                buf.copy_from_slice(&self.spec.bytes_at(offset, buf.len()));
+                return;
+            }
+            self.rng = self.spec.keystream_at(offset);
+        }
+        self.rng.fill_bytes(buf);
+        // Whole words are generated, so the rest of a partial word at the end is skipped.
+        self.position = (offset + buf.len() as u64).next_multiple_of(4);
+    }
+}
+
+/// How many chunks the `PrefetchSource` thread generates ahead of the phase.
+const PREFETCH_CHUNKS: usize = 4;
+
+/// The ends of the channels to a thread generating chunks.
+struct PrefetchThread {
+    /// The chunks generated ahead.
+    chunks: Receiver<Vec<u8>>,
+    /// Used chunks, to be filled again.
+    recycled: SyncSender<Vec<u8>>,
+}
+
+/// The keystream generated on a separate thread ahead of the offsets asked for, so generating the
+/// data overlaps with the I/O of the phase instead of alternating with it. Asking for offsets out
+/// of order works, but restarts the thread there.
+pub struct PrefetchSource {
+    spec: KeystreamSpec,
+    chunk_size: usize,
+    /// Started on the first fill, at the offset it asks for.
+    thread: Option<PrefetchThread>,
+    /// The chunk being taken from, and how much of it was taken.
+    chunk: Vec<u8>,
+    taken: usize,
+    /// The offset the next byte of `chunk` is at.
+    position: u64,
+}
+
+impl PrefetchSource {
+    /// Generate the keystream of `spec` in chunks of `chunk_size` bytes.
+    pub fn new(spec: &KeystreamSpec, chunk_size: usize) -> PrefetchSource {
+        PrefetchSource {
+            spec: spec.clone(),
+            chunk_size,
+            thread: None,
+            chunk: Vec::new(),
+            taken: 0,
+            position: 0,
+        }
+    }
+
+    fn spawn(spec: &KeystreamSpec, chunk_size: usize, offset: u64) -> PrefetchThread {
+        let (chunk_tx, chunk_rx) = sync_channel(PREFETCH_CHUNKS);
+        // Room for every chunk that can be in flight, so sending one back never blocks.
+        let (recycled_tx, recycled_rx) = sync_channel::<Vec<u8>>(PREFETCH_CHUNKS + 2);
+        let mut source = KeystreamSource::new(spec);
+        std::thread::spawn(move || {
+            let mut offset = offset;
+            loop {
+                let mut chunk = recycled_rx
+                    .try_recv()
+                    .unwrap_or_else(|_| vec![0; chunk_size]);
+                source.fill(&mut chunk, offset);
+                offset += chunk_size as u64;
+                // Fails once the source is dropped or restarted elsewhere, which ends the thread.
+                if chunk_tx.send(chunk).is_err() {
+                    break;
+                }
+            }
+        });
+        PrefetchThread {
+            chunks: chunk_rx,
+            recycled: recycled_tx,
+        }
+    }
+}
+
+impl DataSource for PrefetchSource {
+    fn fill(&mut self, buf: &mut [u8], offset: u64) {
+        if self.thread.is_none() || offset != self.position {
+            self.thread = Some(Self::spawn(&self.spec, self.chunk_size, offset));
+            self.chunk = Vec::new();
+            self.taken = 0;
+            self.position = offset;
+        }
+        let thread = self.thread.as_ref().expect("Prefetch thread is started");
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.taken == self.chunk.len() {
+                let next = thread
+                    .chunks
+                    .recv()
+                    .expect("Prefetch thread stopped generating");
+                let used = std::mem::replace(&mut self.chunk, next);
+                if !used.is_empty() {
+                    let _ = thread.recycled.try_send(used);
+                }
+                self.taken = 0;
+            }
+            let len = (buf.len() - filled).min(self.chunk.len() - self.taken);
+            buf[filled..filled + len].copy_from_slice(&self.chunk[self.taken..self.taken + len]);
+            filled += len;
+            self.taken += len;
+        }
+        self.position += buf.len() as u64;
+    }
+}
@@ -0,0 +1,137 @@
+//! State persisted between the write and verify stages of a staged run.
+
+use anyhow::{bail, Context, Result};
+
+use crate::json;
+use crate::keystream::KeystreamSpec;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Everything the verify stage needs to know about what the write stage did.
+///
+/// Stored as `name = value` lines, in the same format as keystream descriptors, or as a JSON
+/// object with the same names for --emit-seed-json and --verify-json-in.
+#[derive(Debug, Clone)]
+pub struct RunState {
+    pub keystream: KeystreamSpec,
+    pub device: PathBuf,
+    /// Not known for devices without a serial number.
+    pub device_serial: Option<String>,
+    pub disk_size: u64,
+    pub block_size: u64,
+    pub buffer_size: u64,
+    pub written_bytes: u64,
+}
+
+impl RunState {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut f = std::fs::File::create(path)
+            .with_context(|| format!("Unable to create state file '{}'", path.display()))?;
+        writeln!(
+            f,
+            "algorithm = {}\nkey = {}\nnonce = {}\ncounter = {}",
+            self.keystream.algorithm,
+            hex::encode(self.keystream.key),
+            self.keystream.nonce,
+            self.keystream.counter
+        )?;
+        writeln!(f, "device = {}", self.device.display())?;
+        if let Some(device_serial) = &self.device_serial {
+            writeln!(f, "device_serial = {}", device_serial)?;
+        }
+        writeln!(f, "disk_size = {}", self.disk_size)?;
+        writeln!(f, "block_size = {}", self.block_size)?;
+        writeln!(f, "buffer_size = {}", self.buffer_size)?;
+        writeln!(f, "written_bytes = {}", self.written_bytes)?;
+        f.sync_all()
+            .with_context(|| format!("Unable to sync state file '{}'", path.display()))?;
+        Ok(())
+    }
+
+    /// Encode the state as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        json::object(&self.json_fields())
+    }
+
+    /// The names and encoded JSON values of the fields of the state.
+    pub fn json_fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "algorithm",
+                json::string(&self.keystream.algorithm.to_string()),
+            ),
+            ("key", json::string(&hex::encode(self.keystream.key))),
+            ("nonce", self.keystream.nonce.to_string()),
+            ("counter", self.keystream.counter.to_string()),
+            ("device", json::string(&self.device.to_string_lossy())),
+            (
+                "device_serial",
+                json::optional_string(self.device_serial.as_deref()),
+            ),
+            ("disk_size", self.disk_size.to_string()),
+            ("block_size", self.block_size.to_string()),
+            ("buffer_size", self.buffer_size.to_string()),
+            ("written_bytes", self.written_bytes.to_string()),
+        ]
+    }
+
+    pub fn read(path: &Path) -> Result<RunState> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read state file '{}'", path.display()))?;
+        crate::keystream::parse_pairs(&contents)
+            .and_then(|pairs| Self::from_pairs(&pairs))
+            .with_context(|| format!("Invalid state file '{}'", path.display()))
+    }
+
+    /// Read the state from a JSON object written by `to_json`.
+    pub fn read_json(path: &Path) -> Result<RunState> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read '{}'", path.display()))?;
+        let fields = json::parse_flat_object(&contents)
+            .with_context(|| format!("Invalid JSON in '{}'", path.display()))?;
+        let pairs: Vec<(&str, &str)> = fields
+            .iter()
+            .filter_map(|(name, value)| Some((name.as_str(), value.as_deref()?)))
+            .collect();
+        Self::from_pairs(&pairs).with_context(|| format!("Invalid state in '{}'", path.display()))
+    }
+
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Result<RunState> {
+        let keystream = KeystreamSpec::from_pairs(pairs)?;
+        let mut device = None;
+        let mut device_serial = None;
+        let mut disk_size = None;
+        let mut block_size = None;
+        let mut buffer_size = None;
+        let mut written_bytes = None;
+
+        for &(name, value) in pairs {
+            let number = || -> Result<u64> {
+                value
+                    .parse()
+                    .with_context(|| format!("{} must be an integer", name))
+            };
+            match name {
+                "device" => device = Some(PathBuf::from(value)),
+                "device_serial" => device_serial = Some(value.to_string()),
+                "disk_size" => disk_size = Some(number()?),
+                "block_size" => block_size = Some(number()?),
+                "buffer_size" => buffer_size = Some(number()?),
+                "written_bytes" => written_bytes = Some(number()?),
+                "algorithm" | "key" | "nonce" | "counter" => (),
+                _ => bail!("Unknown name '{}'", name),
+            }
+        }
+
+        Ok(RunState {
+            keystream,
+            device: device.context("Missing device")?,
+            device_serial,
+            disk_size: disk_size.context("Missing disk_size")?,
+            block_size: block_size.context("Missing block_size")?,
+            buffer_size: buffer_size.context("Missing buffer_size")?,
+            written_bytes: written_bytes.context("Missing written_bytes")?,
+        })
+    }
+}
@@ -0,0 +1,54 @@
+//! Conditions that are warnings by default but failures with --strict.
+
+/// An unexpected condition that does not by itself prove the device is broken.
+///
+/// By default these are reported and the run continues (or fails with the generic exit code if
+/// the condition is already fatal). With --strict each one fails the run with its own exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    /// The block size reported by the device is not a power of two.
+    NonPowerOfTwoBlockSize,
+    /// The size reported by the device changed between the start and the end of the run.
+    DiskSizeChanged,
+    /// The device is a device-mapper target, so the result may reflect the mapping rather than
+    /// the physical media.
+    DeviceMapper,
+    /// Reading reached the end of the device before the claimed size.
+    ShortRead,
+    /// Writing reached the end of the device before the claimed size.
+    ShortWrite,
+    /// The device is thinly provisioned, so the result reflects its backing store rather than
+    /// physical media.
+    ThinProvisioned,
+}
+
+impl Condition {
+    /// The exit code used when this condition fails the run with --strict.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Condition::NonPowerOfTwoBlockSize => 10,
+            Condition::DiskSizeChanged => 11,
+            Condition::DeviceMapper => 12,
+            Condition::ShortRead => 13,
+            Condition::ShortWrite => 14,
+            Condition::ThinProvisioned => 15,
+        }
+    }
+}
+
+/// Error raised when --strict promotes a condition to a failure. Attached to the error chain so
+/// `main` can pick the exit code.
+#[derive(Debug)]
+pub struct Violation(pub Condition);
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Failing because of --strict (exit code {})",
+            self.0.exit_code()
+        )
+    }
+}
+
+impl std::error::Error for Violation {}
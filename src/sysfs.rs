@@ -0,0 +1,210 @@
+//! Introspection of block devices through sysfs.
+
+use anyhow::{bail, Context, Result};
+
+use std::path::{Path, PathBuf};
+
+/// Get the sysfs directory of the block device at the given path, e.g. `/sys/class/block/sda1`
+/// for `/dev/sda1`.
+pub fn block_dir(path: &Path) -> Result<PathBuf> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("Unable to resolve path '{}'", path.display()))?;
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => bail!("Path '{}' has no file name", path.display()),
+    };
+
+    let dir = Path::new("/sys/class/block").join(name);
+    if !dir.exists() {
+        bail!("No sysfs entry found at '{}'", dir.display());
+    }
+    Ok(dir)
+}
+
+/// Get the sysfs directory of the whole disk that the block device at the given path belongs to.
+/// For a partition this is the directory of the parent disk, otherwise it is the device's own
+/// directory.
+pub fn disk_dir(path: &Path) -> Result<PathBuf> {
+    let dir = block_dir(path)?;
+    if dir.join("partition").exists() {
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("Unable to resolve path '{}'", dir.display()))?;
+        if let Some(parent) = dir.parent() {
+            return Ok(parent.to_path_buf());
+        }
+    }
+    Ok(dir)
+}
+
+/// Read a sysfs attribute, returning None if it does not exist or is empty.
+pub fn read_attribute(path: &Path) -> Option<String> {
+    let value = std::fs::read_to_string(path).ok()?;
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Get the serial number of the disk that the block device at the given path belongs to.
+///
+/// The serial is taken from the first of the following that is available: the device's `serial`
+/// attribute (NVMe, MMC/SD), its `wwid` attribute (SCSI, NVMe), and the serial of the USB device
+/// it is attached through.
+pub fn device_serial(path: &Path) -> Result<Option<String>> {
+    let disk = disk_dir(path)?;
+    let device = disk.join("device");
+
+    for attribute in ["serial", "wwid"] {
+        if let Some(serial) = read_attribute(&device.join(attribute)) {
+            return Ok(Some(serial));
+        }
+    }
+
+    // For USB mass storage the serial lives on the USB device, a few levels above the SCSI
+    // device in the sysfs hierarchy.
+    if let Ok(device) = device.canonicalize() {
+        for ancestor in device.ancestors() {
+            if ancestor.join("idVendor").exists() {
+                return Ok(read_attribute(&ancestor.join("serial")));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Find the block device with the given identifier, which is either the serial number of a disk
+/// as returned by `device_serial`, or the name of a link under /dev/disk/by-id or
+/// /dev/disk/by-uuid. Fails unless exactly one disk has the serial number.
+pub fn find_by_id(id: &str) -> Result<PathBuf> {
+    for dir in ["/dev/disk/by-id", "/dev/disk/by-uuid"] {
+        let link = Path::new(dir).join(id);
+        if link.exists() {
+            return link
+                .canonicalize()
+                .with_context(|| format!("Unable to resolve path '{}'", link.display()));
+        }
+    }
+
+    let entries = std::fs::read_dir("/sys/class/block")
+        .context("Unable to list block devices in /sys/class/block")?;
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        // Partitions have the serial number of their disk, so only consider whole disks.
+        if entry.path().join("partition").exists() {
+            continue;
+        }
+        let device = Path::new("/dev").join(entry.file_name());
+        if let Ok(Some(serial)) = device_serial(&device) {
+            if serial == id {
+                found.push(device);
+            }
+        }
+    }
+    match found.len() {
+        0 => bail!("No block device found with identifier '{}'", id),
+        1 => Ok(found.remove(0)),
+        _ => bail!(
+            "{} block devices have identifier '{}': {}",
+            found.len(),
+            id,
+            found
+                .iter()
+                .map(|device| device.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Get the temperature of the disk that the block device at the given path belongs to, in degrees
+/// Celsius, from the hwmon sensor of the disk (NVMe, or SATA with the drivetemp module). Returns
+/// None if the temperature is not available.
+pub fn device_temperature(path: &Path) -> Option<f64> {
+    let device = disk_dir(path).ok()?.join("device");
+    // NVMe controllers have their hwmon directories directly in the device directory, SCSI disks
+    // in a hwmon subdirectory.
+    for dir in [device.clone(), device.join("hwmon")] {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            if !entry.file_name().to_string_lossy().starts_with("hwmon") {
+                continue;
+            }
+            if let Some(value) = read_attribute(&entry.path().join("temp1_input")) {
+                let millidegrees: f64 = value.parse().ok()?;
+                return Some(millidegrees / 1000.0);
+            }
+        }
+    }
+    None
+}
+
+/// Check whether the block device at the given path is thinly provisioned, so that its size is
+/// virtual and writing it fills some backing store rather than physical media. Returns a
+/// description of the provisioning if so.
+///
+/// Detects loop devices backed by sparse files, network block devices (which e.g. qemu-nbd uses
+/// to expose qcow2 images), and LVM thin volumes, which are mapped onto a thin pool.
+pub fn thin_provisioning(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = disk_dir(path).ok()?;
+    let name = dir.file_name()?.to_string_lossy().into_owned();
+
+    if let Some(backing_file) = read_attribute(&dir.join("loop/backing_file")) {
+        let metadata = std::fs::metadata(&backing_file).ok()?;
+        if metadata.blocks() * 512 < metadata.len() {
+            return Some(format!(
+                "loop device backed by sparse file '{}'",
+                backing_file
+            ));
+        }
+    }
+
+    if name.starts_with("nbd") {
+        return Some("network block device".to_string());
+    }
+
+    let slaves = std::fs::read_dir(dir.join("slaves")).ok()?;
+    for slave in slaves.flatten() {
+        if let Some(pool) = read_attribute(&slave.path().join("dm/name")) {
+            if pool.ends_with("-tpool") {
+                return Some(format!("LVM thin volume in pool '{}'", pool));
+            }
+        }
+    }
+
+    None
+}
+
+/// If the block device at the given path is a dm-crypt mapping, such as an opened LUKS volume, get
+/// the paths of the devices it is mapped onto.
+pub fn dm_crypt_backing_devices(path: &Path) -> Option<Vec<PathBuf>> {
+    let dir = block_dir(path).ok()?;
+    // dm-crypt sets up its mappings with a uuid like `CRYPT-LUKS2-<uuid>-<name>`.
+    if !read_attribute(&dir.join("dm/uuid"))?.starts_with("CRYPT-") {
+        return None;
+    }
+    let slaves = std::fs::read_dir(dir.join("slaves")).ok()?;
+    Some(
+        slaves
+            .flatten()
+            .map(|slave| Path::new("/dev").join(slave.file_name()))
+            .collect(),
+    )
+}
+
+/// Whether the block device at the given path is a device-mapper target (LVM, dm-crypt, etc.).
+pub fn is_device_mapper(path: &Path) -> bool {
+    match block_dir(path) {
+        Ok(dir) => dir.join("dm").exists(),
+        Err(_) => false,
+    }
+}
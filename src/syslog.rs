@@ -0,0 +1,58 @@
+//! Mirroring of status output to syslog with --syslog, so it shows up in the system journal.
+
+use std::ffi::{CStr, CString};
+
+/// Open the connection to syslog. Must be called before `log`.
+pub fn open() {
+    // openlog keeps the pointer, so the identifier must live for the rest of the program.
+    static IDENT: &CStr = c"verify-device-capacity";
+    unsafe {
+        libc::openlog(IDENT.as_ptr(), libc::LOG_PID, libc::LOG_USER);
+    }
+}
+
+/// Send a status line to syslog, with a severity derived from how the line starts.
+pub fn log(line: &str) {
+    let line = strip_escapes(line);
+    // Also the labels of the translated verdicts and notes from `messages`.
+    let priority = if line.starts_with("Error")
+        || line.starts_with("FAIL")
+        || line.starts_with("NICHT BESTANDEN")
+    {
+        libc::LOG_ERR
+    } else if line.starts_with("WARNING") {
+        libc::LOG_WARNING
+    } else if line.starts_with("NOTE")
+        || line.starts_with("PASS")
+        || line.starts_with("HINWEIS")
+        || line.starts_with("BESTANDEN")
+    {
+        libc::LOG_NOTICE
+    } else {
+        libc::LOG_INFO
+    };
+    // Status lines never contain NUL bytes, but drop any rather than failing.
+    let message = CString::new(line.replace('\0', "")).unwrap_or_default();
+    unsafe {
+        libc::syslog(priority, c"%s".as_ptr(), message.as_ptr());
+    }
+}
+
+/// Remove the ANSI escape sequences added by --color.
+pub fn strip_escapes(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the introducer and parameters up to and including the final letter.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,84 @@
+//! Pausing the run while the device is too hot, with --thermal-guard.
+
+use crate::output::emit;
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How often the temperature is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How far below the threshold the device must cool before the run resumes, so it does not
+/// immediately pause again.
+const HYSTERESIS: f64 = 5.0;
+
+/// Pauses I/O while the device temperature is above a threshold, so thermal-induced errors are not
+/// misattributed to capacity problems.
+pub struct ThermalGuard {
+    device: PathBuf,
+    /// Degrees Celsius.
+    threshold: f64,
+    last_check: Instant,
+}
+
+impl ThermalGuard {
+    /// Create a guard for the device if a threshold is given. If the temperature of the device
+    /// cannot be read, a warning is printed and no guard is created.
+    pub fn new(device: &Path, threshold: Option<f64>) -> Option<ThermalGuard> {
+        let threshold = threshold?;
+        match crate::sysfs::device_temperature(device) {
+            Some(temperature) => emit!(
+                "Device temperature is {:.0}°C, pausing whenever it exceeds {:.0}°C",
+                temperature,
+                threshold
+            ),
+            None => {
+                emit!("WARNING: Unable to read the device temperature, continuing without --thermal-guard");
+                return None;
+            }
+        }
+        Some(ThermalGuard {
+            device: device.to_path_buf(),
+            threshold,
+            last_check: Instant::now(),
+        })
+    }
+
+    /// Check the temperature if it is time to, and wait for the device to cool down if it is too
+    /// hot.
+    pub fn check(&mut self) {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return;
+        }
+        self.last_check = Instant::now();
+
+        let temperature = match crate::sysfs::device_temperature(&self.device) {
+            Some(temperature) if temperature > self.threshold => temperature,
+            _ => return,
+        };
+        emit!(
+            "Device temperature {:.0}°C exceeds {:.0}°C, pausing until it cools to {:.0}°C",
+            temperature,
+            self.threshold,
+            self.threshold - HYSTERESIS
+        );
+        let paused_at = Instant::now();
+        loop {
+            std::thread::sleep(CHECK_INTERVAL);
+            match crate::sysfs::device_temperature(&self.device) {
+                Some(temperature) if temperature > self.threshold - HYSTERESIS => (),
+                temperature => {
+                    emit!(
+                        "Resuming after pausing for {} seconds, device temperature is {}",
+                        paused_at.elapsed().as_secs(),
+                        temperature
+                            .map(|t| format!("{:.0}°C", t))
+                            .unwrap_or_else(|| "unknown".to_string())
+                    );
+                    break;
+                }
+            }
+        }
+        self.last_check = Instant::now();
+    }
+}
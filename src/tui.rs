@@ -0,0 +1,212 @@
+//! Full-screen progress dashboard for --tui.
+
+use anyhow::{Context, Result};
+
+use crossterm::{cursor, style, terminal, QueueableCommand};
+
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// Number of recent errors shown on the dashboard.
+const MAX_ERRORS: usize = 5;
+
+/// Characters used to draw the rate graph, from lowest to highest.
+const GRAPH_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Guard for the terminal being in dashboard mode. The terminal is restored when this is dropped,
+/// when the program panics, and when it is interrupted by SIGINT or SIGTERM.
+pub struct Terminal {
+    _private: (),
+}
+
+impl Terminal {
+    pub fn enter() -> Result<Terminal> {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            hook(info);
+        }));
+
+        let mut signals = signal_hook::iterator::Signals::new([
+            signal_hook::consts::SIGINT,
+            signal_hook::consts::SIGTERM,
+        ])
+        .context("Error installing signal handler")?;
+        std::thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                restore();
+                std::process::exit(128 + signal);
+            }
+        });
+
+        let mut stderr = std::io::stderr();
+        stderr
+            .queue(terminal::EnterAlternateScreen)?
+            .queue(cursor::Hide)?
+            .flush()
+            .context("Error setting up terminal")?;
+
+        Ok(Terminal { _private: () })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Put the terminal back in its normal state. Errors are ignored since there is nothing more we
+/// can do about them.
+fn restore() {
+    let _ = terminal::disable_raw_mode();
+    let mut stderr = std::io::stderr();
+    let _ = stderr
+        .queue(terminal::LeaveAlternateScreen)
+        .and_then(|s| s.queue(cursor::Show))
+        .and_then(|s| s.flush());
+}
+
+/// The state shown on the dashboard for a single phase.
+pub struct Dashboard {
+    /// Rate of each recent update in bytes per second, oldest first.
+    rates: VecDeque<f64>,
+    errors: VecDeque<String>,
+}
+
+impl Dashboard {
+    pub fn new() -> Dashboard {
+        Dashboard {
+            rates: VecDeque::new(),
+            errors: VecDeque::new(),
+        }
+    }
+
+    pub fn add_error(&mut self, error: String) {
+        if self.errors.len() == MAX_ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(error);
+    }
+
+    /// Redraw the dashboard.
+    pub fn render(
+        &mut self,
+        title: &str,
+        bytes: u64,
+        total_bytes: u64,
+        rate: f64,
+        precision: usize,
+    ) -> Result<()> {
+        let (width, _) = terminal::size().unwrap_or((80, 24));
+        let width = width.max(20) as usize;
+
+        if self.rates.len() == width {
+            self.rates.pop_front();
+        }
+        self.rates.push_back(rate);
+
+        let fraction = ((bytes as f64) / (total_bytes as f64)).min(1.0);
+        let bar_width = width - 2;
+        let filled = (fraction * bar_width as f64) as usize;
+        let bar = format!(
+            "[{}{}]",
+            crate::color::paint(&"#".repeat(filled), style::Color::Green),
+            "-".repeat(bar_width - filled)
+        );
+
+        let eta = if rate > 0.0 {
+            crate::progress::format_duration(
+                (total_bytes.saturating_sub(bytes) as f64 / rate) as u64,
+            )
+        } else {
+            "unknown".to_string()
+        };
+
+        let max_rate = self.rates.iter().cloned().fold(0.0, f64::max);
+        let graph: String = self
+            .rates
+            .iter()
+            .map(|rate| {
+                let level = if max_rate > 0.0 {
+                    (rate / max_rate * (GRAPH_LEVELS.len() - 1) as f64).round() as usize
+                } else {
+                    0
+                };
+                GRAPH_LEVELS[level]
+            })
+            .collect();
+
+        let mut lines = vec![
+            title.to_string(),
+            String::new(),
+            bar,
+            format!(
+                "{:.*}% complete. {} of {} bytes.",
+                precision,
+                fraction * 100.0,
+                bytes,
+                total_bytes
+            ),
+            format!("{:.0} bytes/second. ETA {}.", rate, eta),
+            String::new(),
+            "Rate history:".to_string(),
+            graph,
+            String::new(),
+            "Recent errors:".to_string(),
+        ];
+        if self.errors.is_empty() {
+            lines.push("  none".to_string());
+        }
+        lines.extend(self.errors.iter().map(|e| format!("  {}", e)));
+
+        let mut stderr = std::io::stderr();
+        stderr
+            .queue(terminal::Clear(terminal::ClearType::All))?
+            .queue(cursor::MoveTo(0, 0))?;
+        for line in lines {
+            stderr
+                .queue(style::Print(line))?
+                .queue(cursor::MoveToNextLine(1))?;
+        }
+        stderr.flush().context("Error drawing dashboard")?;
+
+        Ok(())
+    }
+}
+
+/// Table of the latest status line of each device when verifying several at once with --tui.
+/// Redrawn in place below the normal output rather than on the alternate screen, so the last state
+/// of every device stays on the terminal when the run is over.
+pub struct DeviceTable {
+    /// Number of lines drawn by the last render, which the next one overwrites.
+    drawn: usize,
+}
+
+impl DeviceTable {
+    pub fn new() -> DeviceTable {
+        DeviceTable { drawn: 0 }
+    }
+
+    /// Redraw the table with the given line for each device.
+    pub fn render(&mut self, names: &[String], lines: &[String]) -> Result<()> {
+        let (width, _) = terminal::size().unwrap_or((80, 24));
+        let name_width = names.iter().map(|name| name.chars().count()).max();
+
+        let mut stderr = std::io::stderr();
+        if self.drawn > 0 {
+            stderr.queue(cursor::MoveToPreviousLine(self.drawn as u16))?;
+        }
+        stderr.queue(terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        for (name, line) in names.iter().zip(lines) {
+            let row = format!("{:<2$}  {}", name, line, name_width.unwrap_or(0));
+            // Long rows would wrap and throw off the number of lines to move back up.
+            let row: String = row.chars().take(width as usize).collect();
+            stderr.queue(style::Print(row))?.queue(style::Print("\n"))?;
+        }
+        stderr.flush().context("Error drawing device table")?;
+        self.drawn = names.len();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,575 @@
+//! Reading and writing the device with io_uring with --io-uring, keeping several operations in
+//! flight so a fast device is not left idle between the syscalls of one buffer after the other.
+//!
+//! The ring is set up with the raw `io_uring_setup` and `io_uring_enter` syscalls and uses the
+//! `IORING_OP_READ` and `IORING_OP_WRITE` operations, so needs Linux 5.6 or later. On top of it,
+//! `UringReader` reads ahead and returns the data in order through `Read`, so it can stand in for
+//! the device in the read phase, and `UringWriter` writes behind and reports the completions in
+//! the order the writes were queued.
+//!
+//! The kernel writes into and reads from the buffers of operations in flight, so a ring waits for
+//! all of them to complete when dropped, and is declared before the buffers it uses so it is
+//! dropped first.
+
+use crate::buffer::Buffer;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+/// `struct io_sqring_offsets` from linux/io_uring.h.
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_cqring_offsets` from linux/io_uring.h.
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+/// `struct io_uring_params` from linux/io_uring.h.
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// `struct io_uring_sqe` from linux/io_uring.h, with only the fields used for reads and writes.
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+/// `struct io_uring_cqe` from linux/io_uring.h.
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A shared memory mapping of the ring, unmapped when dropped.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> std::io::Result<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    /// The field at `offset` bytes into the mapping.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// An io_uring instance, with the operations queued but not yet submitted and the number of
+/// submitted operations not yet completed.
+struct Ring {
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+    sq_entries: u32,
+    queued: u32,
+    in_flight: u32,
+    fd: OwnedFd,
+}
+
+impl Ring {
+    fn new(entries: u32) -> std::io::Result<Ring> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries as libc::c_long,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let raw = fd.as_raw_fd();
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+        Ok(Ring {
+            sq: Mapping::new(raw, sq_len, IORING_OFF_SQ_RING)?,
+            cq: Mapping::new(raw, cq_len, IORING_OFF_CQ_RING)?,
+            sqes: Mapping::new(raw, sqes_len, IORING_OFF_SQES)?,
+            sq_entries: params.sq_entries,
+            sq_off: params.sq_off,
+            cq_off: params.cq_off,
+            queued: 0,
+            in_flight: 0,
+            fd,
+        })
+    }
+
+    /// Queue a read or write of `len` bytes at `offset` of `fd` from or into `buf`, to be
+    /// submitted by the next `enter`.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must stay valid for `len` bytes until the operation completes.
+    unsafe fn queue(
+        &mut self,
+        opcode: u8,
+        fd: RawFd,
+        buf: *mut u8,
+        len: usize,
+        offset: u64,
+        user_data: u64,
+    ) {
+        assert!(
+            self.queued + self.in_flight < self.sq_entries,
+            "io_uring submission queue is full"
+        );
+        let tail = &*self.sq.at::<AtomicU32>(self.sq_off.tail);
+        let mask = *self.sq.at::<u32>(self.sq_off.ring_mask);
+        let t = tail.load(Ordering::Relaxed);
+        let index = t & mask;
+        self.sqes.at::<Sqe>(0).add(index as usize).write(Sqe {
+            opcode,
+            fd,
+            off: offset,
+            addr: buf as u64,
+            len: len as u32,
+            user_data,
+            ..Sqe::default()
+        });
+        *self.sq.at::<u32>(self.sq_off.array).add(index as usize) = index;
+        tail.store(t.wrapping_add(1), Ordering::Release);
+        self.queued += 1;
+    }
+
+    /// Submit the queued operations, and wait for at least one to complete if `wait` is set and
+    /// any are in flight.
+    fn enter(&mut self, wait: bool) -> std::io::Result<()> {
+        loop {
+            let min_complete = u32::from(wait && self.queued + self.in_flight > 0);
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    self.queued,
+                    min_complete,
+                    IORING_ENTER_GETEVENTS,
+                    std::ptr::null::<libc::c_void>(),
+                    0usize,
+                )
+            };
+            if ret < 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() == std::io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            let submitted = ret as u32;
+            self.queued -= submitted;
+            self.in_flight += submitted;
+            if self.queued == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// The user data and result of a completed operation, if any.
+    fn complete(&mut self) -> Option<(u64, i32)> {
+        let head = unsafe { &*self.cq.at::<AtomicU32>(self.cq_off.head) };
+        let tail = unsafe { &*self.cq.at::<AtomicU32>(self.cq_off.tail) };
+        let h = head.load(Ordering::Relaxed);
+        if h == tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let mask = unsafe { *self.cq.at::<u32>(self.cq_off.ring_mask) };
+        let cqe = unsafe { &*self.cq.at::<Cqe>(self.cq_off.cqes).add((h & mask) as usize) };
+        let completion = (cqe.user_data, cqe.res);
+        head.store(h.wrapping_add(1), Ordering::Release);
+        self.in_flight -= 1;
+        Some(completion)
+    }
+
+    /// Wait for the next completed operation.
+    fn wait(&mut self) -> std::io::Result<(u64, i32)> {
+        loop {
+            if let Some(completion) = self.complete() {
+                return Ok(completion);
+            }
+            self.enter(true)?;
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // The kernel may still use the buffers of operations in flight, which are freed next.
+        while self.in_flight > 0 {
+            if self.complete().is_none() && self.enter(true).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// The result of an operation as returned by `read` or `write`.
+fn to_result(res: i32) -> std::io::Result<usize> {
+    if res < 0 {
+        Err(std::io::Error::from_raw_os_error(-res))
+    } else {
+        Ok(res as usize)
+    }
+}
+
+/// A read ahead by `UringReader`.
+struct ReadChunk {
+    buf: Buffer,
+    offset: u64,
+    len: usize,
+    /// Set once the read completed.
+    result: Option<std::io::Result<usize>>,
+    /// How much of the data was returned by `read` already.
+    taken: usize,
+}
+
+/// Reads a file from the position it is seeked to with up to `depth` reads of `chunk_size`
+/// bytes in flight, returning the data in order. Stops reading ahead at `end`.
+pub struct UringReader {
+    ring: Ring,
+    chunks: VecDeque<ReadChunk>,
+    spare: Vec<Buffer>,
+    file: File,
+    chunk_size: usize,
+    depth: usize,
+    end: u64,
+    /// The offset of the next byte returned by `read`, and where the next read ahead starts.
+    position: u64,
+    next_offset: u64,
+}
+
+impl UringReader {
+    pub fn new(
+        file: File,
+        depth: usize,
+        chunk_size: usize,
+        end: u64,
+        hugepages: bool,
+    ) -> std::io::Result<UringReader> {
+        Ok(UringReader {
+            ring: Ring::new(depth as u32)?,
+            chunks: VecDeque::with_capacity(depth),
+            spare: (0..depth)
+                .map(|_| Buffer::new(chunk_size, hugepages))
+                .collect(),
+            file,
+            chunk_size,
+            depth,
+            end,
+            position: 0,
+            next_offset: 0,
+        })
+    }
+
+    fn read_ahead(&mut self) -> std::io::Result<()> {
+        while self.chunks.len() < self.depth && self.next_offset < self.end {
+            let mut buf = self.spare.pop().expect("A buffer is spare");
+            let len = (self.end - self.next_offset).min(self.chunk_size as u64) as usize;
+            unsafe {
+                self.ring.queue(
+                    IORING_OP_READ,
+                    self.file.as_raw_fd(),
+                    buf.as_mut_ptr(),
+                    len,
+                    self.next_offset,
+                    self.next_offset,
+                )
+            };
+            self.chunks.push_back(ReadChunk {
+                buf,
+                offset: self.next_offset,
+                len,
+                result: None,
+                taken: 0,
+            });
+            self.next_offset += len as u64;
+        }
+        self.ring.enter(false)
+    }
+
+    /// Wait for the read of the first chunk to complete, storing the results of any others that
+    /// complete before it.
+    fn wait_for_first(&mut self) -> std::io::Result<()> {
+        while self
+            .chunks
+            .front()
+            .is_some_and(|chunk| chunk.result.is_none())
+        {
+            let (offset, res) = self.ring.wait()?;
+            if let Some(chunk) = self.chunks.iter_mut().find(|chunk| chunk.offset == offset) {
+                chunk.result = Some(to_result(res));
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the data read ahead, waiting for the reads in flight, and continue from `position`.
+    fn restart(&mut self, position: u64) -> std::io::Result<()> {
+        while self.ring.in_flight > 0 || self.ring.queued > 0 {
+            self.ring.wait()?;
+        }
+        self.spare
+            .extend(self.chunks.drain(..).map(|chunk| chunk.buf));
+        self.position = position;
+        self.next_offset = position;
+        Ok(())
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_ahead()?;
+        self.wait_for_first()?;
+        let chunk = match self.chunks.front_mut() {
+            Some(chunk) => chunk,
+            None => return Ok(0),
+        };
+        let n = match chunk.result.take().expect("The first read completed") {
+            Ok(n) => n,
+            Err(e) => {
+                let position = self.position;
+                self.restart(position)?;
+                return Err(e);
+            }
+        };
+        let len = (n - chunk.taken).min(buf.len());
+        buf[..len].copy_from_slice(&chunk.buf[chunk.taken..chunk.taken + len]);
+        chunk.taken += len;
+        chunk.result = Some(Ok(n));
+        self.position += len as u64;
+        if chunk.taken == n {
+            let short = n < chunk.len;
+            let chunk = self.chunks.pop_front().expect("The first chunk exists");
+            self.spare.push(chunk.buf);
+            // The chunks read ahead start after the end of this one, so a short read leaves a gap
+            // before them and they are read again from where this one ended.
+            if short {
+                let position = self.position;
+                self.restart(position)?;
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Seek for UringReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => position,
+            SeekFrom::Current(delta) => {
+                self.position.checked_add_signed(delta).ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid seek")
+                })?
+            }
+            SeekFrom::End(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Seeking from the end is not supported with io_uring",
+                ))
+            }
+        };
+        self.restart(position)?;
+        Ok(position)
+    }
+}
+
+/// A write queued by `UringWriter`.
+struct WriteChunk {
+    buf: Buffer,
+    /// Where the part of the buffer still to be written starts, in the buffer and on the device.
+    start: usize,
+    offset: u64,
+    len: usize,
+    /// Set once the write completed.
+    result: Option<std::io::Result<usize>>,
+}
+
+/// Writes a file with up to `depth` writes of buffers of `chunk_size` bytes in flight.
+pub struct UringWriter {
+    ring: Ring,
+    chunks: VecDeque<WriteChunk>,
+    spare: Vec<Buffer>,
+    file: File,
+    /// Identifies the writes in flight, as the same offset can be written twice.
+    next_id: u64,
+    ids: VecDeque<u64>,
+}
+
+impl UringWriter {
+    pub fn new(
+        file: File,
+        depth: usize,
+        chunk_size: usize,
+        hugepages: bool,
+    ) -> std::io::Result<UringWriter> {
+        Ok(UringWriter {
+            ring: Ring::new(depth as u32)?,
+            chunks: VecDeque::with_capacity(depth),
+            spare: (0..depth)
+                .map(|_| Buffer::new(chunk_size, hugepages))
+                .collect(),
+            file,
+            next_id: 0,
+            ids: VecDeque::with_capacity(depth),
+        })
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// A buffer to fill for the next write, if fewer than `depth` writes are in flight.
+    pub fn spare_buffer(&mut self) -> Option<Buffer> {
+        self.spare.pop()
+    }
+
+    /// Write the first `len` bytes of `buf`, taken from `spare_buffer`, at `offset`.
+    pub fn write(&mut self, buf: Buffer, len: usize, offset: u64) -> std::io::Result<()> {
+        self.chunks.push_back(WriteChunk {
+            buf,
+            start: 0,
+            offset,
+            len,
+            result: None,
+        });
+        self.ids.push_back(self.next_id);
+        self.submit(self.chunks.len() - 1)
+    }
+
+    fn submit(&mut self, index: usize) -> std::io::Result<()> {
+        let chunk = &mut self.chunks[index];
+        let id = self.next_id;
+        self.next_id += 1;
+        self.ids[index] = id;
+        unsafe {
+            self.ring.queue(
+                IORING_OP_WRITE,
+                self.file.as_raw_fd(),
+                chunk.buf.as_mut_ptr().add(chunk.start),
+                chunk.len - chunk.start,
+                chunk.offset,
+                id,
+            )
+        };
+        self.ring.enter(false)
+    }
+
+    /// Wait for the oldest write to complete and return its result like `write` does, or None if
+    /// no writes are in flight. A write that wrote only part of its buffer returns how much it
+    /// wrote, and the rest is written again as the oldest write.
+    pub fn next_completion(&mut self) -> std::io::Result<Option<std::io::Result<usize>>> {
+        while self
+            .chunks
+            .front()
+            .is_some_and(|chunk| chunk.result.is_none())
+        {
+            let (id, res) = self.ring.wait()?;
+            if let Some(index) = self.ids.iter().position(|&i| i == id) {
+                self.chunks[index].result = Some(to_result(res));
+            }
+        }
+        let chunk = match self.chunks.front_mut() {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        let result = chunk.result.take().expect("The oldest write completed");
+        match result {
+            Ok(n) if n > 0 && chunk.start + n < chunk.len => {
+                chunk.start += n;
+                chunk.offset += n as u64;
+                self.submit(0)?;
+            }
+            _ => {
+                let chunk = self.chunks.pop_front().expect("The oldest write exists");
+                self.ids.pop_front();
+                self.spare.push(chunk.buf);
+            }
+        }
+        Ok(Some(result))
+    }
+}
@@ -0,0 +1,64 @@
+//! CPU and memory usage of the process, from procfs, shown alongside progress with -vv.
+
+use std::time::{Duration, Instant};
+
+/// Tracks CPU time used between successive reports.
+pub struct UsageReporter {
+    last_cpu_time: Option<Duration>,
+    last_report: Instant,
+}
+
+impl UsageReporter {
+    pub fn new() -> UsageReporter {
+        UsageReporter {
+            last_cpu_time: cpu_time(),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Describe the CPU usage since the last report and the current resident memory, or None if
+    /// they are not available.
+    pub fn report(&mut self) -> Option<String> {
+        let cpu_time = cpu_time()?;
+        let elapsed = self.last_report.elapsed();
+        let used = cpu_time.saturating_sub(self.last_cpu_time?);
+        self.last_cpu_time = Some(cpu_time);
+        self.last_report = Instant::now();
+
+        // Over 100% if more than one thread is busy.
+        let cpu_percent = used.as_secs_f64() / elapsed.as_secs_f64() * 100.0;
+        Some(format!(
+            "CPU: {:.0}%. RSS: {} bytes.",
+            cpu_percent,
+            rss_bytes()?
+        ))
+    }
+}
+
+/// The user and system CPU time used by the process so far, from /proc/self/stat.
+fn cpu_time() -> Option<Duration> {
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name in parentheses may contain spaces, so split after it. utime and stime are
+    // fields 14 and 15, the 12th and 13th after the command name.
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_second <= 0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(
+        (utime + stime) as f64 / ticks_per_second as f64,
+    ))
+}
+
+/// The resident set size of the process, from /proc/self/statm.
+fn rss_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if page_size <= 0 {
+        return None;
+    }
+    Some(pages * page_size as u64)
+}
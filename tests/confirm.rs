@@ -0,0 +1,218 @@
+//! Tests of the overwrite confirmation, run against an image file so nothing real is overwritten.
+//!
+//! Tests of the site policy write /etc/verify-device-capacity/policy, which needs root and would
+//! change how the other tests are confirmed, so they are ignored by default. Run them with
+//! `sudo cargo test --test confirm -- --ignored`.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const IMAGE_SIZE: usize = 1 << 20;
+
+/// A zero-filled image file, removed when dropped.
+struct Image(PathBuf);
+
+impl Image {
+    fn new(name: &str) -> Image {
+        let path =
+            std::env::temp_dir().join(format!("vdc-confirm-{}-{}.img", name, std::process::id()));
+        std::fs::write(&path, vec![0u8; IMAGE_SIZE]).unwrap();
+        Image(path)
+    }
+
+    fn is_written(&self) -> bool {
+        std::fs::read(&self.0).unwrap().iter().any(|&b| b != 0)
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Write to the image, giving `input` on stdin, or closing stdin right away if it is empty.
+fn write(image: &Image, args: &[&str], input: &str) -> Output {
+    write_with_assume_yes(image, args, input, None)
+}
+
+/// Like `write`, with VDC_ASSUME_YES set to `assume_yes` if given.
+fn write_with_assume_yes(
+    image: &Image,
+    args: &[&str],
+    input: &str,
+    assume_yes: Option<&str>,
+) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_verify-device-capacity"));
+    match assume_yes {
+        Some(value) => command.env("VDC_ASSUME_YES", value),
+        None => command.env_remove("VDC_ASSUME_YES"),
+    };
+    let mut child = command
+        .args(["--write", "--seed", "confirm", "--allow-file"])
+        // Messages are matched in English whatever the language of the environment.
+        .env("LC_ALL", "C")
+        .args(args)
+        .arg(&image.0)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn assert_confirmed(name: &str, input: &str) {
+    let image = Image::new(name);
+    let output = write(&image, &[], input);
+    assert!(
+        output.status.success(),
+        "run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(image.is_written());
+}
+
+fn assert_refused(name: &str, input: &str) {
+    let image = Image::new(name);
+    let output = write(&image, &[], input);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Did not accept overwriting data on device"));
+    assert!(!image.is_written());
+}
+
+#[test]
+fn lowercase_y_confirms() {
+    assert_confirmed("y", "y\n");
+}
+
+#[test]
+fn uppercase_y_confirms() {
+    assert_confirmed("upper-y", "Y\n");
+}
+
+#[test]
+fn surrounding_whitespace_is_ignored() {
+    assert_confirmed("whitespace", "  y  \n");
+}
+
+#[test]
+fn n_refuses() {
+    assert_refused("n", "n\n");
+}
+
+#[test]
+fn yes_refuses() {
+    // Only a single y is accepted, so a typo'd answer is never taken as confirmation.
+    assert_refused("yes", "yes\n");
+}
+
+#[test]
+fn empty_line_refuses() {
+    assert_refused("empty", "\n");
+}
+
+#[test]
+fn end_of_input_refuses() {
+    assert_refused("eof", "");
+}
+
+#[test]
+fn yes_flag_skips_the_question() {
+    let image = Image::new("flag");
+    let output = write(&image, &["--yes"], "");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(!stderr.contains("Are you sure"));
+    assert!(image.is_written());
+}
+
+#[test]
+fn assume_yes_variable_skips_the_question() {
+    let image = Image::new("env-yes");
+    let output = write_with_assume_yes(&image, &[], "", Some("1"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(!stderr.contains("Are you sure"));
+    assert!(image.is_written());
+}
+
+#[test]
+fn assume_yes_variable_of_no_still_asks() {
+    let image = Image::new("env-no");
+    let output = write_with_assume_yes(&image, &[], "", Some("no"));
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Are you sure"));
+    assert!(!image.is_written());
+}
+
+#[test]
+fn german_j_confirms_with_lang_de() {
+    let image = Image::new("j");
+    let output = write(&image, &["--lang", "de"], "j\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(stderr.contains("Wirklich fortfahren? (j/N)"));
+    assert!(image.is_written());
+}
+
+#[test]
+fn j_refuses_in_english() {
+    assert_refused("english-j", "j\n");
+}
+
+const SITE_POLICY_PATH: &str = "/etc/verify-device-capacity/policy";
+
+/// The site policy set to the given contents, with whatever was there before restored when
+/// dropped.
+struct SitePolicy(Option<Vec<u8>>);
+
+impl SitePolicy {
+    fn new(contents: &str) -> SitePolicy {
+        let previous = std::fs::read(SITE_POLICY_PATH).ok();
+        std::fs::create_dir_all("/etc/verify-device-capacity").unwrap();
+        std::fs::write(SITE_POLICY_PATH, contents).unwrap();
+        SitePolicy(previous)
+    }
+}
+
+impl Drop for SitePolicy {
+    fn drop(&mut self) {
+        let _ = match &self.0 {
+            Some(previous) => std::fs::write(SITE_POLICY_PATH, previous),
+            None => std::fs::remove_file(SITE_POLICY_PATH),
+        };
+    }
+}
+
+#[test]
+#[ignore]
+fn interactive_policy_refuses_confirmation_file() {
+    let _policy = SitePolicy::new("confirmation = interactive\n");
+    let image = Image::new("policy-file");
+    let confirmation = image.0.with_extension("confirm");
+    std::fs::write(&confirmation, image.0.as_os_str().as_encoded_bytes()).unwrap();
+
+    let output = write(
+        &image,
+        &[
+            "--overwrite-confirmation-from",
+            confirmation.to_str().unwrap(),
+        ],
+        "",
+    );
+    let _ = std::fs::remove_file(&confirmation);
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("so --overwrite-confirmation-from cannot be used"));
+    assert!(!image.is_written());
+}
@@ -0,0 +1,422 @@
+//! Tests of the comparison logic against image files, which match the keystream except for bytes
+//! corrupted at known offsets.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const IMAGE_SIZE: usize = 1 << 20;
+
+/// A temporary file, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> TempFile {
+        TempFile(std::env::temp_dir().join(format!("vdc-{}-{}", name, std::process::id())))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn run(image: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_verify-device-capacity"))
+        .args(args)
+        .arg("--allow-file")
+        // Messages are matched in English whatever the language of the environment.
+        .env("LC_ALL", "C")
+        .arg(image)
+        .output()
+        .unwrap()
+}
+
+/// Create an image holding the keystream for `seed`, taken from --emit-stream.
+fn keystream_image(name: &str, seed: &str) -> (TempFile, Vec<u8>) {
+    let image = TempFile::new(name);
+    std::fs::write(&image.0, vec![0u8; IMAGE_SIZE]).unwrap();
+    let output = run(&image.0, &["--emit-stream", "--seed", seed]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), IMAGE_SIZE);
+    std::fs::write(&image.0, &output.stdout).unwrap();
+    (image, output.stdout)
+}
+
+#[test]
+fn matching_image_passes() {
+    let (image, _) = keystream_image("match.img", "image");
+
+    let output = run(&image.0, &["--read", "--seed", "image"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(stderr.contains(&format!("Verified: {} bytes", IMAGE_SIZE)));
+}
+
+#[test]
+fn corrupt_bytes_are_reported_exactly() {
+    let (image, stream) = keystream_image("corrupt.img", "image");
+    // Far enough apart to land in different buffers, so each is logged.
+    let corrupt = [
+        (1000, stream[1000] ^ 0xff),
+        (300_001, stream[300_001] ^ 0x01),
+    ];
+    let mut contents = stream.clone();
+    for &(offset, value) in &corrupt {
+        contents[offset] = value;
+    }
+    std::fs::write(&image.0, &contents).unwrap();
+    let error_log = TempFile::new("corrupt.log");
+
+    let output = run(
+        &image.0,
+        &[
+            "--read",
+            "--seed",
+            "image",
+            "--buffer-size",
+            "64K",
+            "--continue-on-error",
+            "--error-log",
+            error_log.0.to_str().unwrap(),
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains(&format!(
+        "position 1000. Device had contents 0x{:02x}, but expected 0x{:02x}.",
+        corrupt[0].1, stream[1000]
+    )));
+    assert!(stderr.contains("Verified: 1000 bytes"));
+    let expected_log: String = corrupt
+        .iter()
+        .map(|&(offset, value)| {
+            format!(
+                "event=mismatch offset={} found=0x{:02x} expected=0x{:02x}\n",
+                offset, value, stream[offset]
+            )
+        })
+        .collect();
+    assert_eq!(std::fs::read_to_string(&error_log.0).unwrap(), expected_log);
+}
+
+#[test]
+fn image_smaller_than_a_block_passes() {
+    let image = TempFile::new("tiny.img");
+    std::fs::write(&image.0, vec![0u8; 100]).unwrap();
+
+    let output = run(
+        &image.0,
+        &["--seed", "image", "--yes", "--buffer-size", "1M"],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(stderr.contains("Verified: 100 bytes"));
+    assert_eq!(std::fs::metadata(&image.0).unwrap().len(), 100);
+}
+
+#[test]
+fn write_verify_reads_back_every_buffer() {
+    let image = TempFile::new("write-verify.img");
+    std::fs::write(&image.0, vec![0u8; IMAGE_SIZE]).unwrap();
+
+    let output = run(
+        &image.0,
+        &[
+            "--write",
+            "--write-verify",
+            "--seed",
+            "image",
+            "--yes",
+            "--buffer-size",
+            "64K",
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(stderr.contains("Read back every buffer after writing it"));
+    let expected = run(&image.0, &["--emit-stream", "--seed", "image"]).stdout;
+    assert_eq!(std::fs::read(&image.0).unwrap(), expected);
+}
+
+#[test]
+fn several_images_are_verified_concurrently() {
+    let first = TempFile::new("first.img");
+    let second = TempFile::new("second.img");
+    std::fs::write(&first.0, vec![0u8; IMAGE_SIZE]).unwrap();
+    std::fs::write(&second.0, vec![0u8; IMAGE_SIZE]).unwrap();
+
+    let output = run(&first.0, &["--yes", second.0.to_str().unwrap()]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    for image in [&first, &second] {
+        let name = image.0.file_name().unwrap().to_str().unwrap();
+        assert!(stderr.contains(&format!("PASS: {}: Verified: {} bytes", name, IMAGE_SIZE)));
+    }
+}
+
+#[test]
+fn several_images_are_summarized_with_timestamps() {
+    let first = TempFile::new("first-timed.img");
+    let second = TempFile::new("second-timed.img");
+    std::fs::write(&first.0, vec![0u8; IMAGE_SIZE]).unwrap();
+    std::fs::write(&second.0, vec![0u8; IMAGE_SIZE]).unwrap();
+
+    let output = run(
+        &first.0,
+        &["--yes", "--timestamps", second.0.to_str().unwrap()],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    for image in [&first, &second] {
+        let name = image.0.file_name().unwrap().to_str().unwrap();
+        assert!(stderr.contains(&format!("PASS: {}: Verified: {} bytes", name, IMAGE_SIZE)));
+    }
+}
+
+#[test]
+fn empty_image_is_refused() {
+    let image = TempFile::new("empty.img");
+    std::fs::write(&image.0, []).unwrap();
+
+    let output = run(&image.0, &["--yes"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("Device reports size 0, cannot verify"));
+}
+
+#[test]
+fn probe_capacity_passes_genuine_image() {
+    let image = TempFile::new("probe.img");
+    std::fs::write(&image.0, vec![0u8; IMAGE_SIZE]).unwrap();
+
+    let output = run(&image.0, &["--yes", "--probe-capacity"]);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(stderr.contains("PASS: every probed block holds its data"));
+}
+
+#[test]
+fn repair_remap_lists_blocks_with_mismatches() {
+    let (image, stream) = keystream_image("remap.img", "image");
+    let mut contents = stream.clone();
+    // The middle two share block 73 of 4096 bytes.
+    for offset in [1000, 300_001, 302_000, IMAGE_SIZE - 1] {
+        contents[offset] ^= 0xff;
+    }
+    std::fs::write(&image.0, &contents).unwrap();
+    let bad_blocks = TempFile::new("remap.txt");
+
+    let output = run(
+        &image.0,
+        &[
+            "--read",
+            "--seed",
+            "image",
+            "--repair-remap",
+            bad_blocks.0.to_str().unwrap(),
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Wrote 3 bad blocks of 4096 bytes"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        std::fs::read_to_string(&bad_blocks.0).unwrap(),
+        format!("0\n73\n{}\n", IMAGE_SIZE / 4096 - 1)
+    );
+}
+
+#[test]
+fn rng_thread_writes_the_same_keystream() {
+    let image = TempFile::new("rng-thread.img");
+    std::fs::write(&image.0, vec![0u8; IMAGE_SIZE]).unwrap();
+
+    let output = run(
+        &image.0,
+        &[
+            "--yes",
+            "--seed",
+            "image",
+            "--rng-thread",
+            "--buffer-size",
+            "64K",
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    let expected = run(&image.0, &["--emit-stream", "--seed", "image"]).stdout;
+    assert_eq!(std::fs::read(&image.0).unwrap(), expected);
+}
+
+#[test]
+fn piped_stderr_has_no_escape_codes() {
+    for color in [None, Some("auto")] {
+        let image = TempFile::new("piped.img");
+        std::fs::write(&image.0, vec![0u8; IMAGE_SIZE]).unwrap();
+        let mut args = vec!["--yes", "--seed", "image"];
+        args.extend(color.iter().flat_map(|color| ["--color", color]));
+
+        let output = run(&image.0, &args);
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "run failed: {}", stderr);
+        assert!(stderr.contains("PASS"));
+        assert!(!stderr.contains('\x1b'), "escape codes in: {:?}", stderr);
+        assert!(
+            !stderr.lines().any(str::is_empty),
+            "blank lines in: {:?}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn io_uring_writes_the_same_keystream() {
+    let image = TempFile::new("io-uring.img");
+    std::fs::write(&image.0, vec![0u8; IMAGE_SIZE]).unwrap();
+
+    let output = run(
+        &image.0,
+        &[
+            "--yes",
+            "--seed",
+            "image",
+            "--io-uring",
+            "--io-uring-depth",
+            "4",
+            "--buffer-size",
+            "64K",
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    let expected = run(&image.0, &["--emit-stream", "--seed", "image"]).stdout;
+    assert_eq!(std::fs::read(&image.0).unwrap(), expected);
+}
+
+#[test]
+fn simulated_fake_is_detected_with_its_real_size() {
+    let image = TempFile::new("fake.img");
+    std::fs::File::create(&image.0)
+        .unwrap()
+        .set_len(4 * IMAGE_SIZE as u64)
+        .unwrap();
+
+    let output = run(
+        &image.0,
+        &[
+            "--yes",
+            "--seed",
+            "image",
+            "--simulate-fake",
+            &IMAGE_SIZE.to_string(),
+            "--detect-wraparound",
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("FAIL: device capacity does not match the claimed size"));
+    assert!(
+        stderr.contains(&format!("Device wraps around every {} bytes", IMAGE_SIZE)),
+        "{}",
+        stderr
+    );
+    // Nothing beyond the real size was ever written.
+    let contents = std::fs::read(&image.0).unwrap();
+    assert!(contents[IMAGE_SIZE..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn control_file_left_saying_stop_is_refused() {
+    let (image, _) = keystream_image("control.img", "image");
+    let control = TempFile::new("control");
+    std::fs::write(&control.0, "stop\n").unwrap();
+
+    let output = run(
+        &image.0,
+        &[
+            "--read",
+            "--seed",
+            "image",
+            "--control-file",
+            control.0.to_str().unwrap(),
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("already says to stop"), "{}", stderr);
+}
+
+#[test]
+fn region_with_zero_buffer_size_is_refused() {
+    let image = TempFile::new("region.img");
+    std::fs::write(&image.0, vec![0u8; IMAGE_SIZE]).unwrap();
+
+    let output = run(
+        &image.0,
+        &["--yes", "--region", "0:64K:image", "--buffer-size", "0"],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(
+        stderr.contains("Buffer size must be greater than 0"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn report_is_signed_with_sign_key() {
+    let (image, _) = keystream_image("signed.img", "image");
+    let report = TempFile::new("signed-report");
+    let signature = TempFile(PathBuf::from(format!("{}.sig", report.0.display())));
+    let key = TempFile::new("sign-key");
+    // The private key of the first test vector of RFC 8032.
+    std::fs::write(
+        &key.0,
+        "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60\n",
+    )
+    .unwrap();
+
+    let output = run(
+        &image.0,
+        &[
+            "--read",
+            "--seed",
+            "image",
+            "--report-out",
+            report.0.to_str().unwrap(),
+            "--sign-key",
+            key.0.to_str().unwrap(),
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "run failed: {}", stderr);
+    let signature = std::fs::read_to_string(&signature.0).unwrap();
+    assert!(signature.starts_with(
+        "public_key = d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a\nsignature = "
+    ));
+    assert_eq!(
+        signature.lines().nth(1).unwrap().len(),
+        "signature = ".len() + 128
+    );
+}
@@ -0,0 +1,108 @@
+//! End-to-end tests against real block devices, set up as loop devices over temporary files.
+//!
+//! These need root and `losetup`, so they are ignored by default. Run them with
+//! `sudo cargo test --test loopback -- --ignored`.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+const DEVICE_SIZE: usize = 8 << 20;
+
+/// A loop device over a temporary backing file, both removed when dropped.
+struct LoopDevice {
+    device: PathBuf,
+    backing_file: PathBuf,
+}
+
+impl LoopDevice {
+    fn new(name: &str) -> LoopDevice {
+        let backing_file =
+            std::env::temp_dir().join(format!("vdc-{}-{}.img", name, std::process::id()));
+        // Write the file out rather than truncating it, so it is not sparse.
+        std::fs::write(&backing_file, vec![0u8; DEVICE_SIZE]).unwrap();
+
+        let output = Command::new("losetup")
+            .arg("--find")
+            .arg("--show")
+            .arg(&backing_file)
+            .output()
+            .expect("Unable to run losetup");
+        assert!(
+            output.status.success(),
+            "losetup failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let device = PathBuf::from(String::from_utf8(output.stdout).unwrap().trim());
+
+        LoopDevice {
+            device,
+            backing_file,
+        }
+    }
+}
+
+impl Drop for LoopDevice {
+    fn drop(&mut self) {
+        let _ = Command::new("losetup").arg("-d").arg(&self.device).status();
+        let _ = std::fs::remove_file(&self.backing_file);
+    }
+}
+
+/// Run the tool on the device, answering yes to the overwrite confirmation.
+fn run(device: &Path, args: &[&str]) -> (Output, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_verify-device-capacity"))
+        .args(args)
+        .arg(device)
+        // Messages are matched in English whatever the language of the environment.
+        .env("LC_ALL", "C")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"y\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    (output, stderr)
+}
+
+#[test]
+#[ignore = "requires root and losetup"]
+fn full_device_passes() {
+    let device = LoopDevice::new("full");
+
+    let (output, stderr) = run(&device.device, &["--seed", "full"]);
+
+    assert!(output.status.success(), "run failed: {}", stderr);
+    assert!(stderr.contains(&format!("Disk size is {} bytes", DEVICE_SIZE)));
+    assert!(stderr.contains(&format!("Verified: {} bytes", DEVICE_SIZE)));
+    assert!(stderr.contains("PASS: device capacity matches the claimed size"));
+}
+
+#[test]
+#[ignore = "requires root and losetup"]
+fn truncated_backing_file_fails() {
+    let device = LoopDevice::new("short");
+
+    let (output, stderr) = run(&device.device, &["--write", "--seed", "short"]);
+    assert!(output.status.success(), "write failed: {}", stderr);
+
+    // Throw away the second half of what was written by truncating the backing file and extending
+    // it again, so that part reads back as zeros, like a fake device that only stores part of
+    // what it claims.
+    let real_size = DEVICE_SIZE / 2;
+    let f = std::fs::OpenOptions::new()
+        .write(true)
+        .open(&device.backing_file)
+        .unwrap();
+    f.set_len(real_size as u64).unwrap();
+    f.set_len(DEVICE_SIZE as u64).unwrap();
+
+    let (output, stderr) = run(&device.device, &["--read", "--seed", "short"]);
+
+    assert!(!output.status.success());
+    assert!(stderr.contains(&format!("Claimed: {} bytes", DEVICE_SIZE)));
+    assert!(stderr.contains(&format!("Verified: {} bytes", real_size)));
+    assert!(stderr.contains("FAIL: device capacity does not match the claimed size"));
+}
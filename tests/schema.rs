@@ -0,0 +1,56 @@
+//! Checks that the schema printed with --json-schema describes the JSON the tool writes.
+
+use std::process::{Command, Output};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_verify-device-capacity"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The names of the fields of a flat JSON object as written by the tool.
+fn field_names(object: &str) -> Vec<String> {
+    object
+        .trim()
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .split(", \"")
+        .map(|field| {
+            let name = field.trim_start_matches('"');
+            name[..name.find('"').unwrap()].to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn schema_covers_emitted_state() {
+    let image = std::env::temp_dir().join(format!("vdc-schema-{}.img", std::process::id()));
+    std::fs::write(&image, vec![0u8; 64 << 10]).unwrap();
+    let output = run(&[
+        "--write",
+        "--emit-seed-json",
+        "--seed",
+        "schema",
+        "--yes",
+        "--allow-file",
+        image.to_str().unwrap(),
+    ]);
+    let _ = std::fs::remove_file(&image);
+    assert!(
+        output.status.success(),
+        "run failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let schema = run(&["--json-schema"]);
+    assert!(schema.status.success());
+    let schema = String::from_utf8(schema.stdout).unwrap();
+    for name in field_names(&String::from_utf8(output.stdout).unwrap()) {
+        assert!(
+            schema.contains(&format!("\"{}\": {{", name)),
+            "field {} is missing from the schema",
+            name
+        );
+    }
+}